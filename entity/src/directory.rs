@@ -9,11 +9,14 @@ pub struct Model {
     pub id: Uuid,
     pub directory: String,
     pub parent_id: Option<Uuid>,
+    /// Whether the subdirectories are shown within the tree of directories
+    pub expanded: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
-    // Picture,
+    #[sea_orm(has_many = "super::picture::Entity")]
+    Picture,
     #[sea_orm(belongs_to = "Entity", from = "Column::ParentId", to = "Column::Id")]
     SelfReferencing,
     // #[sea_orm(has_many = "Entity")]
@@ -28,11 +31,11 @@ impl Related<Entity> for Entity {
     }
 }
 
-// impl Related<super::picture::Entity> for Entity {
-//     fn to() -> RelationDef {
-//         Relation::Picture.def()
-//     }
-// }
+impl Related<super::picture::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Picture.def()
+    }
+}
 
 pub struct SelfReferencingLink;
 
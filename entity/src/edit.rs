@@ -0,0 +1,42 @@
+use sea_orm::entity::prelude::*;
+
+use crate::EditField;
+
+/// A change made to a picture, recorded so it can be undone
+///
+/// The edits made by a single action of the user share the same `action`, so
+/// they are undone and redone together. The values are stored in the same form
+/// as the column of the picture they apply to, with a tag stored by name in
+/// `after` when it was added and `before` when it was removed.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "edits")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub action: Uuid,
+    pub picture_id: Uuid,
+    pub field: EditField,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Whether the edit has been undone, making it available to redo
+    pub undone: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::picture::Entity",
+        from = "Column::PictureId",
+        to = "super::picture::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Picture,
+}
+
+impl Related<super::picture::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Picture.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
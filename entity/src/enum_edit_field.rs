@@ -0,0 +1,20 @@
+use sea_orm::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The property of a picture changed by an edit
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+pub enum EditField {
+    #[sea_orm(string_value = "Selection")]
+    Selection,
+    #[sea_orm(string_value = "Rating")]
+    Rating,
+    #[sea_orm(string_value = "Flag")]
+    Flag,
+    #[sea_orm(string_value = "Hidden")]
+    Hidden,
+    #[sea_orm(string_value = "Tag")]
+    Tag,
+    #[sea_orm(string_value = "Caption")]
+    Caption,
+}
@@ -15,3 +15,26 @@ pub enum Flag {
     #[sea_orm(string_value = "Purple")]
     Purple,
 }
+
+impl Flag {
+    pub const ALL: [Flag; 5] = [
+        Flag::Red,
+        Flag::Yellow,
+        Flag::Green,
+        Flag::Blue,
+        Flag::Purple,
+    ];
+}
+
+impl std::fmt::Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Flag::Red => "Red",
+            Flag::Green => "Green",
+            Flag::Blue => "Blue",
+            Flag::Yellow => "Yellow",
+            Flag::Purple => "Purple",
+        };
+        write!(f, "{name}")
+    }
+}
@@ -7,7 +7,6 @@ use serde::{Deserialize, Serialize};
     Copy,
     Serialize,
     Deserialize,
-    Default,
     PartialEq,
     Eq,
     PartialOrd,
@@ -17,9 +16,6 @@ use serde::{Deserialize, Serialize};
 )]
 #[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
 pub enum Rating {
-    #[default]
-    #[sea_orm(string_value = "Zero")]
-    Zero,
     #[sea_orm(string_value = "One")]
     One,
     #[sea_orm(string_value = "Two")]
@@ -31,3 +27,29 @@ pub enum Rating {
     #[sea_orm(string_value = "Five")]
     Five,
 }
+
+impl Rating {
+    /// The number of stars of the rating.
+    pub fn stars(&self) -> u8 {
+        match self {
+            Rating::One => 1,
+            Rating::Two => 2,
+            Rating::Three => 3,
+            Rating::Four => 4,
+            Rating::Five => 5,
+        }
+    }
+
+    /// The rating with the number of stars, which is None without any stars or
+    /// above five stars.
+    pub fn from_stars(stars: u8) -> Option<Self> {
+        match stars {
+            1 => Some(Rating::One),
+            2 => Some(Rating::Two),
+            3 => Some(Rating::Three),
+            4 => Some(Rating::Four),
+            5 => Some(Rating::Five),
+            _ => None,
+        }
+    }
+}
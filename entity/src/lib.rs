@@ -1,12 +1,18 @@
 pub mod prelude;
 
+pub mod enum_edit_field;
 pub mod enum_flag;
 pub mod enum_rating;
 pub mod enum_selection;
 
 pub mod directory;
+pub mod edit;
 pub mod picture;
+pub mod picture_metadata;
+pub mod picture_tag;
+pub mod tag;
 
+pub use enum_edit_field::EditField;
 pub use enum_flag::Flag;
 pub use enum_rating::Rating;
 pub use enum_selection::Selection;
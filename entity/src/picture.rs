@@ -8,23 +8,30 @@ use super::{Flag, Rating, Selection};
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
-    pub directory: String,
     pub filename: String,
     pub raw_extension: Option<String>,
     pub short_hash: Option<Vec<u8>>,
     pub full_hash: Option<Vec<u8>>,
+    /// The local time the picture was taken
     pub capture_time: Option<TimeDateTime>,
+    /// The offset of the capture time from UTC in seconds, where this is known
+    pub capture_offset: Option<i32>,
     pub rating: Option<Rating>,
     pub flag: Option<Flag>,
     pub hidden: bool,
     pub selection: Selection,
     pub thumbnail: Option<Vec<u8>>,
-    pub directory_id: Option<Uuid>,
+    /// The directory containing the picture
+    pub directory_id: Uuid,
+    /// A description of the picture, which is included within searches
+    pub caption: Option<String>,
 }
 
 impl Model {
-    pub fn filepath(&self) -> Utf8PathBuf {
-        [self.directory.clone(), self.filename.clone()]
+    /// The path of the picture, given the directory it is related to.
+    pub fn filepath(&self, directory: &super::directory::Model) -> Utf8PathBuf {
+        debug_assert_eq!(self.directory_id, directory.id);
+        [directory.directory.as_str(), self.filename.as_str()]
             .iter()
             .collect::<Utf8PathBuf>()
     }
@@ -57,4 +64,20 @@ impl Related<super::directory::Entity> for Entity {
     }
 }
 
+impl Related<super::picture_metadata::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::picture_metadata::Relation::Picture.def().rev()
+    }
+}
+
+impl Related<super::tag::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::picture_tag::Relation::Tag.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(super::picture_tag::Relation::Picture.def().rev())
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,48 @@
+use sea_orm::entity::prelude::*;
+
+/// The camera settings and other details of a picture read from its exif data
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "picture_metadata")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub picture_id: Uuid,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub lens: Option<String>,
+    /// The focal length in millimetres
+    pub focal_length: Option<f64>,
+    /// The f-number of the aperture
+    pub aperture: Option<f64>,
+    /// The shutter speed in seconds
+    pub exposure_time: Option<f64>,
+    pub iso: Option<i32>,
+    /// The exposure compensation in stops
+    pub exposure_compensation: Option<f64>,
+    /// The latitude in degrees, positive to the north
+    pub latitude: Option<f64>,
+    /// The longitude in degrees, positive to the east
+    pub longitude: Option<f64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub orientation: Option<i32>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::picture::Entity",
+        from = "Column::PictureId",
+        to = "super::picture::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Picture,
+}
+
+impl Related<super::picture::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Picture.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -2,5 +2,13 @@
 
 pub use super::directory;
 pub use super::directory::Entity as Directory;
+pub use super::edit;
+pub use super::edit::Entity as Edit;
 pub use super::picture;
 pub use super::picture::Entity as Picture;
+pub use super::picture_metadata;
+pub use super::picture_metadata::Entity as PictureMetadata;
+pub use super::picture_tag;
+pub use super::picture_tag::Entity as PictureTag;
+pub use super::tag;
+pub use super::tag::Entity as Tag;
@@ -0,0 +1,47 @@
+use sea_orm::entity::prelude::*;
+
+/// A tag which can be applied to pictures
+///
+/// Tags are hierarchical, with the levels of the name separated by a `/`, for
+/// example `Places/Sydney` is a child of the `Places` tag.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "tags")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// The full name of the tag, including the names of all the parent tags
+    #[sea_orm(unique)]
+    pub name: String,
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "Entity",
+        from = "Column::ParentId",
+        to = "Column::Id",
+        on_delete = "Cascade"
+    )]
+    Parent,
+    #[sea_orm(has_many = "super::picture_tag::Entity")]
+    PictureTag,
+}
+
+impl Related<super::picture_tag::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PictureTag.def()
+    }
+}
+
+impl Related<super::picture::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::picture_tag::Relation::Picture.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(super::picture_tag::Relation::Tag.def().rev())
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
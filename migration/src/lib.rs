@@ -2,6 +2,14 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20230802_113601_create_pictures_table;
 mod m20250319_000211_create_directory_table;
+mod m20250601_000000_create_tag_tables;
+mod m20250615_000000_create_search_table;
+mod m20250701_000000_create_picture_metadata_table;
+mod m20250715_000000_add_capture_offset;
+mod m20250801_000000_normalise_picture_directories;
+mod m20250815_000000_create_edit_journal;
+mod m20250901_000000_add_directory_expanded;
+mod m20251001_000000_clear_zero_ratings;
 
 pub struct Migrator;
 
@@ -11,6 +19,14 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20230802_113601_create_pictures_table::Migration),
             Box::new(m20250319_000211_create_directory_table::Migration),
+            Box::new(m20250601_000000_create_tag_tables::Migration),
+            Box::new(m20250615_000000_create_search_table::Migration),
+            Box::new(m20250701_000000_create_picture_metadata_table::Migration),
+            Box::new(m20250715_000000_add_capture_offset::Migration),
+            Box::new(m20250801_000000_normalise_picture_directories::Migration),
+            Box::new(m20250815_000000_create_edit_journal::Migration),
+            Box::new(m20250901_000000_add_directory_expanded::Migration),
+            Box::new(m20251001_000000_clear_zero_ratings::Migration),
         ]
     }
 }
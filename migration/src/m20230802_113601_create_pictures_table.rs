@@ -1,20 +1,41 @@
 use entity::prelude::*;
-use sea_orm::Schema;
 use sea_orm_migration::prelude::*;
 
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
+// The columns of the table when it was first created. These are listed rather than
+// taken from the entity, since later migrations change the table and expect to find
+// it as it was.
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
-        // Replace the sample below with your own migration scripts
-        let backend = manager.get_database_backend();
         manager
             .create_table(
-                Schema::new(backend)
-                    .create_table_from_entity(Picture)
+                Table::create()
+                    .table(Picture)
                     .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("id"))
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("directory")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("filename")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("raw_extension")).string().null())
+                    .col(ColumnDef::new(Alias::new("short_hash")).blob().null())
+                    .col(ColumnDef::new(Alias::new("full_hash")).blob().null())
+                    .col(
+                        ColumnDef::new(Alias::new("capture_time"))
+                            .date_time()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(Alias::new("rating")).string().null())
+                    .col(ColumnDef::new(Alias::new("flag")).string().null())
+                    .col(ColumnDef::new(Alias::new("hidden")).boolean().not_null())
+                    .col(ColumnDef::new(Alias::new("selection")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("thumbnail")).blob().null())
                     .to_owned(),
             )
             .await
@@ -12,9 +12,8 @@ impl MigrationTrait for Migration {
         let schema = Schema::new(backend);
         let table = Table::alter()
             .table(Picture)
-            .add_column_if_not_exists(
-                &mut schema.get_column_def::<Picture>(picture::Column::DirectoryId),
-            )
+            // The column was optional before every picture was given a directory
+            .add_column_if_not_exists(ColumnDef::new(Alias::new("directory_id")).uuid().null())
             .take();
         manager.alter_table(table).await?;
 
@@ -25,11 +24,26 @@ impl MigrationTrait for Migration {
             manager.create_index(index.if_not_exists().take()).await?
         }
 
+        // The columns of the table when it was first created, which are listed rather
+        // than taken from the entity since later migrations add to the table.
         manager
             .create_table(
-                schema
-                    .create_table_from_entity(Directory)
+                Table::create()
+                    .table(Directory)
                     .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("id"))
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("directory")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("parent_id")).uuid().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Directory, Alias::new("parent_id"))
+                            .to(Directory, Alias::new("id")),
+                    )
                     .to_owned(),
             )
             .await?;
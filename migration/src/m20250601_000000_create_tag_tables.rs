@@ -0,0 +1,92 @@
+use entity::prelude::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// The columns of the tables when they were first created, which are listed rather
+// than taken from the entities since later migrations may change the tables.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The tags need to exist before the join table referencing them
+        manager
+            .create_table(
+                Table::create()
+                    .table(Tag)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("id"))
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Alias::new("name"))
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("parent_id")).uuid().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Tag, Alias::new("parent_id"))
+                            .to(Tag, Alias::new("id"))
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(PictureTag)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Alias::new("picture_id")).uuid().not_null())
+                    .col(ColumnDef::new(Alias::new("tag_id")).uuid().not_null())
+                    .primary_key(
+                        Index::create()
+                            .col(Alias::new("picture_id"))
+                            .col(Alias::new("tag_id")),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(PictureTag, Alias::new("picture_id"))
+                            .to(Picture, Alias::new("id"))
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(PictureTag, Alias::new("tag_id"))
+                            .to(Tag, Alias::new("id"))
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Finding the pictures with a tag is the common query, which isn't covered by
+        // the primary key starting with the picture.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-picture_tags-tag_id")
+                    .table(PictureTag)
+                    .col(Alias::new("tag_id"))
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PictureTag).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Tag).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use entity::prelude::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// The search index is an FTS5 table with a row for each picture, which is kept in
+// sync with the pictures and their tags by triggers. Each row has the same rowid as
+// its picture, so the triggers find the row to update without scanning the index,
+// and stores the text searched, where the tags are all the names joined by a space.
+const CREATE_SEARCH: &str = r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS pictures_search USING fts5(
+    filename,
+    directory,
+    tags,
+    caption,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_insert AFTER INSERT ON pictures BEGIN
+    INSERT INTO pictures_search (rowid, filename, directory, tags, caption)
+    VALUES (new.rowid, new.filename, new.directory, '', new.caption);
+END;
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_update
+AFTER UPDATE OF filename, directory, caption ON pictures BEGIN
+    UPDATE pictures_search
+    SET filename = new.filename, directory = new.directory, caption = new.caption
+    WHERE rowid = old.rowid;
+END;
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_delete AFTER DELETE ON pictures BEGIN
+    DELETE FROM pictures_search WHERE rowid = old.rowid;
+END;
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_tag_insert AFTER INSERT ON picture_tags BEGIN
+    UPDATE pictures_search
+    SET tags = (
+        SELECT coalesce(group_concat(tags.name, ' '), '')
+        FROM picture_tags JOIN tags ON tags.id = picture_tags.tag_id
+        WHERE picture_tags.picture_id = new.picture_id
+    )
+    WHERE rowid = (SELECT rowid FROM pictures WHERE id = new.picture_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_tag_delete AFTER DELETE ON picture_tags BEGIN
+    UPDATE pictures_search
+    SET tags = (
+        SELECT coalesce(group_concat(tags.name, ' '), '')
+        FROM picture_tags JOIN tags ON tags.id = picture_tags.tag_id
+        WHERE picture_tags.picture_id = old.picture_id
+    )
+    WHERE rowid = (SELECT rowid FROM pictures WHERE id = old.picture_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_tag_rename AFTER UPDATE OF name ON tags BEGIN
+    UPDATE pictures_search
+    SET tags = (
+        SELECT coalesce(group_concat(tags.name, ' '), '')
+        FROM pictures
+        JOIN picture_tags ON picture_tags.picture_id = pictures.id
+        JOIN tags ON tags.id = picture_tags.tag_id
+        WHERE pictures.rowid = pictures_search.rowid
+    )
+    WHERE rowid IN (
+        SELECT pictures.rowid
+        FROM pictures JOIN picture_tags ON picture_tags.picture_id = pictures.id
+        WHERE picture_tags.tag_id = new.id
+    );
+END;
+
+INSERT INTO pictures_search (rowid, filename, directory, tags, caption)
+SELECT
+    pictures.rowid,
+    pictures.filename,
+    pictures.directory,
+    (
+        SELECT coalesce(group_concat(tags.name, ' '), '')
+        FROM picture_tags JOIN tags ON tags.id = picture_tags.tag_id
+        WHERE picture_tags.picture_id = pictures.id
+    ),
+    pictures.caption
+FROM pictures;
+"#;
+
+const DROP_SEARCH: &str = r#"
+DROP TRIGGER IF EXISTS pictures_search_insert;
+DROP TRIGGER IF EXISTS pictures_search_update;
+DROP TRIGGER IF EXISTS pictures_search_delete;
+DROP TRIGGER IF EXISTS pictures_search_tag_insert;
+DROP TRIGGER IF EXISTS pictures_search_tag_delete;
+DROP TRIGGER IF EXISTS pictures_search_tag_rename;
+DROP TABLE IF EXISTS pictures_search;
+"#;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite can't skip adding a column which already exists, so the column is
+        // checked for first
+        if !manager.has_column("pictures", "caption").await? {
+            let table = Table::alter()
+                .table(Picture)
+                .add_column(ColumnDef::new(Alias::new("caption")).string().null())
+                .take();
+            manager.alter_table(table).await?;
+        }
+
+        manager
+            .get_connection()
+            .execute_unprepared(CREATE_SEARCH)
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(DROP_SEARCH)
+            .await?;
+
+        let table = Table::alter()
+            .table(Picture)
+            .drop_column(Alias::new("caption"))
+            .take();
+        manager.alter_table(table).await?;
+        Ok(())
+    }
+}
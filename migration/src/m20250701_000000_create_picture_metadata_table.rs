@@ -0,0 +1,56 @@
+use entity::prelude::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// The columns of the table when it was first created, which are listed rather than
+// taken from the entity since later migrations may change the table.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let text = |name: &str| ColumnDef::new(Alias::new(name)).string().null().take();
+        let real = |name: &str| ColumnDef::new(Alias::new(name)).double().null().take();
+        let integer = |name: &str| ColumnDef::new(Alias::new(name)).integer().null().take();
+        manager
+            .create_table(
+                Table::create()
+                    .table(PictureMetadata)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("picture_id"))
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(text("make"))
+                    .col(text("model"))
+                    .col(text("serial"))
+                    .col(text("lens"))
+                    .col(real("focal_length"))
+                    .col(real("aperture"))
+                    .col(real("exposure_time"))
+                    .col(integer("iso"))
+                    .col(real("exposure_compensation"))
+                    .col(real("latitude"))
+                    .col(real("longitude"))
+                    .col(integer("width"))
+                    .col(integer("height"))
+                    .col(integer("orientation"))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(PictureMetadata, Alias::new("picture_id"))
+                            .to(Picture, Alias::new("id"))
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PictureMetadata).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,35 @@
+use entity::prelude::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite can't skip adding a column which already exists, so the column is
+        // checked for first
+        if manager.has_column("pictures", "capture_offset").await? {
+            return Ok(());
+        }
+        let table = Table::alter()
+            .table(Picture)
+            .add_column(
+                ColumnDef::new(Alias::new("capture_offset"))
+                    .integer()
+                    .null(),
+            )
+            .take();
+        manager.alter_table(table).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let table = Table::alter()
+            .table(Picture)
+            .drop_column(Alias::new("capture_offset"))
+            .take();
+        manager.alter_table(table).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use std::path::Path;
+
+use sea_orm::{ConnectionTrait, Statement};
+use sea_orm_migration::prelude::*;
+use uuid::Uuid;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Pictures stored both the path of their directory and the id of the matching row
+// within `directories`, with the id filled in afterwards. The id is now the only
+// record of the directory, so each picture is given the directory matching its
+// path before the path is dropped. The search index takes the path of the directory
+// from `directories`, so it also needs updating when a directory is moved.
+//
+// SQLite can't add a constraint to an existing column, so the table is rebuilt with
+// every picture required to have a directory. This is all run as a single statement
+// so it stays on the one connection, since foreign keys can only be turned off
+// outside of a transaction, and otherwise dropping the pictures would also remove
+// their tags and metadata. The triggers referring to the pictures are recreated
+// once the new table has taken its place, keeping the rowid of each picture which
+// the search index is keyed by.
+const NORMALISE_PICTURES: &str = r#"
+PRAGMA foreign_keys = OFF;
+BEGIN;
+
+DROP TRIGGER IF EXISTS pictures_search_insert;
+DROP TRIGGER IF EXISTS pictures_search_update;
+DROP TRIGGER IF EXISTS pictures_search_delete;
+DROP TRIGGER IF EXISTS pictures_search_tag_insert;
+DROP TRIGGER IF EXISTS pictures_search_tag_delete;
+DROP TRIGGER IF EXISTS pictures_search_tag_rename;
+
+CREATE TABLE pictures_normalised (
+    id uuid_text NOT NULL PRIMARY KEY,
+    filename varchar NOT NULL,
+    raw_extension varchar,
+    short_hash blob,
+    full_hash blob,
+    capture_time datetime_text,
+    rating varchar,
+    flag varchar,
+    hidden boolean NOT NULL,
+    selection varchar NOT NULL,
+    thumbnail blob,
+    directory_id uuid_text NOT NULL REFERENCES directories (id),
+    caption varchar,
+    capture_offset integer
+);
+
+INSERT INTO pictures_normalised (
+    rowid, id, filename, raw_extension, short_hash, full_hash, capture_time, rating,
+    flag, hidden, selection, thumbnail, directory_id, caption, capture_offset
+)
+SELECT
+    rowid, id, filename, raw_extension, short_hash, full_hash, capture_time, rating,
+    flag, hidden, selection, thumbnail, directory_id, caption, capture_offset
+FROM pictures;
+
+DROP TABLE pictures;
+ALTER TABLE pictures_normalised RENAME TO pictures;
+
+CREATE TRIGGER pictures_search_insert AFTER INSERT ON pictures BEGIN
+    INSERT INTO pictures_search (rowid, filename, directory, tags, caption)
+    VALUES (
+        new.rowid,
+        new.filename,
+        (SELECT directory FROM directories WHERE id = new.directory_id),
+        '',
+        new.caption
+    );
+END;
+
+CREATE TRIGGER pictures_search_update
+AFTER UPDATE OF filename, directory_id, caption ON pictures BEGIN
+    UPDATE pictures_search
+    SET
+        filename = new.filename,
+        directory = (SELECT directory FROM directories WHERE id = new.directory_id),
+        caption = new.caption
+    WHERE rowid = old.rowid;
+END;
+
+CREATE TRIGGER pictures_search_delete AFTER DELETE ON pictures BEGIN
+    DELETE FROM pictures_search WHERE rowid = old.rowid;
+END;
+
+CREATE TRIGGER pictures_search_tag_insert AFTER INSERT ON picture_tags BEGIN
+    UPDATE pictures_search
+    SET tags = (
+        SELECT coalesce(group_concat(tags.name, ' '), '')
+        FROM picture_tags JOIN tags ON tags.id = picture_tags.tag_id
+        WHERE picture_tags.picture_id = new.picture_id
+    )
+    WHERE rowid = (SELECT rowid FROM pictures WHERE id = new.picture_id);
+END;
+
+CREATE TRIGGER pictures_search_tag_delete AFTER DELETE ON picture_tags BEGIN
+    UPDATE pictures_search
+    SET tags = (
+        SELECT coalesce(group_concat(tags.name, ' '), '')
+        FROM picture_tags JOIN tags ON tags.id = picture_tags.tag_id
+        WHERE picture_tags.picture_id = old.picture_id
+    )
+    WHERE rowid = (SELECT rowid FROM pictures WHERE id = old.picture_id);
+END;
+
+CREATE TRIGGER pictures_search_tag_rename AFTER UPDATE OF name ON tags BEGIN
+    UPDATE pictures_search
+    SET tags = (
+        SELECT coalesce(group_concat(tags.name, ' '), '')
+        FROM pictures
+        JOIN picture_tags ON picture_tags.picture_id = pictures.id
+        JOIN tags ON tags.id = picture_tags.tag_id
+        WHERE pictures.rowid = pictures_search.rowid
+    )
+    WHERE rowid IN (
+        SELECT pictures.rowid
+        FROM pictures JOIN picture_tags ON picture_tags.picture_id = pictures.id
+        WHERE picture_tags.tag_id = new.id
+    );
+END;
+
+CREATE TRIGGER pictures_search_directory_update
+AFTER UPDATE OF directory ON directories BEGIN
+    UPDATE pictures_search
+    SET directory = new.directory
+    WHERE rowid IN (SELECT rowid FROM pictures WHERE directory_id = new.id);
+END;
+
+COMMIT;
+PRAGMA foreign_keys = ON;
+"#;
+
+const RESTORE_SEARCH: &str = r#"
+DROP TRIGGER IF EXISTS pictures_search_insert;
+DROP TRIGGER IF EXISTS pictures_search_update;
+DROP TRIGGER IF EXISTS pictures_search_directory_update;
+
+ALTER TABLE pictures ADD COLUMN directory varchar NOT NULL DEFAULT '';
+UPDATE pictures
+SET directory = coalesce(
+    (SELECT directory FROM directories WHERE directories.id = pictures.directory_id),
+    ''
+);
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_insert AFTER INSERT ON pictures BEGIN
+    INSERT INTO pictures_search (rowid, filename, directory, tags, caption)
+    VALUES (new.rowid, new.filename, new.directory, '', new.caption);
+END;
+
+CREATE TRIGGER IF NOT EXISTS pictures_search_update
+AFTER UPDATE OF filename, directory, caption ON pictures BEGIN
+    UPDATE pictures_search
+    SET filename = new.filename, directory = new.directory, caption = new.caption
+    WHERE rowid = old.rowid;
+END;
+"#;
+
+/// The id of the directory with the path, creating it where it doesn't exist.
+///
+/// Missing parents are created the same way as when importing pictures, where the
+/// root of the filesystem isn't included as a directory.
+///
+/// The directories are written with SQL rather than through the entity, since later
+/// migrations add columns to the directories which don't exist yet.
+async fn directory_id<C: ConnectionTrait>(db: &C, path: &str) -> Result<Uuid, DbErr> {
+    let backend = db.get_database_backend();
+    if let Some(existing) = db
+        .query_one(Statement::from_sql_and_values(
+            backend,
+            "SELECT id FROM directories WHERE directory = ?",
+            [path.into()],
+        ))
+        .await?
+    {
+        return existing.try_get::<Uuid>("", "id");
+    }
+    let parent_id = match Path::new(path).parent() {
+        Some(parent) if parent.parent().is_some() => {
+            let parent = parent
+                .to_str()
+                .ok_or(DbErr::Custom(format!("Invalid directory {path}")))?;
+            Some(Box::pin(directory_id(db, parent)).await?)
+        }
+        _ => None,
+    };
+    let id = Uuid::new_v4();
+    db.execute(Statement::from_sql_and_values(
+        backend,
+        "INSERT INTO directories (id, directory, parent_id) VALUES (?, ?, ?)",
+        [id.into(), path.into(), parent_id.into()],
+    ))
+    .await?;
+    Ok(id)
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+
+        // The path is treated as correct, since the id was only ever derived from it
+        let directories: Vec<String> = db
+            .query_all(Statement::from_string(
+                backend,
+                "SELECT DISTINCT directory FROM pictures",
+            ))
+            .await?
+            .iter()
+            .map(|row| row.try_get::<String>("", "directory"))
+            .collect::<Result<_, _>>()?;
+        for directory in directories.into_iter() {
+            let id = directory_id(db, &directory).await?;
+            db.execute(Statement::from_sql_and_values(
+                backend,
+                "UPDATE pictures SET directory_id = ? WHERE directory = ?",
+                [id.into(), directory.into()],
+            ))
+            .await?;
+        }
+
+        db.execute_unprepared(NORMALISE_PICTURES).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(RESTORE_SEARCH)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use entity::prelude::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// The columns of the table when it was first created, which are listed rather than
+// taken from the entity since later migrations may change the table.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Edit)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("id"))
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("action")).uuid().not_null())
+                    .col(ColumnDef::new(Alias::new("picture_id")).uuid().not_null())
+                    .col(ColumnDef::new(Alias::new("field")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("before")).string().null())
+                    .col(ColumnDef::new(Alias::new("after")).string().null())
+                    .col(ColumnDef::new(Alias::new("undone")).boolean().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Edit, Alias::new("picture_id"))
+                            .to(Picture, Alias::new("id"))
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // The edits are undone and redone a whole action at a time
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-edits-action")
+                    .table(Edit)
+                    .col(Alias::new("action"))
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Edit).to_owned())
+            .await?;
+        Ok(())
+    }
+}
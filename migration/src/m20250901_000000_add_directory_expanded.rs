@@ -0,0 +1,36 @@
+use entity::prelude::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite can't skip adding a column which already exists, so the column is
+        // checked for first
+        if manager.has_column("directories", "expanded").await? {
+            return Ok(());
+        }
+        let table = Table::alter()
+            .table(Directory)
+            .add_column(
+                ColumnDef::new(Alias::new("expanded"))
+                    .boolean()
+                    .not_null()
+                    .default(false),
+            )
+            .take();
+        manager.alter_table(table).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let table = Table::alter()
+            .table(Directory)
+            .drop_column(Alias::new("expanded"))
+            .take();
+        manager.alter_table(table).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// A rating of zero stars is shown the same as a picture without a rating, so the
+// ratings of zero are cleared along with the edits which set them, leaving a single
+// way for a picture to have no stars.
+const CLEAR_ZERO_RATINGS: &str = r#"
+UPDATE pictures SET rating = NULL WHERE rating = 'Zero';
+UPDATE edits SET "before" = NULL WHERE field = 'Rating' AND "before" = 'Zero';
+UPDATE edits SET "after" = NULL WHERE field = 'Rating' AND "after" = 'Zero';
+"#;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(CLEAR_ZERO_RATINGS)
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        // The pictures without a rating are left as they are, since which of them
+        // were rated zero isn't kept
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use entity::prelude::*;
+use entity::enum_selection::Selection;
+use entity::{directory, picture};
+use migration::{Migrator, MigratorTrait};
+use sea_orm::prelude::Uuid;
+use sea_orm::{ActiveValue, ConnectionTrait, Database, DatabaseConnection, EntityTrait, Statement};
+
+async fn migrated_database() -> DatabaseConnection {
+    let db = Database::connect("sqlite::memory:")
+        .await
+        .expect("Unable to open database");
+    Migrator::up(&db, None)
+        .await
+        .expect("Unable to migrate database");
+    db
+}
+
+#[tokio::test]
+async fn test_fresh_database() {
+    let db = migrated_database().await;
+
+    // The tables match the entities once every migration has run
+    let directory_id = Uuid::new_v4();
+    Directory::insert(directory::ActiveModel {
+        id: ActiveValue::Set(directory_id),
+        directory: ActiveValue::Set("/photos".to_string()),
+        parent_id: ActiveValue::Set(None),
+        expanded: ActiveValue::Set(true),
+    })
+    .exec(&db)
+    .await
+    .unwrap();
+    Picture::insert(picture::ActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        filename: ActiveValue::Set("IMG_0001.JPG".to_string()),
+        hidden: ActiveValue::Set(false),
+        selection: ActiveValue::Set(Selection::Ordinary),
+        directory_id: ActiveValue::Set(directory_id),
+        ..Default::default()
+    })
+    .exec(&db)
+    .await
+    .unwrap();
+
+    let directory = Directory::find_by_id(directory_id)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(directory.expanded);
+    assert_eq!(Picture::find().all(&db).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_migrate_twice() {
+    let db = migrated_database().await;
+    Migrator::up(&db, None).await.unwrap();
+    assert!(Migrator::get_pending_migrations(&db)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_picture_requires_directory() {
+    let db = migrated_database().await;
+    let inserted = Picture::insert(picture::ActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        filename: ActiveValue::Set("IMG_0001.JPG".to_string()),
+        hidden: ActiveValue::Set(false),
+        selection: ActiveValue::Set(Selection::Ordinary),
+        directory_id: ActiveValue::Set(Uuid::new_v4()),
+        ..Default::default()
+    })
+    .exec(&db)
+    .await;
+    assert!(inserted.is_err());
+}
+
+#[tokio::test]
+async fn test_normalise_picture_directories() {
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    // The migrations before the pictures were given their directory by id
+    Migrator::up(&db, Some(6)).await.unwrap();
+    let backend = db.get_database_backend();
+    let picture_id = Uuid::new_v4();
+    let tag_id = Uuid::new_v4();
+    for (sql, values) in [
+        (
+            "INSERT INTO pictures (id, directory, filename, hidden, selection) \
+             VALUES (?, '/photos/2024', 'IMG_0001.JPG', false, 'Ordinary')",
+            vec![picture_id.into()],
+        ),
+        (
+            "INSERT INTO tags (id, name) VALUES (?, 'beach')",
+            vec![tag_id.into()],
+        ),
+        (
+            "INSERT INTO picture_tags (picture_id, tag_id) VALUES (?, ?)",
+            vec![picture_id.into(), tag_id.into()],
+        ),
+    ] {
+        db.execute(Statement::from_sql_and_values(backend, sql, values))
+            .await
+            .unwrap();
+    }
+    Migrator::up(&db, None).await.unwrap();
+
+    let picture = Picture::find_by_id(picture_id)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    let directory = Directory::find_by_id(picture.directory_id)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(directory.directory, "/photos/2024");
+
+    // Rebuilding the pictures keeps their tags and the search index
+    assert_eq!(PictureTag::find().all(&db).await.unwrap().len(), 1);
+    let search = |term: &str| {
+        Statement::from_sql_and_values(
+            backend,
+            "SELECT rowid FROM pictures_search WHERE pictures_search MATCH ?",
+            [term.into()],
+        )
+    };
+    for term in ["beach", "2024", "jpg"] {
+        assert_eq!(db.query_all(search(term)).await.unwrap().len(), 1);
+    }
+    db.execute_unprepared(
+        "UPDATE directories SET directory = '/photos/2025' WHERE directory = '/photos/2024'",
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.query_all(search("2025")).await.unwrap().len(), 1);
+}
@@ -1,3 +0,0 @@
--- Add migration script here
-ALTER TABLE picture
-ADD column thumbnail BLOB;
\ No newline at end of file
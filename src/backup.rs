@@ -0,0 +1,603 @@
+//! Snapshots of the database and restoring the library from them
+//
+// All the work on the library is stored within a single database, so snapshots of
+// it are taken automatically before importing and once a day, along with whenever
+// the user asks. Snapshots are taken with `VACUUM INTO`, which writes a consistent
+// copy of the database while it is still in use.
+//
+// Each snapshot is a database of its own within the backups directory, named with
+// the time it was taken and why, so the snapshots can be listed without keeping
+// any other record of them. Restoring a snapshot replaces the database, after first
+// taking a snapshot of the current library so the restore can itself be undone.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use camino::{Utf8Path, Utf8PathBuf};
+use iced::widget::{button, column, container, horizontal_space, row, scrollable, text};
+use iced::{Element, Length, Task};
+use itertools::Itertools;
+use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection, Statement};
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+
+use crate::database::{connect, data_directory, database_path, prepare_database};
+use crate::database::{DatabaseError, DatabaseReport};
+use crate::persistence::{PersistenceError, PersistenceMessage};
+use crate::settings::BackupSettings;
+use crate::Message;
+
+const BACKUP_DIRECTORY: &str = "backups";
+const SNAPSHOT_EXTENSION: &str = "db";
+/// The time within the filenames of snapshots, which are always in UTC
+const FILENAME_FORMAT: &[FormatItem<'_>] =
+    format_description!("[year][month][day]T[hour][minute][second]Z");
+const DISPLAY_FORMAT: &[FormatItem<'_>] =
+    format_description!("[year]-[month]-[day] [hour]:[minute]:[second] UTC");
+
+/// Why a snapshot was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotReason {
+    /// Taken when requested by the user
+    Manual,
+    /// Taken before adding or importing pictures
+    Import,
+    /// Taken when the application is started for the first time each day
+    Daily,
+    /// The library as it was before restoring another snapshot
+    Restore,
+    /// Taken before repairs which remove pictures or directories from the library
+    Repair,
+}
+
+impl SnapshotReason {
+    const ALL: [SnapshotReason; 5] = [
+        SnapshotReason::Manual,
+        SnapshotReason::Import,
+        SnapshotReason::Daily,
+        SnapshotReason::Restore,
+        SnapshotReason::Repair,
+    ];
+
+    /// The name of the reason within the filenames of snapshots.
+    fn name(&self) -> &'static str {
+        match self {
+            SnapshotReason::Manual => "manual",
+            SnapshotReason::Import => "import",
+            SnapshotReason::Daily => "daily",
+            SnapshotReason::Restore => "restore",
+            SnapshotReason::Repair => "repair",
+        }
+    }
+
+    /// Whether snapshots are removed once there are too many of them.
+    fn is_rotated(&self) -> bool {
+        !matches!(self, SnapshotReason::Manual)
+    }
+}
+
+impl std::fmt::Display for SnapshotReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            SnapshotReason::Manual => "Manual",
+            SnapshotReason::Import => "Before import",
+            SnapshotReason::Daily => "Daily",
+            SnapshotReason::Restore => "Before restore",
+            SnapshotReason::Repair => "Before repair",
+        };
+        write!(f, "{value}")
+    }
+}
+
+/// A snapshot of the database within the backups directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub path: Utf8PathBuf,
+    /// When the snapshot was taken, in UTC
+    pub created: PrimitiveDateTime,
+    pub reason: SnapshotReason,
+    /// The number of pictures within the snapshot, where it could be read
+    pub pictures: Option<u64>,
+}
+
+impl Snapshot {
+    /// Read the details of a snapshot from its filename, which is ignored where it
+    /// isn't a snapshot.
+    fn from_path(path: Utf8PathBuf) -> Option<Self> {
+        if path.extension() != Some(SNAPSHOT_EXTENSION) {
+            return None;
+        }
+        let (created, reason) = path.file_stem()?.split_once('-')?;
+        let created = PrimitiveDateTime::parse(created, FILENAME_FORMAT).ok()?;
+        let reason = SnapshotReason::ALL
+            .into_iter()
+            .find(|r| r.name() == reason)?;
+        Some(Self {
+            path,
+            created,
+            reason,
+            pictures: None,
+        })
+    }
+
+    fn view(&self) -> Element<'_, BackupMessage> {
+        let created = self
+            .created
+            .format(DISPLAY_FORMAT)
+            .expect("Snapshot times can always be formatted");
+        let pictures = match self.pictures {
+            Some(count) => format!("{count} pictures"),
+            None => "Unreadable".to_string(),
+        };
+        row![
+            text(created).width(Length::FillPortion(3)),
+            text(self.reason.to_string()).width(Length::FillPortion(2)),
+            text(pictures).width(Length::FillPortion(2)),
+            button(text("Restore"))
+                .on_press_maybe(self.pictures.map(|_| BackupMessage::Restore(self.clone()))),
+        ]
+        .spacing(10)
+        .align_y(iced::Alignment::Center)
+        .into()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BackupError {
+    BackupFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for BackupError {
+    fn from(error: anyhow::Error) -> Self {
+        BackupError::BackupFailed(Arc::new(error))
+    }
+}
+
+impl std::fmt::Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupError::BackupFailed(e) => write!(f, "Backup failed: {e}"),
+        }
+    }
+}
+
+pub fn backup_directory() -> Result<Utf8PathBuf, Error> {
+    Ok(data_directory()?.join(BACKUP_DIRECTORY))
+}
+
+/// The snapshots within the backups directory, from newest to oldest.
+///
+/// The number of pictures within each snapshot isn't read, since this requires
+/// opening each of them.
+fn find_snapshots() -> Result<Vec<Snapshot>, Error> {
+    let directory = backup_directory()?;
+    if !directory.try_exists()? {
+        return Ok(vec![]);
+    }
+    Ok(directory
+        .read_dir_utf8()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Snapshot::from_path(entry.into_path()))
+        .sorted_by_key(|s| s.created)
+        .rev()
+        .collect())
+}
+
+/// Count the pictures within a snapshot, checking it can be read.
+async fn count_pictures(path: &Utf8Path) -> Result<u64, Error> {
+    let mut options = ConnectOptions::new(format!("sqlite://{path}?mode=ro"));
+    options.max_connections(1).min_connections(1);
+    let db = Database::connect(options).await?;
+    let row = db
+        .query_one(Statement::from_string(
+            db.get_database_backend(),
+            "SELECT count(*) AS count FROM pictures",
+        ))
+        .await?
+        .ok_or(anyhow!("Unable to count the pictures within {path}"))?;
+    let count = row.try_get::<i64>("", "count")?;
+    db.close().await?;
+    Ok(count as u64)
+}
+
+/// All the snapshots along with the number of pictures within each of them.
+#[tracing::instrument(name = "Listing snapshots")]
+pub async fn list_snapshots() -> Result<Vec<Snapshot>, Error> {
+    let mut snapshots = find_snapshots()?;
+    for snapshot in snapshots.iter_mut() {
+        snapshot.pictures = count_pictures(&snapshot.path)
+            .await
+            .inspect_err(|e| tracing::warn!("Unable to read snapshot {}: {e}", snapshot.path))
+            .ok();
+    }
+    Ok(snapshots)
+}
+
+/// Remove the oldest snapshots taken for the reason, keeping the newest.
+fn rotate_snapshots(reason: SnapshotReason, keep: usize) -> Result<(), Error> {
+    if !reason.is_rotated() {
+        return Ok(());
+    }
+    for snapshot in find_snapshots()?
+        .into_iter()
+        .filter(|s| s.reason == reason)
+        .skip(keep)
+    {
+        tracing::info!("Removing snapshot {}", snapshot.path);
+        std::fs::remove_file(&snapshot.path)?;
+    }
+    Ok(())
+}
+
+/// Take a snapshot of the database, removing the oldest snapshots of the same kind
+/// so only `keep` of them remain.
+#[tracing::instrument(name = "Taking snapshot", skip(db))]
+pub async fn create_snapshot(
+    db: &DatabaseConnection,
+    reason: SnapshotReason,
+    keep: usize,
+) -> Result<Snapshot, Error> {
+    let directory = backup_directory()?;
+    std::fs::create_dir_all(&directory)?;
+    let now = OffsetDateTime::now_utc();
+    let created = PrimitiveDateTime::new(now.date(), now.time().replace_nanosecond(0)?);
+    let filename = format!(
+        "{}-{}.{SNAPSHOT_EXTENSION}",
+        created.format(FILENAME_FORMAT)?,
+        reason.name()
+    );
+    let path = directory.join(filename);
+    if path.try_exists()? {
+        return Err(anyhow!("Snapshot {path} already exists"));
+    }
+
+    db.execute(Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "VACUUM INTO ?",
+        [path.to_string().into()],
+    ))
+    .await?;
+    tracing::info!("Created snapshot {path}");
+
+    rotate_snapshots(reason, keep)?;
+    Ok(Snapshot {
+        path,
+        created,
+        reason,
+        pictures: None,
+    })
+}
+
+/// Take a snapshot where enabled within the settings.
+///
+/// These snapshots happen alongside other work, so any problems are logged rather
+/// than stopping that work.
+pub async fn automatic_snapshot(
+    db: &DatabaseConnection,
+    reason: SnapshotReason,
+    settings: &BackupSettings,
+) {
+    let result = async {
+        let enabled = match reason {
+            SnapshotReason::Import => settings.before_import,
+            SnapshotReason::Daily => {
+                // Only once a day, however often the application is started
+                let latest = find_snapshots()?
+                    .into_iter()
+                    .find(|s| s.reason == SnapshotReason::Daily);
+                let now = OffsetDateTime::now_utc();
+                let now = PrimitiveDateTime::new(now.date(), now.time());
+                settings.daily && latest.is_none_or(|s| now - s.created >= Duration::days(1))
+            }
+            SnapshotReason::Manual | SnapshotReason::Restore | SnapshotReason::Repair => true,
+        };
+        if enabled {
+            create_snapshot(db, reason, settings.keep).await?;
+        }
+        Ok::<(), Error>(())
+    };
+    if let Err(e) = result.await {
+        tracing::warn!("Unable to take snapshot: {e}");
+    }
+}
+
+/// The library once a snapshot has been restored.
+#[derive(Debug, Clone)]
+pub struct RestoredLibrary {
+    pub database: DatabaseConnection,
+    /// The outcome of bringing the database up to date with the current schema
+    pub status: Result<DatabaseReport, DatabaseError>,
+    /// Why the snapshot couldn't replace the database after the connection was
+    /// closed, where the library is opened again as it was
+    pub failed: Option<BackupError>,
+}
+
+/// Replace the database of the library with the snapshot.
+///
+/// The current library is kept as a snapshot first. The snapshot is copied next to
+/// the database before the connection is closed, so once closed replacing the
+/// database is just a rename. The returned connection is to the restored database,
+/// which is brought up to date with the current schema, or to the current database
+/// where it couldn't be replaced. Once the connection has been closed the library
+/// is always returned, disconnected where it can't be opened again.
+#[tracing::instrument(name = "Restoring snapshot", skip(db))]
+pub async fn restore_snapshot(
+    db: DatabaseConnection,
+    snapshot: Snapshot,
+    keep: usize,
+) -> Result<RestoredLibrary, Error> {
+    count_pictures(&snapshot.path).await?;
+    let path = database_path()?;
+    let staged = path.with_extension("restore");
+    std::fs::copy(&snapshot.path, &staged)?;
+    if let Err(e) = create_snapshot(&db, SnapshotReason::Restore, keep).await {
+        std::fs::remove_file(&staged)?;
+        return Err(anyhow!("Unable to keep the current library: {e}"));
+    }
+
+    let closed = db.close().await;
+    let replaced = closed.map_err(Error::from).and_then(|()| {
+        // Any remaining journal belongs to the database being replaced
+        for suffix in ["-wal", "-shm"] {
+            let journal = Utf8PathBuf::from(format!("{path}{suffix}"));
+            if journal.try_exists()? {
+                std::fs::remove_file(&journal)?;
+            }
+        }
+        std::fs::rename(&staged, &path)?;
+        Ok(())
+    });
+    let failed = match replaced {
+        Ok(()) => {
+            tracing::info!("Restored the library from {}", snapshot.path);
+            None
+        }
+        Err(e) => {
+            tracing::error!("Unable to replace the library, opening it again: {e}");
+            let _ = std::fs::remove_file(&staged);
+            Some(anyhow!("Unable to replace the library with the snapshot: {e}").into())
+        }
+    };
+
+    let (database, status) = match connect(&path).await {
+        Ok(database) => {
+            let status = prepare_database(&database).await;
+            (database, status)
+        }
+        Err(e) => {
+            tracing::error!("Unable to open the library again: {e}");
+            (
+                DatabaseConnection::Disconnected,
+                Err(DatabaseError::from(e)),
+            )
+        }
+    };
+    Ok(RestoredLibrary {
+        database,
+        status,
+        failed,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub enum BackupMessage {
+    BackupNow,
+    BackedUp(Result<Snapshot, BackupError>),
+    /// Open the list of snapshots which can be restored
+    ShowSnapshots,
+    Snapshots(Result<Vec<Snapshot>, BackupError>),
+    /// Restore the snapshot, once confirmed by the user
+    Restore(Snapshot),
+    RestoreConfirm,
+    RestoreCancel,
+    /// Whether the changes to pictures were written, which happens before restoring
+    RestoreSaved(Result<(), PersistenceError>),
+    Restored(Result<RestoredLibrary, BackupError>),
+    Close,
+}
+
+impl From<BackupMessage> for Message {
+    fn from(val: BackupMessage) -> Self {
+        Message::Backup(val)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BackupView {
+    database: DatabaseConnection,
+    /// The snapshots shown to the user, while the list is open
+    pub snapshots: Option<Vec<Snapshot>>,
+    /// The snapshot waiting for confirmation before it is restored
+    restore: Option<Snapshot>,
+    /// The snapshot being restored, once the changes to pictures are written
+    restoring: Option<Snapshot>,
+    /// The outcome of the last backup or restore, shown along with the snapshots
+    status: Option<String>,
+    busy: bool,
+}
+
+impl BackupView {
+    pub fn new(database: DatabaseConnection) -> Self {
+        Self {
+            database,
+            snapshots: None,
+            restore: None,
+            restoring: None,
+            status: None,
+            busy: false,
+        }
+    }
+
+    pub fn update(&mut self, message: BackupMessage, settings: &BackupSettings) -> Task<Message> {
+        let keep = settings.keep;
+        let database = self.database.clone();
+        match message {
+            BackupMessage::BackupNow => {
+                if self.busy {
+                    return Task::none();
+                }
+                self.busy = true;
+                Task::perform(
+                    async move {
+                        Ok(create_snapshot(&database, SnapshotReason::Manual, keep).await?)
+                    },
+                    BackupMessage::BackedUp,
+                )
+                .map(Message::Backup)
+            }
+            BackupMessage::BackedUp(result) => {
+                self.busy = false;
+                match result {
+                    Ok(snapshot) => {
+                        self.status = Some(format!("Saved snapshot {}", snapshot.path));
+                        // Keep the list of snapshots up to date where it is open
+                        if self.snapshots.is_some() {
+                            return Task::done(BackupMessage::ShowSnapshots).map(Message::Backup);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        self.status = Some(e.to_string());
+                    }
+                }
+                Task::none()
+            }
+            BackupMessage::ShowSnapshots => Task::perform(
+                async move { Ok(list_snapshots().await?) },
+                BackupMessage::Snapshots,
+            )
+            .map(Message::Backup),
+            BackupMessage::Snapshots(Ok(snapshots)) => {
+                self.snapshots = Some(snapshots);
+                Task::none()
+            }
+            BackupMessage::Snapshots(Err(e)) => {
+                tracing::error!("{e}");
+                self.status = Some(e.to_string());
+                Task::none()
+            }
+            BackupMessage::Restore(snapshot) => {
+                self.restore = Some(snapshot);
+                Task::none()
+            }
+            BackupMessage::RestoreCancel => {
+                self.restore = None;
+                Task::none()
+            }
+            BackupMessage::RestoreConfirm => {
+                let Some(snapshot) = self.restore.take() else {
+                    return Task::none();
+                };
+                if self.busy {
+                    return Task::none();
+                }
+                self.busy = true;
+                self.restoring = Some(snapshot);
+                // The snapshot of the current library includes all the changes made
+                Task::done(PersistenceMessage::Settle(|result| {
+                    BackupMessage::RestoreSaved(result).into()
+                }))
+                .map(Message::Persistence)
+            }
+            BackupMessage::RestoreSaved(result) => {
+                let Some(snapshot) = self.restoring.take() else {
+                    return Task::none();
+                };
+                if let Err(e) = result {
+                    self.busy = false;
+                    self.status = Some(format!("Unable to restore: {e}"));
+                    return Task::none();
+                }
+                Task::perform(
+                    async move { Ok(restore_snapshot(database, snapshot, keep).await?) },
+                    BackupMessage::Restored,
+                )
+                .map(Message::Backup)
+            }
+            // A successful restore replaces the whole application state, so is
+            // handled by the application.
+            BackupMessage::Restored(Ok(_)) => Task::none(),
+            BackupMessage::Restored(Err(e)) => {
+                self.busy = false;
+                tracing::error!("{e}");
+                self.status = Some(e.to_string());
+                Task::none()
+            }
+            BackupMessage::Close => {
+                self.snapshots = None;
+                self.restore = None;
+                self.status = None;
+                Task::none()
+            }
+        }
+    }
+
+    pub fn menu_view(&self) -> Element<'_, Message> {
+        row![
+            button(text("Backup Now"))
+                .on_press_maybe((!self.busy).then_some(BackupMessage::BackupNow.into())),
+            button(text("Restore")).on_press(BackupMessage::ShowSnapshots.into()),
+        ]
+        .into()
+    }
+
+    /// The list of snapshots, or the confirmation of the snapshot being restored.
+    pub fn view(&self) -> Element<'_, BackupMessage> {
+        let Some(snapshots) = &self.snapshots else {
+            return column![].into();
+        };
+        let status = text(self.status.clone().unwrap_or_default());
+        let content: Element<'_, BackupMessage> = if let Some(snapshot) = &self.restore {
+            column![
+                text(format!(
+                    "Restore the library to the snapshot taken {}? The current library \
+                     is kept as a snapshot, so it can be restored afterwards.",
+                    snapshot
+                        .created
+                        .format(DISPLAY_FORMAT)
+                        .expect("Snapshot times can always be formatted")
+                )),
+                row![
+                    horizontal_space(),
+                    button(text("Cancel")).on_press(BackupMessage::RestoreCancel),
+                    button(text("Restore"))
+                        .on_press_maybe((!self.busy).then_some(BackupMessage::RestoreConfirm)),
+                ]
+                .spacing(10)
+            ]
+            .spacing(10)
+            .into()
+        } else {
+            let summary = if snapshots.is_empty() {
+                text("There are no snapshots of the library yet.")
+            } else {
+                text(format!("{} snapshots of the library", snapshots.len()))
+            };
+            column![
+                summary,
+                scrollable(column(snapshots.iter().map(Snapshot::view)).spacing(4))
+                    .direction(scrollable::Direction::Vertical(
+                        scrollable::Scrollbar::new().width(2.).scroller_width(10.),
+                    ))
+                    .height(Length::Fill),
+                row![
+                    status,
+                    horizontal_space(),
+                    button(text("Backup Now"))
+                        .on_press_maybe((!self.busy).then_some(BackupMessage::BackupNow)),
+                    button(text("Close")).on_press(BackupMessage::Close),
+                ]
+                .spacing(10)
+            ]
+            .spacing(10)
+            .into()
+        };
+        container(content)
+            .style(container::rounded_box)
+            .padding(20)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+}
@@ -0,0 +1,736 @@
+//! Timezones of capture times and shifting the capture times of pictures
+//
+// The capture time within the exif data is the local time of the camera, which is
+// stored along with the offset from UTC where this is known, either from the
+// `OffsetTimeOriginal` field of newer cameras or the timezone chosen by the user.
+//
+// Cameras are often left on the wrong time, like after travelling or daylight
+// saving, so the capture times of a selection of pictures can be shifted together.
+// The new times can be written back to the exif data of the files, and the files
+// moved to where they now belong within the import structure.
+//
+// Unlike the changes made while culling, shifting capture times isn't recorded
+// within the journal, since undoing it would also rewrite and move the files.
+// Instead the pictures can be shifted back by the same amount.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error, Result};
+use camino::Utf8PathBuf;
+use exif::{Context, Tag};
+use sea_orm::{DatabaseConnection, TransactionTrait};
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::{Duration, PrimitiveDateTime, UtcOffset};
+
+use crate::data::update_picture_data;
+use crate::get_parent_directory;
+use crate::import::ImportStructure;
+use crate::picture::PictureData;
+use crate::settings::SidecarNaming;
+use crate::sidecar::sidecar_path;
+
+const OFFSET_FORMAT: &[FormatItem<'_>] =
+    format_description!("[offset_hour sign:mandatory]:[offset_minute]");
+const EXIF_TIME_FORMAT: &[FormatItem<'_>] =
+    format_description!("[year]:[month]:[day] [hour]:[minute]:[second]");
+const DISPLAY_FORMAT: &[FormatItem<'_>] =
+    format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+
+/// The tag within the first IFD pointing to the IFD with the exif fields.
+const EXIF_IFD_POINTER: u16 = 0x8769;
+/// The type of the TIFF fields holding text, which includes the times.
+const ASCII_TYPE: u16 = 2;
+/// The highest sequence number given to a picture when re-filing it.
+const MAX_SEQUENCE: usize = 9999;
+
+/// Parse an offset from UTC like `+10:00`, where `Z` and `UTC` are also accepted.
+pub fn parse_offset(value: &str) -> Result<UtcOffset> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("z") || value.eq_ignore_ascii_case("utc") {
+        return Ok(UtcOffset::UTC);
+    }
+    UtcOffset::parse(value, OFFSET_FORMAT)
+        .map_err(|_| anyhow!("Invalid timezone {value}, expected an offset like +10:00"))
+}
+
+pub fn format_offset(offset: UtcOffset) -> String {
+    offset
+        .format(OFFSET_FORMAT)
+        .expect("Offsets can always be formatted")
+}
+
+/// The capture time as shown to the user, including the offset where known.
+pub fn format_capture_time(time: PrimitiveDateTime, offset: Option<UtcOffset>) -> String {
+    let time = time
+        .format(DISPLAY_FORMAT)
+        .expect("Capture times can always be formatted");
+    match offset {
+        Some(offset) => format!("{time} {}", format_offset(offset)),
+        None => time,
+    }
+}
+
+/// Parse the amount to shift capture times by, like `-1:30` or `2d 3:00:15`.
+///
+/// The time is given as hours, minutes and seconds, any of which can be left off
+/// from the end, with an optional number of days before it.
+pub fn parse_shift(value: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid time shift {value}, expected a value like -1:30 or 2d 3:00");
+    let trimmed = value.trim();
+    let (negative, trimmed) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (days, time) = match trimmed.split_once('d') {
+        Some((days, time)) => (
+            days.trim().parse::<i64>().map_err(|_| invalid())?,
+            time.trim(),
+        ),
+        None => (0, trimmed),
+    };
+    let mut seconds = days * 24 * 60 * 60;
+    if !time.is_empty() {
+        let parts = time
+            .split(':')
+            .map(|p| p.trim().parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+        seconds += parts
+            .iter()
+            .zip([60 * 60, 60, 1])
+            .map(|(p, s)| p * s)
+            .sum::<i64>();
+    }
+    let shift = Duration::seconds(seconds);
+    Ok(if negative { -shift } else { shift })
+}
+
+/// A change to the capture times of pictures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeShift {
+    /// The amount added to each capture time
+    pub shift: Duration,
+    /// The timezone the capture times are in, which leaves the local times as they
+    /// are. Where this is None, the existing timezone is kept.
+    pub offset: Option<UtcOffset>,
+    /// Whether the new times are also written to the exif data of the files
+    pub write_exif: bool,
+}
+
+impl TimeShift {
+    pub fn is_empty(&self) -> bool {
+        self.shift.is_zero() && self.offset.is_none()
+    }
+
+    /// Update the capture time of the picture, returning whether it changed.
+    ///
+    /// Pictures without a capture time are left as they are, since there is nothing
+    /// to shift.
+    pub fn apply(&self, picture: &mut PictureData) -> bool {
+        let Some(time) = picture.capture_time else {
+            return false;
+        };
+        let offset = self.offset.or(picture.capture_offset);
+        let time = time + self.shift;
+        let changed = Some(time) != picture.capture_time || offset != picture.capture_offset;
+        picture.capture_time = Some(time);
+        picture.capture_offset = offset;
+        changed
+    }
+}
+
+fn exif_time(time: PrimitiveDateTime) -> Vec<u8> {
+    let mut value = time
+        .format(EXIF_TIME_FORMAT)
+        .expect("Capture times can always be formatted")
+        .into_bytes();
+    value.push(0);
+    value
+}
+
+fn exif_offset(offset: UtcOffset) -> Vec<u8> {
+    let mut value = format_offset(offset).into_bytes();
+    value.push(0);
+    value
+}
+
+/// Find the start of the TIFF structure holding the exif data, which is either the
+/// whole file for TIFF based RAW files, or within the APP1 segment of a JPEG.
+fn find_tiff(contents: &[u8]) -> Option<usize> {
+    if contents.starts_with(b"II*\0") || contents.starts_with(b"MM\0*") {
+        return Some(0);
+    }
+    if !contents.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut position = 2;
+    while let Some(&[0xff, marker, length_high, length_low, ..]) = contents.get(position..) {
+        match marker {
+            // Markers which don't have a length
+            0x01 | 0xd0..=0xd8 => position += 2,
+            // The image data follows, without any more metadata segments
+            0xd9 | 0xda => return None,
+            _ => {
+                let data = position + 4;
+                if marker == 0xe1 && contents[data..].starts_with(b"Exif\0\0") {
+                    return Some(data + 6);
+                }
+                position += 2 + u16::from_be_bytes([length_high, length_low]) as usize;
+            }
+        }
+    }
+    None
+}
+
+/// Read the integers within a TIFF structure, which can be either byte order.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            big_endian: data.starts_with(b"MM"),
+        }
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// The location of the text value of each tag within the IFD, following the
+    /// pointer to the exif IFD from the first IFD.
+    fn text_fields(&self, ifd: usize, context: Context, fields: &mut Vec<(Tag, Range<usize>)>) {
+        let Some(count) = self.u16(ifd) else {
+            return;
+        };
+        for index in 0..count as usize {
+            let entry = ifd + 2 + 12 * index;
+            let (Some(tag), Some(kind), Some(length), Some(value)) = (
+                self.u16(entry),
+                self.u16(entry + 2),
+                self.u32(entry + 4),
+                self.u32(entry + 8),
+            ) else {
+                return;
+            };
+            if context == Context::Tiff && tag == EXIF_IFD_POINTER {
+                self.text_fields(value as usize, Context::Exif, fields);
+            } else if kind == ASCII_TYPE {
+                // Values of up to four bytes are stored within the entry itself
+                let start = if length <= 4 {
+                    entry + 8
+                } else {
+                    value as usize
+                };
+                let range = start..start + length as usize;
+                if range.end <= self.data.len() {
+                    fields.push((Tag(context, tag), range));
+                }
+            }
+        }
+    }
+}
+
+/// Write a new capture time into the exif data within the contents of a file.
+///
+/// The times within the exif data have a fixed length, so are replaced in place
+/// without rewriting the rest of the exif data. This covers the original, digitised
+/// and modified times which match the previous capture time, which are all the same
+/// for pictures straight from the camera. For the same reason the timezone can only
+/// be changed where the file already has one, since adding a field requires
+/// rewriting the exif data.
+fn patch_exif_time(
+    contents: &mut [u8],
+    before: PrimitiveDateTime,
+    before_offset: Option<UtcOffset>,
+    after: PrimitiveDateTime,
+    after_offset: Option<UtcOffset>,
+) -> Result<()> {
+    let start = find_tiff(contents).ok_or(anyhow!("No exif data found"))?;
+    let tiff = Tiff::new(&contents[start..]);
+    let first_ifd = tiff.u32(4).ok_or(anyhow!("Invalid exif data"))?;
+    let mut fields = vec![];
+    tiff.text_fields(first_ifd as usize, Context::Tiff, &mut fields);
+
+    let mut patch = |tags: &[Tag], from: &[u8], to: &[u8]| -> usize {
+        let mut count = 0;
+        for (tag, range) in fields.iter() {
+            let value = &mut contents[start + range.start..start + range.end];
+            if tags.contains(tag) && *value == *from {
+                value.copy_from_slice(to);
+                count += 1;
+            }
+        }
+        count
+    };
+    if before != after
+        && patch(
+            &[Tag::DateTime, Tag::DateTimeOriginal, Tag::DateTimeDigitized],
+            &exif_time(before),
+            &exif_time(after),
+        ) == 0
+    {
+        return Err(anyhow!(
+            "Unable to find the capture time within the exif data"
+        ));
+    }
+    match (before_offset, after_offset) {
+        (Some(before), Some(after)) if before != after => {
+            if patch(
+                &[
+                    Tag::OffsetTime,
+                    Tag::OffsetTimeOriginal,
+                    Tag::OffsetTimeDigitized,
+                ],
+                &exif_offset(before),
+                &exif_offset(after),
+            ) == 0
+            {
+                tracing::warn!("Unable to find the timezone within the exif data");
+            }
+        }
+        (None, Some(_)) => {
+            tracing::warn!("There is no timezone within the exif data to update");
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Write the new capture time of a picture into the exif data of its files.
+///
+/// All the files are updated in memory before any are written, so the JPEG and RAW
+/// files of a picture are either both updated or left as they were.
+pub fn write_exif_time(
+    filepaths: &[Utf8PathBuf],
+    before: PrimitiveDateTime,
+    before_offset: Option<UtcOffset>,
+    after: PrimitiveDateTime,
+    after_offset: Option<UtcOffset>,
+) -> Result<()> {
+    let mut files = vec![];
+    for filepath in filepaths.iter() {
+        let original = std::fs::read(filepath)?;
+        let mut contents = original.clone();
+        patch_exif_time(&mut contents, before, before_offset, after, after_offset)
+            .map_err(|e| anyhow!("{filepath}: {e}"))?;
+        // Writing to a temporary file first ensures the picture isn't left partially
+        // written.
+        let temporary =
+            filepath.with_extension(format!("{}.tmp", filepath.extension().unwrap_or_default()));
+        files.push((filepath, temporary, original, contents));
+    }
+
+    let written = files
+        .iter()
+        .try_for_each(|(_, temporary, _, contents)| std::fs::write(temporary, contents));
+    if let Err(e) = written {
+        for (_, temporary, _, _) in files.iter() {
+            let _ = std::fs::remove_file(temporary);
+        }
+        return Err(e.into());
+    }
+    for (index, (filepath, temporary, _, _)) in files.iter().enumerate() {
+        if let Err(e) = std::fs::rename(temporary, filepath) {
+            // Put back the files which were already replaced
+            for (filepath, temporary, original, _) in files.iter().take(index) {
+                if let Err(e) = std::fs::write(filepath, original) {
+                    tracing::error!("Unable to restore {filepath}: {e}");
+                }
+                let _ = std::fs::remove_file(temporary);
+            }
+            for (_, temporary, _, _) in files.iter().skip(index) {
+                let _ = std::fs::remove_file(temporary);
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Where a picture belongs within the import structure for its capture time.
+///
+/// Where the structure numbers the pictures, the picture is given the first number
+/// not already used within the destination, so it doesn't collide with the pictures
+/// already there.
+fn refile_destination(picture: &PictureData, structure: &ImportStructure) -> Result<Utf8PathBuf> {
+    for sequence in 1..=MAX_SEQUENCE {
+        let destination = structure.build_filename(picture, None, sequence)?;
+        if destination == picture.filepath || !structure.uses_sequence() {
+            return Ok(destination);
+        }
+        let raw = picture
+            .raw_extension
+            .as_ref()
+            .map(|ext| destination.with_extension(ext));
+        let taken =
+            destination.try_exists()? || raw.map(|p| p.try_exists()).transpose()?.unwrap_or(false);
+        if !taken {
+            return Ok(destination);
+        }
+    }
+    Err(anyhow!("No free sequence number for {}", picture.filepath))
+}
+
+/// Move a picture to where it belongs within the import structure, along with its
+/// RAW file and sidecar.
+fn refile_picture(picture: &mut PictureData, structure: &ImportStructure) -> Result<()> {
+    let destination = refile_destination(picture, structure)?;
+    move_picture(picture, destination)
+}
+
+/// Move a picture along with its RAW file and sidecar.
+///
+/// Where one of the files can't be moved, the files already moved are put back so
+/// the picture isn't split between the two locations.
+fn move_picture(picture: &mut PictureData, destination: Utf8PathBuf) -> Result<()> {
+    if destination == picture.filepath {
+        return Ok(());
+    }
+    let raw = picture.raw_extension.as_ref().map(|ext| {
+        (
+            picture.filepath.with_extension(ext),
+            destination.with_extension(ext),
+        )
+    });
+    // The sidecars are moved whichever way they are named
+    let sidecars = SidecarNaming::ALL
+        .into_iter()
+        .map(|naming| {
+            (
+                sidecar_path(&picture.filepath, naming),
+                sidecar_path(&destination, naming),
+            )
+        })
+        .filter(|(source, _)| source.exists());
+    let moves: Vec<_> = std::iter::once((picture.filepath.clone(), destination.clone()))
+        .chain(raw)
+        .chain(sidecars)
+        .collect();
+
+    // Check everything first, so we don't leave the picture partially moved
+    for (_, to) in moves.iter() {
+        if to.try_exists()? {
+            return Err(anyhow!("File {to} already exists, not moving"));
+        }
+    }
+    let parent = destination
+        .parent()
+        .ok_or(anyhow!("No parent directory of {destination}"))?;
+    std::fs::create_dir_all(parent)?;
+    for (index, (from, to)) in moves.iter().enumerate() {
+        if let Err(e) = std::fs::rename(from, to) {
+            for (from, to) in moves.iter().take(index) {
+                if let Err(e) = std::fs::rename(to, from) {
+                    tracing::error!("Unable to move {to} back to {from}: {e}");
+                }
+            }
+            return Err(anyhow!("Unable to move {from} to {to}: {e}"));
+        }
+    }
+    picture.filepath = destination;
+    Ok(())
+}
+
+/// Replace the capture time of `from` with the capture time of `to` within the exif
+/// data of the files of the picture.
+fn write_picture_time(picture: &PictureData, from: &PictureData, to: &PictureData) -> Result<()> {
+    let (Some(before), Some(after)) = (from.capture_time, to.capture_time) else {
+        return Ok(());
+    };
+    let raw = picture
+        .raw_extension
+        .as_ref()
+        .map(|ext| picture.filepath.with_extension(ext));
+    let filepaths: Vec<Utf8PathBuf> = std::iter::once(picture.filepath.clone())
+        .chain(raw)
+        .collect();
+    write_exif_time(
+        &filepaths,
+        before,
+        from.capture_offset,
+        after,
+        to.capture_offset,
+    )
+}
+
+/// Update the files of a picture after its capture time has changed.
+///
+/// Where the picture can't be re-filed, the exif data is put back so the files are
+/// left as they were.
+fn update_files(
+    picture: &mut PictureData,
+    before: &PictureData,
+    shift: &TimeShift,
+    structure: Option<&ImportStructure>,
+) -> Result<()> {
+    if shift.write_exif {
+        write_picture_time(picture, before, picture)?;
+    }
+    let updated = (|| -> Result<()> {
+        if shift.write_exif {
+            // The contents have changed, so the hashes are no longer valid
+            picture.update_hashes()?;
+        }
+        if let Some(structure) = structure {
+            refile_picture(picture, structure)?;
+        }
+        Ok(())
+    })();
+    if updated.is_err() {
+        restore_files(picture, before, shift);
+    }
+    updated
+}
+
+/// Put the files of a picture back to how they were before its capture time was
+/// shifted, where the shift couldn't be completed.
+fn restore_files(picture: &PictureData, before: &PictureData, shift: &TimeShift) {
+    let mut picture = picture.clone();
+    if let Err(e) = move_picture(&mut picture, before.filepath.clone()) {
+        tracing::error!("Unable to move {} back: {e}", picture.filepath);
+        return;
+    }
+    if shift.write_exif {
+        if let Err(e) = write_picture_time(&picture, &picture, before) {
+            tracing::error!(
+                "Unable to restore the capture time of {}: {e}",
+                picture.filepath
+            );
+        }
+    }
+}
+
+/// Write the shifted pictures to the database, all within a single transaction.
+async fn write_pictures(
+    db: &DatabaseConnection,
+    pictures: &mut [(PictureData, PictureData)],
+) -> Result<()> {
+    let txn = db.begin().await?;
+    for (before, picture) in pictures.iter_mut() {
+        if picture.filepath != before.filepath {
+            picture.directory_id = get_parent_directory(&txn, &picture.directory().into()).await?;
+        }
+        update_picture_data(&txn, picture.clone()).await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// The outcome of shifting the capture time of each picture.
+#[derive(Debug, Clone, Default)]
+pub struct ShiftReport {
+    /// The pictures with their new capture times
+    pub shifted: Vec<PictureData>,
+    /// The pictures left as they were, with the reason why
+    pub failed: Vec<(Utf8PathBuf, String)>,
+}
+
+impl std::fmt::Display for ShiftReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Shifted {} pictures", self.shifted.len())?;
+        if !self.failed.is_empty() {
+            write!(f, ", {} failed", self.failed.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Shift the capture times of pictures, updating the database and optionally the
+/// files themselves.
+///
+/// Where re-filing, each picture is moved to where the import structure places it
+/// for the new capture time. Failing to update the files of a single picture doesn't
+/// stop the others from being updated. The pictures are then written to the
+/// database together, and where that fails their files are put back, so the files
+/// always match the library.
+#[tracing::instrument(name = "Shifting capture times", skip(db, pictures, structure))]
+pub async fn shift_capture_times(
+    db: &DatabaseConnection,
+    pictures: Vec<PictureData>,
+    shift: TimeShift,
+    structure: Option<ImportStructure>,
+) -> Result<ShiftReport, Error> {
+    let updated: Vec<(PictureData, Result<PictureData>)> = tokio::task::spawn_blocking(move || {
+        pictures
+            .into_iter()
+            .filter_map(|before| {
+                let mut picture = before.clone();
+                if !shift.apply(&mut picture) {
+                    return None;
+                }
+                let result = update_files(&mut picture, &before, &shift, structure.as_ref());
+                Some((before, result.map(|()| picture)))
+            })
+            .collect()
+    })
+    .await?;
+
+    let mut report = ShiftReport::default();
+    let mut shifted = vec![];
+    for (before, result) in updated.into_iter() {
+        match result {
+            Ok(picture) => shifted.push((before, picture)),
+            Err(e) => {
+                tracing::warn!("Unable to shift the time of {}: {e}", before.filepath);
+                report.failed.push((before.filepath, e.to_string()));
+            }
+        }
+    }
+
+    if let Err(e) = write_pictures(db, &mut shifted).await {
+        tracing::error!("Unable to write the shifted capture times: {e}");
+        let restored = shifted.clone();
+        tokio::task::spawn_blocking(move || {
+            for (before, picture) in restored.iter() {
+                restore_files(picture, before, &shift);
+            }
+        })
+        .await?;
+        report.failed.extend(shifted.into_iter().map(|(before, _)| {
+            (
+                before.filepath,
+                format!("Unable to update the library: {e}"),
+            )
+        }));
+    } else {
+        report.shifted = shifted.into_iter().map(|(_, picture)| picture).collect();
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Clone)]
+pub enum CaptureTimeError {
+    ShiftFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for CaptureTimeError {
+    fn from(error: anyhow::Error) -> Self {
+        CaptureTimeError::ShiftFailed(Arc::new(error))
+    }
+}
+
+impl std::fmt::Display for CaptureTimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureTimeError::ShiftFailed(e) => write!(f, "Shifting capture times failed: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::macros::{datetime, offset};
+
+    use super::*;
+
+    /// A little endian TIFF structure with the modified time in the first IFD, and the
+    /// original time and timezone within the exif IFD.
+    fn tiff(time: &[u8], offset: &[u8]) -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            [
+                &tag.to_le_bytes()[..],
+                &kind.to_le_bytes(),
+                &count.to_le_bytes(),
+                &value.to_le_bytes(),
+            ]
+            .concat()
+        };
+        // The header, the first IFD with two entries then the exif IFD with two entries
+        let exif_ifd = 8 + 2 + 2 * 12 + 4;
+        let values = exif_ifd + 2 + 2 * 12 + 4;
+        let mut data = b"II*\0".to_vec();
+        data.extend(8u32.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+        data.extend(entry(0x0132, ASCII_TYPE, 20, values));
+        data.extend(entry(EXIF_IFD_POINTER, 4, 1, exif_ifd));
+        data.extend(0u32.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+        data.extend(entry(0x9003, ASCII_TYPE, 20, values + 20));
+        data.extend(entry(0x9011, ASCII_TYPE, 7, values + 40));
+        data.extend(0u32.to_le_bytes());
+        data.extend(time);
+        data.extend(time);
+        data.extend(offset);
+        data
+    }
+
+    #[test]
+    fn test_patch_tiff() {
+        let before = datetime!(2024-03-01 10:00:00);
+        let after = datetime!(2024-03-01 12:30:00);
+        let mut contents = tiff(&exif_time(before), &exif_offset(offset!(+1)));
+        patch_exif_time(
+            &mut contents,
+            before,
+            Some(offset!(+1)),
+            after,
+            Some(offset!(+10)),
+        )
+        .unwrap();
+        assert_eq!(
+            contents,
+            tiff(&exif_time(after), &exif_offset(offset!(+10)))
+        );
+    }
+
+    #[test]
+    fn test_patch_jpeg() {
+        let before = datetime!(2024-03-01 10:00:00);
+        let after = datetime!(2024-03-02 10:00:00);
+        let jpeg = |time: PrimitiveDateTime| {
+            let segment = [&b"Exif\0\0"[..], &tiff(&exif_time(time), b"+00:00\0")].concat();
+            let mut data = vec![0xff, 0xd8, 0xff, 0xe1];
+            data.extend((segment.len() as u16 + 2).to_be_bytes());
+            data.extend(segment);
+            data.extend([0xff, 0xda, 0, 2, 1, 2, 3]);
+            data
+        };
+        let mut contents = jpeg(before);
+        patch_exif_time(&mut contents, before, None, after, None).unwrap();
+        assert_eq!(contents, jpeg(after));
+    }
+
+    #[test]
+    fn test_patch_other_time() {
+        // Only the times matching the previous capture time are replaced
+        let mut contents = tiff(&exif_time(datetime!(2020-01-01 0:00)), b"+00:00\0");
+        let result = patch_exif_time(
+            &mut contents,
+            datetime!(2024-03-01 10:00:00),
+            None,
+            datetime!(2024-03-01 11:00:00),
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_patch_without_exif() {
+        let mut contents = b"not a picture".to_vec();
+        let before = datetime!(2024-03-01 10:00:00);
+        assert!(patch_exif_time(&mut contents, before, None, before, None).is_err());
+    }
+}
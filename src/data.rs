@@ -4,17 +4,17 @@
 // to be properly handled and tested, so we split it into this file to maintain
 // the understanding and separation.
 
+use std::collections::HashMap;
 use std::io::Cursor;
 use std::ops::Not;
 use std::sync::Arc;
 
-use ::entity::{picture, Selection};
+use ::entity::{picture, picture_metadata, picture_tag, tag, Selection};
 use anyhow::anyhow;
 use anyhow::Error;
 use anyhow::Result;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use entity::directory;
-use futures::future::join_all;
 use futures::StreamExt;
 use iced::task::sipper;
 use iced::task::Straw;
@@ -23,38 +23,197 @@ use itertools::Itertools;
 use sea_orm::entity::*;
 use sea_orm::prelude::*;
 use sea_orm::query::*;
+use sea_orm::sea_query::{Expr, OnConflict, SimpleExpr, Value};
+use sea_orm::{ConnectionTrait, Statement, TransactionTrait};
 use uuid::Uuid;
 
-use crate::directory::DirectoryData;
+use crate::directory::{DirectoryCounts, DirectoryData};
 use crate::picture::PictureThumbnail;
-use crate::picture::{PictureData, ThumbnailData};
-use crate::DirectoryDataDB;
+use crate::picture::{PictureData, PictureMetadata, ThumbnailData};
+use crate::tag::parent_tag;
+use crate::{get_parent_directory, DirectoryDataDB};
 
-/// Search for pictures in the database located within a directory
+/// Search for pictures in the database located within directories
 ///
-/// The directory searched is specified from the input. The directory is the
-/// full directory to the file, subdirectories are not included within the
-/// search.
+/// Only the pictures directly within the directories with the ids are found, so
+/// any subdirectories to include need to be within the ids.
 #[tracing::instrument(name = "Querying Picture from directories", skip(db))]
 pub(crate) async fn query_directory_pictures(
     db: &DatabaseConnection,
-    directory: DirectoryDataDB,
+    ids: Vec<Uuid>,
 ) -> Result<Vec<PictureThumbnail>, Error> {
-    let mut ids: Vec<Uuid> = vec![directory.id];
-    ids.extend(directory.children.iter());
-    Ok(picture::Entity::find()
-        .filter(picture::Column::DirectoryId.is_in(ids))
+    let mut pictures = vec![];
+    for group in ids.chunks(1024) {
+        pictures.extend(
+            picture::Entity::find()
+                .filter(picture::Column::DirectoryId.is_in(group.iter().copied()))
+                .find_with_related(tag::Entity)
+                .all(db)
+                .await?,
+        );
+    }
+    into_thumbnails(db, pictures).await
+}
+
+/// Count the pictures directly within each directory, along with those picked.
+///
+/// Directories without any pictures are left out.
+pub(crate) async fn query_directory_counts(
+    db: &DatabaseConnection,
+) -> Result<HashMap<Uuid, DirectoryCounts>, Error> {
+    let counts: Vec<(Uuid, i64, i64)> = picture::Entity::find()
+        .select_only()
+        .column(picture::Column::DirectoryId)
+        .column_as(picture::Column::Id.count(), "total")
+        .column_as(
+            Expr::cust_with_values("coalesce(sum(selection = ?), 0)", [Selection::Pick]),
+            "picks",
+        )
+        .group_by(picture::Column::DirectoryId)
+        .into_tuple()
         .all(db)
-        .await?
+        .await?;
+    Ok(counts
         .into_iter()
-        .map(PictureData::from)
-        .map(|d| PictureThumbnail {
-            data: d,
-            handle: None,
+        .map(|(id, total, picks)| {
+            let counts = DirectoryCounts {
+                total: total as u64,
+                picks: picks as u64,
+            };
+            (id, counts)
         })
         .collect())
 }
 
+/// Remember whether the subdirectories of the directory are shown in the tree.
+pub(crate) async fn set_directory_expanded(
+    db: &DatabaseConnection,
+    id: Uuid,
+    expanded: bool,
+) -> Result<(), Error> {
+    directory::ActiveModel {
+        id: ActiveValue::Unchanged(id),
+        expanded: ActiveValue::Set(expanded),
+        ..Default::default()
+    }
+    .update(db)
+    .await?;
+    Ok(())
+}
+
+/// Pair a picture with the directory loaded along with it.
+///
+/// Every picture is within a directory, so a picture without one means the library
+/// is inconsistent.
+fn with_directory(
+    (picture, directory): (picture::Model, Option<directory::Model>),
+) -> Result<(picture::Model, directory::Model), Error> {
+    match directory {
+        Some(directory) => Ok((picture, directory)),
+        None => Err(anyhow!(
+            "The directory of picture {} is missing",
+            picture.id
+        )),
+    }
+}
+
+/// Load the directories with the ids, keyed by their id.
+async fn query_directories_by_id<C: ConnectionTrait>(
+    db: &C,
+    ids: impl IntoIterator<Item = Uuid>,
+) -> Result<HashMap<Uuid, directory::Model>, Error> {
+    let ids: Vec<Uuid> = ids.into_iter().unique().collect();
+    let mut directories = HashMap::new();
+    for group in ids.chunks(1024) {
+        directories.extend(
+            directory::Entity::find()
+                .filter(directory::Column::Id.is_in(group.iter().copied()))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|d| (d.id, d)),
+        );
+    }
+    Ok(directories)
+}
+
+async fn into_thumbnails(
+    db: &DatabaseConnection,
+    pictures: Vec<(picture::Model, Vec<tag::Model>)>,
+) -> Result<Vec<PictureThumbnail>, Error> {
+    // The directories are loaded separately, like the metadata, since only one
+    // relation can be loaded along with the pictures.
+    let directories =
+        query_directories_by_id(db, pictures.iter().map(|(p, _)| p.directory_id)).await?;
+    let mut thumbnails: Vec<PictureThumbnail> = pictures
+        .into_iter()
+        .map(|(picture, tags)| {
+            let directory = directories.get(&picture.directory_id).ok_or(anyhow!(
+                "The directory of picture {} is missing",
+                picture.id
+            ))?;
+            let mut data = PictureData::from((picture, directory));
+            data.tags = tags.into_iter().map(|t| t.name).sorted().collect();
+            Ok(PictureThumbnail { data, handle: None })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    // The metadata is loaded separately, since only one relation can be loaded along
+    // with the pictures.
+    let ids: Vec<Uuid> = thumbnails.iter().map(|t| t.data.id).collect();
+    let mut metadata = HashMap::new();
+    for group in ids.chunks(1024) {
+        metadata.extend(
+            picture_metadata::Entity::find()
+                .filter(picture_metadata::Column::PictureId.is_in(group.iter().copied()))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|m| (m.picture_id, PictureMetadata::from(m))),
+        );
+    }
+    for thumbnail in thumbnails.iter_mut() {
+        thumbnail.data.metadata = metadata.remove(&thumbnail.data.id);
+    }
+    Ok(thumbnails)
+}
+
+/// Convert the search entered by the user into an FTS5 query.
+///
+/// Each word is quoted so any characters with a meaning to FTS5 are searched for
+/// literally, and matches the start of the indexed words. Pictures need to match
+/// all the words.
+fn search_expression(search: &str) -> Option<String> {
+    let terms: Vec<String> = search
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Search for pictures across the whole library
+///
+/// The filenames, directories, tags and captions of the pictures are searched
+/// using the full-text index maintained by the database.
+#[tracing::instrument(name = "Searching pictures", skip(db))]
+pub(crate) async fn query_search_pictures(
+    db: &DatabaseConnection,
+    search: &str,
+) -> Result<Vec<PictureThumbnail>, Error> {
+    let Some(expression) = search_expression(search) else {
+        return Ok(vec![]);
+    };
+    let pictures = picture::Entity::find()
+        .filter(Expr::cust_with_values(
+            "pictures.rowid IN (SELECT rowid FROM pictures_search WHERE pictures_search MATCH ?)",
+            [expression],
+        ))
+        .find_with_related(tag::Entity)
+        .all(db)
+        .await?;
+    into_thumbnails(db, pictures).await
+}
+
 #[tracing::instrument(
     name = "Querying Picture within directories or subdirectories.",
     skip(db)
@@ -63,24 +222,76 @@ pub(crate) async fn query_existing_pictures(
     db: &DatabaseConnection,
     directory: &Utf8PathBuf,
 ) -> Result<Vec<Utf8PathBuf>, Error> {
-    Ok(picture::Entity::find()
-        .filter(
-            Condition::all()
-                // This matches the current directory. There is no slash after
-                // the directory so we just use the exact value.
-                .add(picture::Column::Directory.eq(format!("{directory}")))
-                // This matches all the subdirectories, which are needed since we
-                // perform a recursive search when adding new directories.
-                .add(picture::Column::Directory.like(format!("{directory}/%"))),
-        )
+    picture::Entity::find()
+        .find_also_related(directory::Entity)
+        // This matches the current directory along with all the subdirectories,
+        // which are needed since we perform a recursive search when adding new
+        // directories.
+        .filter(within_directory("directories.directory", directory))
         .all(db)
         .await?
-        .iter()
-        .map(picture::Model::filepath)
-        .collect::<Vec<_>>())
+        .into_iter()
+        .map(|p| with_directory(p).map(|(p, d)| p.filepath(&d)))
+        .collect()
 }
 
-fn load_thumbnail_buffer(filepath: &Utf8PathBuf, size: u32) -> Result<Vec<u8>, Error> {
+/// Load the pictures within a directory or any of its subdirectories.
+#[tracing::instrument(name = "Querying pictures within directory", skip(db))]
+pub(crate) async fn query_pictures_within(
+    db: &DatabaseConnection,
+    directory: &Utf8PathBuf,
+) -> Result<Vec<PictureData>, Error> {
+    picture::Entity::find()
+        .find_also_related(directory::Entity)
+        .filter(within_directory("directories.directory", directory))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|p| with_directory(p).map(|(p, d)| PictureData::from((p, &d))))
+        .collect()
+}
+
+/// The content hashes of a picture already within the library.
+#[derive(Debug, Clone, FromQueryResult)]
+pub(crate) struct PictureHashes {
+    pub directory: String,
+    pub filename: String,
+    pub short_hash: Option<Vec<u8>>,
+    pub full_hash: Option<Vec<u8>>,
+}
+
+impl PictureHashes {
+    pub fn filepath(&self) -> Utf8PathBuf {
+        [self.directory.as_str(), self.filename.as_str()]
+            .iter()
+            .collect()
+    }
+}
+
+/// Load the content hashes of every picture within the library.
+///
+/// This only loads the columns required for duplicate detection, so we are not
+/// pulling every thumbnail into memory. The directory of each picture is taken from
+/// the directory it is related to.
+#[tracing::instrument(name = "Querying picture hashes", skip(db))]
+pub(crate) async fn query_picture_hashes(
+    db: &DatabaseConnection,
+) -> Result<Vec<PictureHashes>, Error> {
+    Ok(picture::Entity::find()
+        .inner_join(directory::Entity)
+        .select_only()
+        .column_as(directory::Column::Directory, "directory")
+        .columns([
+            picture::Column::Filename,
+            picture::Column::ShortHash,
+            picture::Column::FullHash,
+        ])
+        .into_model::<PictureHashes>()
+        .all(db)
+        .await?)
+}
+
+pub(crate) fn load_thumbnail_buffer(filepath: &Utf8PathBuf, size: u32) -> Result<Vec<u8>, Error> {
     let _span = tracing::info_span!("Updating thumbnail");
     let thumbnail_buffer: Result<Cursor<Vec<u8>>, Error> = {
         let mut buffer = Cursor::new(vec![]);
@@ -115,6 +326,7 @@ pub(crate) fn update_thumbnails(
             .map_err(|e| Into::<ThumbnailError>::into(Error::from(e)))?;
         tracing::info!("{} {:?}", update_all, num_items);
         let mut paginated_results = query
+            .find_also_related(directory::Entity)
             .stream(&db)
             .await
             .map_err(|e| Into::<ThumbnailError>::into(Error::from(e)))?;
@@ -123,7 +335,14 @@ pub(crate) fn update_thumbnails(
         while let Some(picture) = paginated_results.next().await {
             // tracing::debug!("Loading Picture {picture:?}");
             let picture = picture.map_err(|e| Into::<ThumbnailError>::into(Error::from(e)))?;
-            let filepath = picture.filepath().clone();
+            let (picture, directory) = match with_directory(picture) {
+                Ok(p) => p,
+                Err(e) => {
+                    tracing::error!("{e}");
+                    continue;
+                }
+            };
+            let filepath = picture.filepath(&directory);
             let buffer_result =
                 tokio::task::spawn_blocking(move || load_thumbnail_buffer(&filepath, 480))
                     .await
@@ -167,18 +386,95 @@ pub(crate) async fn add_new_images(
     db: &DatabaseConnection,
     images: Vec<PictureData>,
 ) -> Result<(), Error> {
-    let mut futures = vec![];
-    for group in &images
+    // Pictures are located through their directory, so can't be added without one
+    if let Some(image) = images.iter().find(|i| i.directory_id.is_none()) {
+        return Err(anyhow!(
+            "No directory for {} within the library",
+            image.filepath
+        ));
+    }
+    let metadata: Vec<_> = images
+        .iter()
+        .filter_map(|i| i.metadata.clone().map(|m| m.into_active(i.id)))
+        .collect();
+
+    // The pictures are added in chunks, to stay within the limit on the number of
+    // values within a statement, all within a transaction so a failure doesn't
+    // leave some of the pictures added without the others.
+    let pictures: Vec<Vec<_>> = images
         .into_iter()
         .map(PictureData::into_active)
         .chunks(1024)
-    {
-        futures.push(picture::Entity::insert_many(group).exec(db))
+        .into_iter()
+        .map(Iterator::collect)
+        .collect();
+    let metadata: Vec<Vec<_>> = metadata
+        .into_iter()
+        .chunks(1024)
+        .into_iter()
+        .map(Iterator::collect)
+        .collect();
+    let txn = db.begin().await?;
+    for group in pictures.into_iter() {
+        picture::Entity::insert_many(group).exec(&txn).await?;
     }
-    join_all(futures).await;
+    // The metadata references the pictures, so can only be added once they exist
+    for group in metadata.into_iter() {
+        picture_metadata::Entity::insert_many(group)
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await?;
     Ok(())
 }
 
+/// Read the metadata of the pictures without any stored, like those added to the
+/// library before the metadata was recorded.
+///
+/// This returns the number of pictures updated.
+#[tracing::instrument(name = "Updating missing metadata", skip(db))]
+pub(crate) async fn update_missing_metadata(db: &DatabaseConnection) -> Result<usize, Error> {
+    let pictures: Vec<(Uuid, Utf8PathBuf)> = picture::Entity::find()
+        .left_join(picture_metadata::Entity)
+        .filter(picture_metadata::Column::PictureId.is_null())
+        .find_also_related(directory::Entity)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|p| with_directory(p).map(|(p, d)| (p.id, p.filepath(&d))))
+        .collect::<Result<_, Error>>()?;
+
+    let metadata: Vec<_> = tokio::task::spawn_blocking(move || {
+        pictures
+            .into_iter()
+            .map(|(id, filepath)| {
+                let metadata = PictureMetadata::from_file(&filepath).unwrap_or_else(|e| {
+                    tracing::debug!("No exif data within {filepath}: {e}");
+                    let mut metadata = PictureMetadata::default();
+                    metadata.update_dimensions(&filepath);
+                    metadata
+                });
+                metadata.into_active(id)
+            })
+            .collect()
+    })
+    .await?;
+
+    let count = metadata.len();
+    let groups: Vec<Vec<_>> = metadata
+        .into_iter()
+        .chunks(1024)
+        .into_iter()
+        .map(Iterator::collect)
+        .collect();
+    for group in groups {
+        picture_metadata::Entity::insert_many(group)
+            .exec(db)
+            .await?;
+    }
+    Ok(count)
+}
+
 pub(crate) async fn query_directories(
     db: &DatabaseConnection,
 ) -> Result<Vec<DirectoryDataDB>, Error> {
@@ -199,9 +495,10 @@ pub(crate) async fn query_directories(
 pub(crate) async fn query_unique_directories(
     db: &DatabaseConnection,
 ) -> Result<Vec<DirectoryData>, Error> {
-    Ok(picture::Entity::find()
+    Ok(directory::Entity::find()
+        .inner_join(picture::Entity)
         .select_only()
-        .column(picture::Column::Directory)
+        .column(directory::Column::Directory)
         .distinct()
         .into_tuple::<String>()
         .all(db)
@@ -211,39 +508,357 @@ pub(crate) async fn query_unique_directories(
         .collect())
 }
 
-pub(crate) async fn update_picture_data(
-    db: &DatabaseConnection,
+pub(crate) async fn update_picture_data<C: ConnectionTrait>(
+    db: &C,
     data: PictureData,
 ) -> Result<(), Error> {
     data.into_active().update(db).await?;
     Ok(())
 }
 
-/// Modify the state of the selected attribute on an image
+/// Modify the state of the picture set while culling
 ///
-/// Provides an interface to the database ensuring the state of the selected
-/// image is appropriately updated.
-pub(crate) async fn update_selection_state(
+/// Only the selection, rating, flag and hidden state are written, so the other
+/// values of the picture aren't replaced by an outdated copy. This returns whether
+/// the picture was updated, since it may have been removed from the library after
+/// it was changed.
+pub(crate) async fn update_picture_state<C: ConnectionTrait>(
+    db: &C,
+    picture: &PictureData,
+) -> Result<bool, Error> {
+    let updated = picture::Entity::update_many()
+        .set(picture::ActiveModel {
+            selection: ActiveValue::Set(picture.selection),
+            rating: ActiveValue::Set(picture.rating),
+            flag: ActiveValue::Set(picture.flag),
+            hidden: ActiveValue::Set(picture.hidden),
+            caption: ActiveValue::Set(picture.caption.clone()),
+            ..Default::default()
+        })
+        .filter(picture::Column::Id.eq(picture.id))
+        .exec(db)
+        .await?;
+    Ok(updated.rows_affected > 0)
+}
+
+/// The condition matching a directory column with the directory or any of its
+/// subdirectories.
+///
+/// This compares the start of the value rather than using LIKE, so characters like
+/// `%` and `_` within the directory are matched exactly.
+fn within_directory(column: &str, directory: &Utf8Path) -> SimpleExpr {
+    // SQLite counts the length of text in characters rather than bytes
+    let length = directory.as_str().chars().count() as i64;
+    Expr::cust_with_values(
+        format!("({column} = ? OR substr({column}, 1, ?) = ?)"),
+        [
+            Value::from(directory.to_string()),
+            Value::from(length + 1),
+            Value::from(format!("{directory}/")),
+        ],
+    )
+}
+
+/// Move a directory of the library to a new location, like when a drive has been
+/// mounted at a different path.
+///
+/// The paths of the directory and all its subdirectories are updated, replacing the
+/// start of the path, which moves all the pictures within them, including hidden
+/// pictures. This is done within a single transaction, so the library is never left
+/// partially moved.
+#[tracing::instrument(name = "Relocating directory", skip(db))]
+pub(crate) async fn relocate_directory(
     db: &DatabaseConnection,
-    id: Uuid,
-    selection: Selection,
+    from: &Utf8Path,
+    to: &Utf8Path,
 ) -> Result<(), Error> {
+    if from == to {
+        return Ok(());
+    }
+    if to.starts_with(from) {
+        return Err(anyhow!("Unable to move {from} within itself"));
+    }
+    let txn = db.begin().await?;
+
+    // Merging with a directory already in the library could result in duplicate
+    // pictures, so is not supported.
+    let existing = directory::Entity::find()
+        .filter(within_directory("directory", to))
+        .count(&txn)
+        .await?;
+    if existing > 0 {
+        return Err(anyhow!("{to} is already within the library"));
+    }
+
+    // The values replace the start of the path, keeping the rest of it
+    let rebase = Expr::cust_with_values(
+        "? || substr(directory, ?)",
+        [
+            Value::from(to.to_string()),
+            Value::from(from.as_str().chars().count() as i64 + 1),
+        ],
+    );
+    let directories = directory::Entity::update_many()
+        .col_expr(directory::Column::Directory, rebase)
+        .filter(within_directory("directory", from))
+        .exec(&txn)
+        .await?;
+    tracing::info!("Moved {} directories to {to}", directories.rows_affected);
+
+    // The directory now has a different parent, which may not exist yet
+    let parent_id = match to.parent() {
+        Some(parent) => get_parent_directory(&txn, &parent.to_path_buf()).await?,
+        None => None,
+    };
+    directory::Entity::update_many()
+        .col_expr(directory::Column::ParentId, Expr::value(parent_id))
+        .filter(directory::Column::Directory.eq(to.as_str()))
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+    Ok(())
+}
+
+/// The pictures which aren't within any directory of the library, returning the id
+/// and filename of each.
+///
+/// These can't be loaded as models, since the directory is required.
+#[tracing::instrument(name = "Querying unplaced pictures", skip(db))]
+pub(crate) async fn query_unplaced_pictures(
+    db: &DatabaseConnection,
+) -> Result<Vec<(Uuid, String)>, Error> {
+    let statement = Statement::from_string(
+        db.get_database_backend(),
+        "SELECT id, filename FROM pictures \
+         WHERE directory_id IS NULL OR directory_id NOT IN (SELECT id FROM directories)",
+    );
+    db.query_all(statement)
+        .await?
+        .iter()
+        .map(|row| Ok((row.try_get("", "id")?, row.try_get("", "filename")?)))
+        .collect()
+}
+
+/// The ids of the directories which directly contain pictures.
+pub(crate) async fn query_picture_directory_ids(
+    db: &DatabaseConnection,
+) -> Result<Vec<Uuid>, Error> {
+    Ok(picture::Entity::find()
+        .select_only()
+        .column(picture::Column::DirectoryId)
+        .filter(picture::Column::DirectoryId.is_not_null())
+        .distinct()
+        .into_tuple::<Uuid>()
+        .all(db)
+        .await?)
+}
+
+/// Remove pictures from the library, leaving their files in place.
+///
+/// The tags and metadata of the pictures are removed along with them.
+pub(crate) async fn remove_pictures(db: &DatabaseConnection, ids: Vec<Uuid>) -> Result<u64, Error> {
+    Ok(picture::Entity::delete_many()
+        .filter(picture::Column::Id.is_in(ids))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+/// Forget the RAW files of pictures, so only the main file is used.
+pub(crate) async fn clear_raw_extensions(
+    db: &DatabaseConnection,
+    ids: Vec<Uuid>,
+) -> Result<u64, Error> {
+    Ok(picture::Entity::update_many()
+        .col_expr(
+            picture::Column::RawExtension,
+            Expr::value(Option::<String>::None),
+        )
+        .filter(picture::Column::Id.is_in(ids))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+/// Set the parent of each directory to the directory containing it, creating any
+/// missing parents.
+pub(crate) async fn relink_directories(
+    db: &DatabaseConnection,
+    ids: Vec<Uuid>,
+) -> Result<u64, Error> {
+    let txn = db.begin().await?;
+    let directories = directory::Entity::find()
+        .filter(directory::Column::Id.is_in(ids))
+        .all(&txn)
+        .await?;
+    let count = directories.len() as u64;
+    for directory in directories.into_iter() {
+        let parent_id = match Utf8Path::new(&directory.directory).parent() {
+            Some(parent) => get_parent_directory(&txn, &parent.to_path_buf()).await?,
+            None => None,
+        };
+        directory::ActiveModel {
+            id: ActiveValue::Unchanged(directory.id),
+            parent_id: ActiveValue::Set(parent_id),
+            ..Default::default()
+        }
+        .update(&txn)
+        .await?;
+    }
+    txn.commit().await?;
+    Ok(count)
+}
+
+/// Remove directories from the library.
+///
+/// Any other directories with these as their parent are left without a parent,
+/// rather than being removed along with them.
+pub(crate) async fn remove_directories(
+    db: &DatabaseConnection,
+    ids: Vec<Uuid>,
+) -> Result<u64, Error> {
+    let txn = db.begin().await?;
+    directory::Entity::update_many()
+        .col_expr(
+            directory::Column::ParentId,
+            Expr::value(Option::<Uuid>::None),
+        )
+        .filter(directory::Column::ParentId.is_in(ids.clone()))
+        .exec(&txn)
+        .await?;
+    let removed = directory::Entity::delete_many()
+        .filter(directory::Column::Id.is_in(ids))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    txn.commit().await?;
+    Ok(removed)
+}
+
+/// Create the thumbnail of a picture again from its file.
+///
+/// Where the file can't be read, the thumbnail is removed instead so it can be
+/// created once the file is available. This returns whether a new thumbnail was
+/// created.
+pub(crate) async fn replace_thumbnail(db: &DatabaseConnection, id: Uuid) -> Result<bool, Error> {
+    let picture = picture::Entity::find_by_id(id)
+        .find_also_related(directory::Entity)
+        .one(db)
+        .await?
+        .ok_or(anyhow!("ID does not exist within database."))?;
+    let (picture, directory) = with_directory(picture)?;
+    let filepath = picture.filepath(&directory);
+    let buffer = tokio::task::spawn_blocking(move || load_thumbnail_buffer(&filepath, 480))
+        .await?
+        .inspect_err(|e| tracing::warn!("Unable to create thumbnail: {e}"))
+        .ok();
+    let replaced = buffer.is_some();
     picture::ActiveModel {
         id: ActiveValue::Unchanged(id),
-        selection: ActiveValue::Set(selection),
+        thumbnail: ActiveValue::Set(buffer),
         ..Default::default()
     }
     .update(db)
     .await?;
+    Ok(replaced)
+}
+
+/// The names of all the tags, in alphabetical order.
+#[tracing::instrument(name = "Querying tags", skip(db))]
+pub(crate) async fn query_tags(db: &DatabaseConnection) -> Result<Vec<String>, Error> {
+    Ok(tag::Entity::find()
+        .select_only()
+        .column(tag::Column::Name)
+        .order_by_asc(tag::Column::Name)
+        .into_tuple::<String>()
+        .all(db)
+        .await?)
+}
+
+/// Find the tag with the name, creating it along with any missing parents.
+async fn get_or_create_tag<C: ConnectionTrait>(db: &C, name: &str) -> Result<Uuid, Error> {
+    if let Some(existing) = tag::Entity::find()
+        .filter(tag::Column::Name.eq(name))
+        .one(db)
+        .await?
+    {
+        return Ok(existing.id);
+    }
+    let parent_id = match parent_tag(name) {
+        Some(parent) => Some(Box::pin(get_or_create_tag(db, parent)).await?),
+        None => None,
+    };
+    let id = Uuid::new_v4();
+    tag::ActiveModel {
+        id: ActiveValue::Set(id),
+        name: ActiveValue::Set(name.to_owned()),
+        parent_id: ActiveValue::Set(parent_id),
+    }
+    .insert(db)
+    .await?;
+    Ok(id)
+}
+
+/// Apply the tag to each of the pictures, skipping those which already have the tag.
+#[tracing::instrument(name = "Adding tag to pictures", skip(db, pictures))]
+pub(crate) async fn add_picture_tags<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    name: &str,
+    pictures: Vec<Uuid>,
+) -> Result<(), Error> {
+    let txn = db.begin().await?;
+    let tag_id = get_or_create_tag(&txn, name).await?;
+    picture_tag::Entity::insert_many(pictures.into_iter().map(|picture_id| {
+        picture_tag::ActiveModel {
+            picture_id: ActiveValue::Set(picture_id),
+            tag_id: ActiveValue::Set(tag_id),
+        }
+    }))
+    .on_conflict(
+        OnConflict::columns([picture_tag::Column::PictureId, picture_tag::Column::TagId])
+            .do_nothing()
+            .to_owned(),
+    )
+    .do_nothing()
+    .exec(&txn)
+    .await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Remove the tag from each of the pictures.
+///
+/// The tag itself is kept, so it is still available to apply to other pictures.
+#[tracing::instrument(name = "Removing tag from pictures", skip(db, pictures))]
+pub(crate) async fn remove_picture_tags<C: ConnectionTrait>(
+    db: &C,
+    name: &str,
+    pictures: Vec<Uuid>,
+) -> Result<(), Error> {
+    let Some(tag) = tag::Entity::find()
+        .filter(tag::Column::Name.eq(name))
+        .one(db)
+        .await?
+    else {
+        return Ok(());
+    };
+    picture_tag::Entity::delete_many()
+        .filter(picture_tag::Column::TagId.eq(tag.id))
+        .filter(picture_tag::Column::PictureId.is_in(pictures))
+        .exec(db)
+        .await?;
     Ok(())
 }
 
 pub async fn load_thumbnail(db: &DatabaseConnection, id: Uuid) -> Result<ThumbnailData, Error> {
-    picture::Entity::find_by_id(id)
+    let picture = picture::Entity::find_by_id(id)
+        .find_also_related(directory::Entity)
         .one(db)
         .await?
-        .map(ThumbnailData::from)
-        .ok_or(anyhow!("ID does not exist within database."))
+        .ok_or(anyhow!("ID does not exist within database."))?;
+    let (picture, directory) = with_directory(picture)?;
+    Ok(ThumbnailData::from((picture, &directory)))
 }
 
 #[derive(Debug, Clone)]
@@ -256,3 +871,176 @@ impl From<anyhow::Error> for ThumbnailError {
         ThumbnailError::ThumbnailFailed(Arc::new(error))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::Database;
+
+    use super::*;
+
+    async fn library(directories: &[&str]) -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        for directory in directories.iter() {
+            get_parent_directory(&db, &Utf8PathBuf::from(*directory))
+                .await
+                .unwrap();
+        }
+        db
+    }
+
+    async fn directories_within(db: &DatabaseConnection, directory: &str) -> Vec<String> {
+        directory::Entity::find()
+            .filter(within_directory("directory", Utf8Path::new(directory)))
+            .order_by_asc(directory::Column::Directory)
+            .all(db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.directory)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_within_directory() {
+        let db = library(&["/photos/2024", "/photos2024", "/photo_", "/pho%/2024"]).await;
+        assert_eq!(
+            directories_within(&db, "/photos").await,
+            ["/photos", "/photos/2024"]
+        );
+        // The characters used as wildcards by LIKE are matched exactly
+        assert_eq!(directories_within(&db, "/photo_").await, ["/photo_"]);
+        assert_eq!(
+            directories_within(&db, "/pho%").await,
+            ["/pho%", "/pho%/2024"]
+        );
+        assert!(directories_within(&db, "/pho").await.is_empty());
+    }
+
+    async fn search(db: &DatabaseConnection, search: &str) -> Vec<String> {
+        query_search_pictures(db, search)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.data.filename())
+            .sorted()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let db = library(&["/photos/beach"]).await;
+        let directory_id = get_parent_directory(&db, &Utf8PathBuf::from("/photos/beach"))
+            .await
+            .unwrap();
+        let mut ids = vec![];
+        for filename in ["IMG_0001.JPG", "IMG_0002.JPG"] {
+            let id = Uuid::new_v4();
+            picture::Entity::insert(picture::ActiveModel {
+                id: ActiveValue::Set(id),
+                filename: ActiveValue::Set(filename.to_string()),
+                hidden: ActiveValue::Set(false),
+                selection: ActiveValue::Set(Selection::Ordinary),
+                directory_id: ActiveValue::Set(directory_id.unwrap()),
+                ..Default::default()
+            })
+            .exec(&db)
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+        assert_eq!(search(&db, "beach").await, ["IMG_0001.JPG", "IMG_0002.JPG"]);
+
+        // Each change finds the row of the picture within the index
+        add_picture_tags(&db, "sunset", vec![ids[0]]).await.unwrap();
+        assert_eq!(search(&db, "sunset").await, ["IMG_0001.JPG"]);
+        tag::Entity::update_many()
+            .col_expr(tag::Column::Name, Expr::value("dusk"))
+            .filter(tag::Column::Name.eq("sunset"))
+            .exec(&db)
+            .await
+            .unwrap();
+        assert!(search(&db, "sunset").await.is_empty());
+        assert_eq!(search(&db, "dusk").await, ["IMG_0001.JPG"]);
+        remove_picture_tags(&db, "dusk", vec![ids[0]])
+            .await
+            .unwrap();
+        assert!(search(&db, "dusk").await.is_empty());
+
+        picture::Entity::update_many()
+            .col_expr(picture::Column::Caption, Expr::value("Low tide"))
+            .filter(picture::Column::Id.eq(ids[1]))
+            .exec(&db)
+            .await
+            .unwrap();
+        assert_eq!(search(&db, "tide").await, ["IMG_0002.JPG"]);
+        picture::Entity::delete_by_id(ids[1])
+            .exec(&db)
+            .await
+            .unwrap();
+        assert!(search(&db, "tide").await.is_empty());
+        assert_eq!(search(&db, "beach").await, ["IMG_0001.JPG"]);
+    }
+
+    #[tokio::test]
+    async fn test_relocate_directory() {
+        let db = library(&["/mnt/old/photos/2024/March", "/mnt/old/photosx"]).await;
+        relocate_directory(
+            &db,
+            Utf8Path::new("/mnt/old/photos"),
+            Utf8Path::new("/media/photos"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            directories_within(&db, "/media").await,
+            [
+                "/media",
+                "/media/photos",
+                "/media/photos/2024",
+                "/media/photos/2024/March"
+            ]
+        );
+        assert_eq!(
+            directories_within(&db, "/mnt/old").await,
+            ["/mnt/old", "/mnt/old/photosx"]
+        );
+
+        // The moved directory is placed beneath its new parent
+        let find = |path: &str| {
+            directory::Entity::find().filter(directory::Column::Directory.eq(path.to_string()))
+        };
+        let photos = find("/media/photos").one(&db).await.unwrap().unwrap();
+        let media = find("/media").one(&db).await.unwrap().unwrap();
+        assert_eq!(photos.parent_id, Some(media.id));
+        let year = find("/media/photos/2024").one(&db).await.unwrap().unwrap();
+        assert_eq!(year.parent_id, Some(photos.id));
+    }
+
+    #[tokio::test]
+    async fn test_relocate_directory_invalid() {
+        let db = library(&["/photos/2024", "/backup/photos"]).await;
+        // A directory can't be moved within itself
+        assert!(relocate_directory(
+            &db,
+            Utf8Path::new("/photos"),
+            Utf8Path::new("/photos/2024/photos")
+        )
+        .await
+        .is_err());
+        // Nor merged with a directory already within the library
+        assert!(relocate_directory(
+            &db,
+            Utf8Path::new("/photos"),
+            Utf8Path::new("/backup/photos")
+        )
+        .await
+        .is_err());
+        assert_eq!(
+            directories_within(&db, "/photos").await,
+            ["/photos", "/photos/2024"]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Preparing the database of the library when the application starts
+//
+// The schema of the database is managed by the migrations within the `migration`
+// crate, which are applied on startup so the database always matches the entities.
+// Before these migrations existed, the schema was created by sqlx with a single
+// `picture` table, so databases still in this form have their pictures copied
+// across to the current schema, with the original table kept as `legacy_picture`.
+//
+// A database which has had migrations applied from a newer version of the
+// application is not opened, since this version doesn't know how to use it and
+// could lose data by writing to it.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use camino::{Utf8Path, Utf8PathBuf};
+use itertools::Itertools;
+use migration::{MigrationName, Migrator, MigratorTrait, SchemaManager};
+use sea_orm::{
+    ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbErr, Statement,
+    TransactionTrait,
+};
+
+use crate::{get_parent_directory, APP_ID};
+
+const DATABASE_FILE: &str = "database.db";
+const LEGACY_TABLE: &str = "picture";
+const LEGACY_MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+const MIGRATIONS_TABLE: &str = "seaql_migrations";
+
+/// Copy the pictures from the sqlx schema, leaving any already within the current
+/// schema as they are. The directories of the pictures need to exist beforehand.
+///
+/// The sqlx schema required a rating, using `Zero` for pictures without one, which
+/// is now stored as no rating.
+const CONVERT_LEGACY: &str = r#"
+INSERT OR IGNORE INTO pictures (
+    id, directory_id, filename, raw_extension, short_hash, full_hash, capture_time,
+    rating, flag, hidden, selection, thumbnail
+)
+SELECT
+    picture.id, directories.id, picture.filename, picture.raw_extension,
+    picture.short_hash, picture.full_hash, picture.capture_time,
+    nullif(picture.rating, 'Zero'), picture.flag, coalesce(picture.hidden, 0), picture.selection, picture.thumbnail
+FROM picture JOIN directories ON directories.directory = picture.directory
+"#;
+
+const RETIRE_LEGACY: &str = r#"
+ALTER TABLE picture RENAME TO legacy_picture;
+DROP TABLE IF EXISTS _sqlx_migrations;
+"#;
+
+/// The directory containing the database of the library, along with its snapshots.
+pub fn data_directory() -> Result<Utf8PathBuf, Error> {
+    let mut path: Utf8PathBuf = dirs::data_local_dir()
+        .ok_or(anyhow!("Unable to find local data dir"))?
+        .try_into()?;
+    path.push(APP_ID);
+    Ok(path)
+}
+
+pub fn database_path() -> Result<Utf8PathBuf, Error> {
+    Ok(data_directory()?.join(DATABASE_FILE))
+}
+
+/// Connect to the database at the path, creating it where it doesn't exist.
+pub async fn connect(path: &Utf8Path) -> Result<DatabaseConnection, DbErr> {
+    let mut connection_options = ConnectOptions::new(format!("sqlite://{path}?mode=rwc"));
+    // The minimum number of connections is rather important. There are cases within the application where
+    // we have multiple connections open simultaneously to handle the streaming of data from the database
+    // while performing operations on the data. This doesn't work if we don't increase the minimum number
+    // of connections resulting in a lock on the connections.
+    connection_options.max_connections(20).min_connections(4);
+    tracing::debug!("Connection Options: {:?}", connection_options);
+    Database::connect(connection_options).await
+}
+
+/// What was changed while preparing the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatabaseReport {
+    /// The names of the migrations applied
+    pub applied: Vec<String>,
+    /// The number of pictures converted from the legacy schema, where the database
+    /// used the legacy schema.
+    pub converted: Option<u64>,
+}
+
+impl DatabaseReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.converted.is_none()
+    }
+}
+
+impl std::fmt::Display for DatabaseReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.applied.is_empty() {
+            write!(
+                f,
+                "Updated the library to the latest version, applying {}.",
+                self.applied.iter().join(", ")
+            )?;
+        }
+        if let Some(converted) = self.converted {
+            let separator = if self.applied.is_empty() { "" } else { " " };
+            write!(
+                f,
+                "{separator}Converted {converted} pictures from the previous format of the library."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DatabaseError {
+    /// The database couldn't be opened or updated
+    OpenFailed(Arc<anyhow::Error>),
+    /// The database has migrations from a newer version of the application
+    NewerSchema(Vec<String>),
+}
+
+impl From<anyhow::Error> for DatabaseError {
+    fn from(error: anyhow::Error) -> Self {
+        DatabaseError::OpenFailed(Arc::new(error))
+    }
+}
+
+impl From<sea_orm::DbErr> for DatabaseError {
+    fn from(error: sea_orm::DbErr) -> Self {
+        DatabaseError::OpenFailed(Arc::new(error.into()))
+    }
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::OpenFailed(e) => write!(f, "Unable to open the library: {e}"),
+            DatabaseError::NewerSchema(unknown) => write!(
+                f,
+                "The library was created by a newer version of the application, which \
+                 applied {}. Update the application to open it.",
+                unknown.iter().join(", ")
+            ),
+        }
+    }
+}
+
+/// The names of the migrations which have been applied to the database.
+async fn applied_migrations(db: &DatabaseConnection) -> Result<Vec<String>, Error> {
+    let manager = SchemaManager::new(db);
+    if !manager.has_table(MIGRATIONS_TABLE).await? {
+        return Ok(vec![]);
+    }
+    let statement = Statement::from_string(
+        db.get_database_backend(),
+        format!("SELECT version FROM {MIGRATIONS_TABLE} ORDER BY version"),
+    );
+    db.query_all(statement)
+        .await?
+        .iter()
+        .map(|row| Ok(row.try_get::<String>("", "version")?))
+        .collect()
+}
+
+/// Whether the database was created by the sqlx schema and hasn't been converted.
+async fn is_legacy(db: &DatabaseConnection) -> Result<bool, Error> {
+    let manager = SchemaManager::new(db);
+    Ok(
+        manager.has_table(LEGACY_TABLE).await?
+            && manager.has_table(LEGACY_MIGRATIONS_TABLE).await?,
+    )
+}
+
+/// Copy the pictures from the legacy schema into the current schema.
+///
+/// The directories of the pictures weren't recorded by the legacy schema, so these
+/// are created first for the pictures to be placed within.
+#[tracing::instrument(name = "Converting legacy database", skip(db))]
+async fn convert_legacy(db: &DatabaseConnection) -> Result<u64, Error> {
+    let txn = db.begin().await?;
+    let statement = Statement::from_string(
+        txn.get_database_backend(),
+        format!("SELECT DISTINCT directory FROM {LEGACY_TABLE}"),
+    );
+    let directories: Vec<String> = txn
+        .query_all(statement)
+        .await?
+        .iter()
+        .map(|row| row.try_get::<String>("", "directory"))
+        .collect::<Result<_, _>>()?;
+    for directory in directories.into_iter() {
+        if get_parent_directory(&txn, &Utf8PathBuf::from(&directory))
+            .await?
+            .is_none()
+        {
+            tracing::warn!("Unable to convert the pictures within {directory}");
+        }
+    }
+
+    let converted = txn
+        .execute_unprepared(CONVERT_LEGACY)
+        .await?
+        .rows_affected();
+    txn.execute_unprepared(RETIRE_LEGACY).await?;
+    txn.commit().await?;
+    Ok(converted)
+}
+
+/// Bring the database up to date with the current schema.
+///
+/// This refuses to update a database from a newer version of the application, and
+/// converts a database using the legacy schema once the current schema is in place.
+#[tracing::instrument(name = "Preparing database", skip(db))]
+pub async fn prepare_database(db: &DatabaseConnection) -> Result<DatabaseReport, DatabaseError> {
+    let known: Vec<String> = Migrator::migrations()
+        .iter()
+        .map(|m| m.name().to_string())
+        .collect();
+    let applied = applied_migrations(db).await?;
+    let unknown: Vec<String> = applied
+        .iter()
+        .filter(|m| !known.contains(m))
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(DatabaseError::NewerSchema(unknown));
+    }
+
+    let legacy = is_legacy(db).await?;
+    let pending: Vec<String> = known.into_iter().filter(|m| !applied.contains(m)).collect();
+    if !pending.is_empty() {
+        tracing::info!("Applying migrations {pending:?}");
+        Migrator::up(db, None)
+            .await
+            .map_err(|e| anyhow!("Unable to update the library: {e}"))?;
+    }
+
+    let converted = if legacy {
+        Some(convert_legacy(db).await?)
+    } else {
+        None
+    };
+    Ok(DatabaseReport {
+        applied: pending,
+        converted,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use entity::{picture, Rating};
+    use sea_orm::{EntityTrait, QueryOrder};
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// The schema created by sqlx, before the migrations existed.
+    const LEGACY_SCHEMA: &str = r#"
+    CREATE TABLE _sqlx_migrations (version BIGINT PRIMARY KEY);
+    CREATE TABLE picture (
+        id BLOB NOT NULL PRIMARY KEY,
+        directory TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        raw_extension TEXT,
+        short_hash BLOB,
+        full_hash BLOB,
+        capture_time DATETIME,
+        rating TEXT NOT NULL,
+        flag TEXT,
+        hidden INTEGER,
+        selection TEXT NOT NULL,
+        thumbnail BLOB
+    );
+    "#;
+
+    #[tokio::test]
+    async fn test_convert_legacy() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        db.execute_unprepared(LEGACY_SCHEMA).await.unwrap();
+        for (filename, rating) in [("a.jpg", "Zero"), ("b.jpg", "Three")] {
+            let statement = Statement::from_sql_and_values(
+                db.get_database_backend(),
+                "INSERT INTO picture (id, directory, filename, rating, selection) \
+                 VALUES (?, '/photos', ?, ?, 'Ordinary')",
+                [Uuid::new_v4().into(), filename.into(), rating.into()],
+            );
+            db.execute(statement).await.unwrap();
+        }
+
+        let report = prepare_database(&db).await.unwrap();
+        assert_eq!(report.converted, Some(2));
+
+        // The pictures are loaded as models, with the rating of zero cleared
+        let ratings: Vec<(String, Option<Rating>)> = picture::Entity::find()
+            .order_by_asc(picture::Column::Filename)
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.filename, p.rating))
+            .collect();
+        assert_eq!(
+            ratings,
+            [
+                ("a.jpg".to_string(), None),
+                ("b.jpg".to_string(), Some(Rating::Three))
+            ]
+        );
+        assert!(!is_legacy(&db).await.unwrap());
+    }
+}
@@ -1,11 +1,26 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
 use camino::{Utf8Path, Utf8PathBuf};
-use iced::widget::{button, column, container, horizontal_space, row, scrollable, text};
-use iced::{Color, Element, Task, Theme};
+use iced::widget::{
+    button, column, container, horizontal_space, row, scrollable, text, toggler, Space,
+};
+use iced::{Color, Element, Length, Task, Theme};
 use itertools::Itertools;
 use sea_orm::DatabaseConnection;
+use uuid::Uuid;
 
-use crate::data::{query_directories, query_directory_pictures};
-use crate::import::{find_new_images, import};
+use crate::backup::{automatic_snapshot, SnapshotReason};
+use crate::data::{
+    query_directories, query_directory_counts, query_directory_pictures, relocate_directory,
+    set_directory_expanded,
+};
+use crate::import::{
+    execute_import, find_new_images, plan_import, ImportError, ImportPlan, ImportReport,
+    ImportStructure,
+};
+use crate::settings::{BackupSettings, ImportMode, Settings};
 use crate::thumbnail::ThumbnailMessage;
 use crate::{DirectoryDataDB, Message};
 
@@ -22,7 +37,50 @@ pub struct DirectoryView {
     pub directories: Vec<DirectoryDataDB>,
     pub selected: Active,
     pub database: DatabaseConnection,
+    /// The import waiting for confirmation from the user
+    pub import_plan: Option<ImportPlan>,
+    /// The outcome of the most recent import, until dismissed by the user
+    pub import_report: Option<ImportReport>,
+    /// The reason the import couldn't be planned, until dismissed by the user
+    pub import_error: Option<ImportError>,
+    /// The directory selected which can't be found, waiting for the user to locate it
+    pub missing: Option<DirectoryDataDB>,
+    /// The position of each directory within the directories
+    index: HashMap<Uuid, usize>,
+    /// The pictures directly within each directory, not including subdirectories
+    pub counts: HashMap<Uuid, DirectoryCounts>,
+    /// The pictures within each directory including all its subdirectories, which
+    /// are kept up to date with the directories and counts
+    totals: HashMap<Uuid, DirectoryCounts>,
+    /// Whether the pictures of all the subdirectories are shown with a directory
+    pub include_subfolders: bool,
+}
+
+/// The number of pictures within a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirectoryCounts {
+    pub total: u64,
+    pub picks: u64,
+}
+
+impl std::ops::AddAssign for DirectoryCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.total += other.total;
+        self.picks += other.picks;
+    }
+}
+
+/// A directory shown within the tree of directories
+struct TreeNode<'a> {
+    directory: &'a DirectoryDataDB,
+    // The path of the directory above, which the name of the directory is shown
+    // relative to
+    parent: Option<&'a Utf8Path>,
+    depth: u16,
+    // Whether the directory has subdirectories which can be expanded
+    branch: bool,
 }
+
 fn directory_style(theme: &Theme, status: button::Status) -> button::Style {
     let palette = theme.extended_palette();
 
@@ -48,11 +106,54 @@ pub enum DirectoryMessage {
     DirectoryAdd,
     /// The request to open the directory selection menu
     DirectoryImport,
+    /// The plan of the import, which is shown for review before importing, or None
+    /// where no directory was selected
+    ImportPlanned(Result<Option<ImportPlan>, ImportError>),
+    /// Execute the import plan that is being reviewed
+    ImportConfirm,
+    /// Whether the files are moved rather than copied by the import being reviewed
+    ImportMoveFiles(bool),
+    ImportCancel,
+    ImportFinished(Result<ImportReport, ImportError>),
+    ImportReportClose,
+    ImportErrorClose,
     QueryDirectories,
     UpdateDirectories(Vec<DirectoryDataDB>),
+    QueryCounts,
+    UpdateCounts(HashMap<Uuid, DirectoryCounts>),
     SelectDirectory(DirectoryDataDB),
+    /// Show or hide the subdirectories of the directory within the tree
+    ToggleExpanded(Uuid),
+    /// Whether selecting a directory shows the pictures of all its subdirectories
+    IncludeSubfolders(bool),
     DirectoryNext,
     DirectoryPrev,
+    /// Ask for the new location of the directory, which can't be found
+    LocateDirectory(Uuid),
+    /// Choose the new location of the missing directory
+    RelocateDirectory,
+    RelocateCancel,
+    /// The directories after relocating, along with the new location
+    Relocated(Result<(Vec<DirectoryDataDB>, Utf8PathBuf), DirectoryError>),
+}
+
+#[derive(Debug, Clone)]
+pub enum DirectoryError {
+    RelocateFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for DirectoryError {
+    fn from(error: anyhow::Error) -> Self {
+        DirectoryError::RelocateFailed(Arc::new(error))
+    }
+}
+
+impl std::fmt::Display for DirectoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DirectoryError::RelocateFailed(e) => write!(f, "Relocating directory failed: {e}"),
+        }
+    }
 }
 
 impl From<DirectoryMessage> for Message {
@@ -67,6 +168,14 @@ impl DirectoryView {
             directories: Default::default(),
             selected: Default::default(),
             database,
+            import_plan: None,
+            import_report: None,
+            import_error: None,
+            missing: None,
+            index: HashMap::new(),
+            counts: HashMap::new(),
+            totals: HashMap::new(),
+            include_subfolders: true,
         }
     }
     fn is_selected(&self, index: &usize) -> bool {
@@ -77,38 +186,152 @@ impl DirectoryView {
         }
     }
 
-    pub fn update(&mut self, message: DirectoryMessage) -> Task<Message> {
+    fn directory(&self, id: Uuid) -> Option<&DirectoryDataDB> {
+        self.index.get(&id).map(|index| &self.directories[*index])
+    }
+
+    /// Replace the directories, updating the index and totals to match.
+    fn set_directories(&mut self, directories: Vec<DirectoryDataDB>) {
+        self.directories = directories.into_iter().sorted().rev().collect();
+        self.index = self
+            .directories
+            .iter()
+            .enumerate()
+            .map(|(index, d)| (d.id, index))
+            .collect();
+        self.update_totals();
+    }
+
+    /// Sum the pictures within each directory and its subdirectories.
+    fn update_totals(&mut self) {
+        let mut totals = HashMap::with_capacity(self.directories.len());
+        for directory in self.directories.iter() {
+            self.sum_counts(directory.id, &mut totals);
+        }
+        self.totals = totals;
+    }
+
+    /// The pictures within the directory and its subdirectories, where the totals of
+    /// each directory are kept as they are found so each is only summed once.
+    fn sum_counts(&self, id: Uuid, totals: &mut HashMap<Uuid, DirectoryCounts>) -> DirectoryCounts {
+        if let Some(total) = totals.get(&id) {
+            return *total;
+        }
+        let mut total = self.counts.get(&id).copied().unwrap_or_default();
+        if let Some(directory) = self.directory(id) {
+            for child in directory.children.iter() {
+                total += self.sum_counts(*child, totals);
+            }
+        }
+        totals.insert(id, total);
+        total
+    }
+
+    /// Collect the ids of the directory and every directory beneath it.
+    fn subdirectories(&self, id: Uuid, ids: &mut Vec<Uuid>) {
+        ids.push(id);
+        if let Some(directory) = self.directory(id) {
+            for child in directory.children.iter() {
+                self.subdirectories(*child, ids);
+            }
+        }
+    }
+
+    /// The pictures within the directory, including all its subdirectories.
+    fn total_counts(&self, id: Uuid) -> DirectoryCounts {
+        self.totals.get(&id).copied().unwrap_or_default()
+    }
+
+    pub fn update(&mut self, message: DirectoryMessage, backup: &BackupSettings) -> Task<Message> {
         let database = self.database.clone();
+        let backup = backup.clone();
         match message {
             DirectoryMessage::DirectoryImport => Task::perform(
                 async move {
-                    let dir: Utf8PathBuf = rfd::AsyncFileDialog::new()
-                        .pick_folder()
-                        .await
-                        .expect("No Directory found")
+                    let Some(folder) = rfd::AsyncFileDialog::new().pick_folder().await else {
+                        return Ok(None);
+                    };
+                    let dir: Utf8PathBuf = folder
                         .path()
                         .to_str()
-                        .unwrap()
+                        .ok_or(anyhow!("Invalid UTF-8 path"))?
                         .into();
 
-                    import(&database, &dir).await.unwrap()
+                    // Check the import settings before looking at any files.
+                    let settings = Settings::load()?.import;
+                    let structure = ImportStructure::new(&settings)?;
+                    let mut plan = plan_import(&database, &dir, &structure).await?;
+                    plan.mode = settings.mode;
+                    Ok(Some(plan))
                 },
-                |_| DirectoryMessage::QueryDirectories,
+                DirectoryMessage::ImportPlanned,
             )
             .map(Message::Directory),
+            DirectoryMessage::ImportPlanned(Ok(plan)) => {
+                self.import_plan = plan;
+                Task::none()
+            }
+            DirectoryMessage::ImportPlanned(Err(e)) => {
+                tracing::error!("{e}");
+                self.import_error = Some(e);
+                Task::none()
+            }
+            DirectoryMessage::ImportConfirm => {
+                let Some(plan) = self.import_plan.take() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        automatic_snapshot(&database, SnapshotReason::Import, &backup).await;
+                        Ok(execute_import(&database, plan).await?)
+                    },
+                    DirectoryMessage::ImportFinished,
+                )
+                .map(Message::Directory)
+            }
+            DirectoryMessage::ImportMoveFiles(move_files) => {
+                if let Some(plan) = &mut self.import_plan {
+                    plan.mode = if move_files {
+                        ImportMode::Move
+                    } else {
+                        ImportMode::Copy
+                    };
+                }
+                Task::none()
+            }
+            DirectoryMessage::ImportCancel => {
+                self.import_plan = None;
+                Task::none()
+            }
+            DirectoryMessage::ImportFinished(result) => {
+                match result {
+                    Ok(report) => self.import_report = Some(report),
+                    Err(e) => tracing::error!("{e}"),
+                }
+                Task::done(DirectoryMessage::QueryDirectories).map(Message::Directory)
+            }
+            DirectoryMessage::ImportReportClose => {
+                self.import_report = None;
+                Task::none()
+            }
+            DirectoryMessage::ImportErrorClose => {
+                self.import_error = None;
+                Task::none()
+            }
             DirectoryMessage::DirectoryAdd => Task::perform(
                 async move {
                     let dir = rfd::AsyncFileDialog::new()
                         .pick_folder()
                         .await
-                        .expect("No Directory found")
+                        .ok_or(anyhow!("No directory selected"))?
                         .path()
                         .to_str()
-                        .unwrap()
+                        .ok_or(anyhow!("Invalid UTF-8 path"))?
                         .into();
-                    find_new_images(&database, &dir).await;
+                    automatic_snapshot(&database, SnapshotReason::Import, &backup).await;
+                    Ok(find_new_images(&database, &dir).await?)
                 },
-                |_| DirectoryMessage::QueryDirectories,
+                DirectoryMessage::ImportFinished,
             )
             .map(Message::Directory),
             DirectoryMessage::QueryDirectories => Task::perform(
@@ -118,31 +341,292 @@ impl DirectoryView {
             .map(Message::Directory),
             DirectoryMessage::UpdateDirectories(dirs) => {
                 tracing::debug!("Directories: {:?}", self.directories);
-                self.directories = dirs.into_iter().sorted().rev().collect();
+                self.set_directories(dirs);
+                Task::done(DirectoryMessage::QueryCounts).map(Message::Directory)
+            }
+            DirectoryMessage::QueryCounts => Task::perform(
+                async move { query_directory_counts(&database).await },
+                |result| match result {
+                    Ok(counts) => DirectoryMessage::UpdateCounts(counts).into(),
+                    Err(e) => {
+                        tracing::error!("Counting the pictures within directories failed: {e}");
+                        Message::Ignore
+                    }
+                },
+            ),
+            DirectoryMessage::UpdateCounts(counts) => {
+                self.counts = counts;
+                self.update_totals();
                 Task::none()
             }
+            DirectoryMessage::ToggleExpanded(id) => {
+                let Some(directory) = self
+                    .index
+                    .get(&id)
+                    .map(|index| &mut self.directories[*index])
+                else {
+                    return Task::none();
+                };
+                directory.expanded = !directory.expanded;
+                let expanded = directory.expanded;
+                Task::perform(
+                    async move { set_directory_expanded(&database, id, expanded).await },
+                    |result| {
+                        if let Err(e) = result {
+                            tracing::error!("Saving the expanded directory failed: {e}");
+                        }
+                        Message::Ignore
+                    },
+                )
+            }
+            DirectoryMessage::IncludeSubfolders(include) => {
+                self.include_subfolders = include;
+                match &self.selected {
+                    Active::Single(index) => Task::done(DirectoryMessage::SelectDirectory(
+                        self.directories[*index].clone(),
+                    ))
+                    .map(Message::Directory),
+                    _ => Task::none(),
+                }
+            }
             DirectoryMessage::SelectDirectory(dir) => {
-                self.selected =
-                    Active::Single(self.directories.iter().position(|d| d == &dir).unwrap());
+                // The drive containing the directory may have been mounted elsewhere
+                if !dir.directory.try_exists().unwrap_or(false) {
+                    tracing::warn!("Directory {} not found", dir.directory);
+                    self.missing = Some(dir);
+                    return Task::none();
+                }
+                let Some(index) = self.index.get(&dir.id) else {
+                    return Task::none();
+                };
+                self.selected = Active::Single(*index);
+                let mut ids = vec![];
+                if self.include_subfolders {
+                    self.subdirectories(dir.id, &mut ids);
+                } else {
+                    ids.push(dir.id);
+                }
                 Task::perform(
-                    async move { query_directory_pictures(&database, dir).await.unwrap() },
+                    async move { query_directory_pictures(&database, ids).await.unwrap() },
                     ThumbnailMessage::SetThumbnails,
                 )
                 .map(Message::Thumbnail)
             }
-            DirectoryMessage::DirectoryNext => todo!(),
-            DirectoryMessage::DirectoryPrev => todo!(),
+            DirectoryMessage::LocateDirectory(id) => {
+                self.missing = self.directory(id).cloned();
+                Task::none()
+            }
+            DirectoryMessage::RelocateDirectory => {
+                let Some(missing) = self.missing.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        let dir: Utf8PathBuf = rfd::AsyncFileDialog::new()
+                            .set_title(format!("Locate {}", missing.directory))
+                            .pick_folder()
+                            .await
+                            .ok_or(anyhow!("No directory selected"))?
+                            .path()
+                            .to_str()
+                            .ok_or(anyhow!("Invalid UTF-8 path"))?
+                            .into();
+                        relocate_directory(&database, &missing.directory, &dir).await?;
+                        Ok((query_directories(&database).await?, dir))
+                    },
+                    DirectoryMessage::Relocated,
+                )
+                .map(Message::Directory)
+            }
+            DirectoryMessage::RelocateCancel => {
+                self.missing = None;
+                Task::none()
+            }
+            DirectoryMessage::Relocated(Ok((dirs, location))) => {
+                self.missing = None;
+                self.set_directories(dirs);
+                match self.directories.iter().find(|d| d.directory == location) {
+                    Some(dir) => Task::done(DirectoryMessage::SelectDirectory(dir.clone()))
+                        .map(Message::Directory),
+                    None => Task::none(),
+                }
+            }
+            DirectoryMessage::Relocated(Err(e)) => {
+                tracing::error!("{e}");
+                Task::none()
+            }
+            DirectoryMessage::DirectoryNext => self.select_adjacent(true),
+            DirectoryMessage::DirectoryPrev => self.select_adjacent(false),
         }
     }
 
-    pub fn view(&self) -> Element<'_, Message> {
-        let values: Element<'_, Message> = column(
-            self.directories
-                .iter()
-                .enumerate()
-                .map(|(index, d)| DirectoryDataDB::view(d, self.is_selected(&index))),
+    /// The prompt to locate the missing directory.
+    pub fn missing_view(&self) -> Element<'_, Message> {
+        let Some(missing) = &self.missing else {
+            return column![].into();
+        };
+        container(
+            column![
+                text(format!(
+                    "The directory {} can't be found.",
+                    missing.directory
+                )),
+                text(
+                    "Where it has been moved, like a drive mounted at a different location, \
+                     choose the new location to update the directory, its subdirectories and \
+                     all the pictures within them."
+                ),
+                row![
+                    horizontal_space(),
+                    button(text("Cancel")).on_press(DirectoryMessage::RelocateCancel.into()),
+                    button(text("Locate")).on_press(DirectoryMessage::RelocateDirectory.into()),
+                ]
+                .spacing(10)
+            ]
+            .spacing(10),
         )
-        .into();
+        .style(container::rounded_box)
+        .padding(20)
+        .into()
+    }
+
+    /// Follow the directories with a single subdirectory and no pictures of their
+    /// own, which are shown along with the subdirectory as a single node.
+    fn compact<'a>(&'a self, mut directory: &'a DirectoryDataDB) -> &'a DirectoryDataDB {
+        while let [child] = directory.children.as_slice() {
+            if self.counts.contains_key(&directory.id) {
+                break;
+            }
+            match self.directory(*child) {
+                Some(child) => directory = child,
+                None => break,
+            }
+        }
+        directory
+    }
+
+    /// Add the node of the directory to the tree, followed by the nodes of its
+    /// subdirectories when it is expanded.
+    fn tree_nodes<'a>(
+        &'a self,
+        directory: &'a DirectoryDataDB,
+        parent: Option<&'a Utf8Path>,
+        depth: u16,
+        nodes: &mut Vec<TreeNode<'a>>,
+    ) {
+        let directory = self.compact(directory);
+        let children: Vec<&DirectoryDataDB> = directory
+            .children
+            .iter()
+            .filter_map(|id| self.directory(*id))
+            .sorted_by(|a, b| a.directory.cmp(&b.directory))
+            .collect();
+        nodes.push(TreeNode {
+            directory,
+            parent,
+            depth,
+            branch: !children.is_empty(),
+        });
+        if directory.expanded {
+            for child in children.into_iter() {
+                self.tree_nodes(child, Some(directory.directory.as_path()), depth + 1, nodes);
+            }
+        }
+    }
+
+    /// The directories shown within the tree, in the order they are shown.
+    fn tree(&self) -> Vec<TreeNode<'_>> {
+        // The directories whose parent isn't within the library are the roots of the tree
+        let mut nodes = vec![];
+        for root in self
+            .directories
+            .iter()
+            .filter(|d| d.parent_id.and_then(|id| self.directory(id)).is_none())
+            .sorted_by(|a, b| a.directory.cmp(&b.directory))
+        {
+            self.tree_nodes(root, None, 0, &mut nodes);
+        }
+        nodes
+    }
+
+    /// Select the directory shown next to the selected directory within the tree,
+    /// which is the first directory where none is selected.
+    fn select_adjacent(&self, forward: bool) -> Task<Message> {
+        let tree = self.tree();
+        let current = match &self.selected {
+            Active::Single(index) => {
+                let id = self.directories[*index].id;
+                tree.iter().position(|node| node.directory.id == id)
+            }
+            _ => None,
+        };
+        let next = match current {
+            Some(i) if forward => (i + 1).min(tree.len().saturating_sub(1)),
+            Some(i) => i.saturating_sub(1),
+            None => 0,
+        };
+        match tree.get(next) {
+            Some(node) if Some(next) != current => {
+                Task::done(DirectoryMessage::SelectDirectory(node.directory.clone()))
+                    .map(Message::Directory)
+            }
+            _ => Task::none(),
+        }
+    }
+
+    fn node_view<'a>(&'a self, node: TreeNode<'a>) -> Element<'a, Message> {
+        let TreeNode {
+            directory,
+            parent,
+            depth,
+            branch,
+        } = node;
+        // The nodes beneath the roots only show the part of the path below the parent
+        let name = parent
+            .and_then(|parent| directory.directory.strip_prefix(parent).ok())
+            .unwrap_or(directory.directory.as_path());
+        let toggle: Element<'a, Message> = if branch {
+            button(text(if directory.expanded { "▾" } else { "▸" }))
+                .on_press(DirectoryMessage::ToggleExpanded(directory.id).into())
+                .style(button::text)
+                .padding(0)
+                .width(20)
+                .into()
+        } else {
+            Space::with_width(20).into()
+        };
+        let counts = self.total_counts(directory.id);
+        let selected = self
+            .index
+            .get(&directory.id)
+            .is_some_and(|index| self.is_selected(index));
+        let message = if selected {
+            None
+        } else {
+            Some(DirectoryMessage::SelectDirectory(directory.clone()).into())
+        };
+        row![
+            Space::with_width(12 * depth),
+            toggle,
+            button(
+                row![
+                    text(name.as_str()).width(Length::Fill),
+                    text(format!("{} · {} picked", counts.total, counts.picks)).size(12),
+                ]
+                .spacing(5)
+                .align_y(iced::Alignment::Center)
+            )
+            .width(Length::Fill)
+            .on_press_maybe(message)
+            .style(directory_style),
+        ]
+        .align_y(iced::Alignment::Center)
+        .into()
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let nodes = self.tree().into_iter().map(|node| self.node_view(node));
+        let values: Element<'_, Message> = column(nodes).into();
 
         container(
             column![
@@ -154,6 +638,9 @@ impl DirectoryView {
                 ]
                 // // The row doesn't introspect size automatically, so we have to force it with the calls to width and height
                 .padding(10.),
+                toggler(self.include_subfolders)
+                    .label("Include subfolders")
+                    .on_toggle(|include| DirectoryMessage::IncludeSubfolders(include).into()),
                 container(
                     scrollable(values).direction(scrollable::Direction::Vertical(
                         scrollable::Scrollbar::new().width(2.).scroller_width(10.),
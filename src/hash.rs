@@ -0,0 +1,56 @@
+//! Content hashes of picture files
+//
+// The hashes are used to determine whether a file being imported already exists
+// within the library. The short hash only covers the start of the file, making it
+// cheap to compute for every candidate, while the full hash covers the entire file.
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+
+use anyhow::Error;
+use camino::Utf8Path;
+
+/// The number of bytes at the start of a file which are included in the short hash.
+const SHORT_HASH_BYTES: u64 = 64 * 1024;
+
+/// Compute the hash of the first [`SHORT_HASH_BYTES`] of a file.
+pub(crate) fn short_hash(filepath: &Utf8Path) -> Result<Vec<u8>, Error> {
+    let file = File::open(filepath)?;
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(
+        &mut BufReader::new(file).take(SHORT_HASH_BYTES),
+        &mut hasher,
+    )?;
+    Ok(hasher.finalize().as_bytes().to_vec())
+}
+
+/// Compute the hash of the entire contents of a file.
+pub(crate) fn full_hash(filepath: &Utf8Path) -> Result<Vec<u8>, Error> {
+    let file = File::open(filepath)?;
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut BufReader::new(file), &mut hasher)?;
+    Ok(hasher.finalize().as_bytes().to_vec())
+}
+
+/// Compare the contents of two files byte by byte.
+pub(crate) fn files_equal(first: &Utf8Path, second: &Utf8Path) -> Result<bool, Error> {
+    let mut first = BufReader::new(File::open(first)?);
+    let mut second = BufReader::new(File::open(second)?);
+    let mut buffer_first = [0u8; 8192];
+    let mut buffer_second = [0u8; 8192];
+    loop {
+        let count = first.read(&mut buffer_first)?;
+        if count == 0 {
+            // Both files have to finish at the same point
+            return Ok(second.read(&mut buffer_second)? == 0);
+        }
+        match second.read_exact(&mut buffer_second[..count]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        if buffer_first[..count] != buffer_second[..count] {
+            return Ok(false);
+        }
+    }
+}
@@ -1,45 +1,155 @@
 use std::collections::HashMap;
-use std::num::NonZero;
+use std::sync::Arc;
 
-use anyhow::Error;
-use camino::{Utf8Path, Utf8PathBuf};
-use futures_concurrency::prelude::*;
+use anyhow::{anyhow, Error};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
 use itertools::Itertools;
 use sea_orm::DatabaseConnection;
+use time::{PrimitiveDateTime, UtcOffset};
 use walkdir::WalkDir;
 
-use crate::data::{add_new_images, query_existing_pictures};
+use crate::data::{add_new_images, query_existing_pictures, PictureHashes};
 use crate::get_parent_directory;
-use crate::picture::{is_image, PictureData};
+use crate::hash::{files_equal, full_hash};
+use crate::picture::{is_image, PictureData, PictureFormat, PictureMetadata};
+use crate::settings::{ImportSettings, Settings};
+use crate::sidecar::{read_sidecar, sync_sidecars};
 
+mod plan;
+mod report;
+mod template;
+
+pub use plan::{execute_import, plan_import, ImportAction, ImportPlan, PlannedImport};
+pub use report::{ImportOutcome, ImportReport};
+use template::{PathTemplate, TemplateValues};
+
+#[derive(Debug, Clone)]
+pub enum ImportError {
+    ImportFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for ImportError {
+    fn from(error: anyhow::Error) -> Self {
+        ImportError::ImportFailed(Arc::new(error))
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::ImportFailed(e) => write!(f, "Import failed: {e}"),
+        }
+    }
+}
+
+/// The layout of the directories and files imported pictures are copied into.
 #[derive(Clone, Debug)]
-struct ImportStructure {
+pub(crate) struct ImportStructure {
     base_directory: Utf8PathBuf,
+    template: PathTemplate,
+    job: String,
+    /// The timezone of pictures without one in their exif data
+    timezone: Option<UtcOffset>,
 }
 
 impl ImportStructure {
-    fn build_filename(&self, image: &PictureData) -> Result<Utf8PathBuf, Error> {
-        let capture_time = image.capture_time.unwrap();
-        // Get the image capture date
-        Ok(format!(
-            // TODO: Provide the ability to configure the expansion string
-            "{base}/{year:04}/{year:04}-{month:02}-{day:02}/{filename}",
-            base = self.base_directory,
-            year = capture_time.year(),
-            month = capture_time.month() as u8,
-            day = capture_time.day(),
-            filename = image.filename()
-        )
-        .try_into()?)
+    /// Create the structure from the user's settings, checking the template is valid.
+    pub fn new(settings: &ImportSettings) -> Result<Self, Error> {
+        let base_directory = match &settings.base_directory {
+            Some(d) => d.clone(),
+            None => dirs::picture_dir()
+                .ok_or(anyhow!("Unable to find pictures directory"))?
+                .try_into()?,
+        };
+        Ok(Self {
+            base_directory,
+            template: settings.template.parse()?,
+            job: settings.job.clone(),
+            timezone: settings.timezone()?,
+        })
+    }
+
+    /// Determine the location of an image within the structure
+    ///
+    /// The fallback time is used when the image doesn't have a capture time, and the
+    /// sequence is the position of the image within the current import.
+    pub(crate) fn build_filename(
+        &self,
+        image: &PictureData,
+        fallback_time: Option<PrimitiveDateTime>,
+        sequence: usize,
+    ) -> Result<Utf8PathBuf, Error> {
+        let metadata = match &image.metadata {
+            Some(metadata) => metadata.clone(),
+            None if self.template.uses_camera() => PictureMetadata::from_file(&image.filepath)
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Unable to read camera from {}: {e}", image.filepath);
+                    PictureMetadata::default()
+                }),
+            None => PictureMetadata::default(),
+        };
+        let stem = image
+            .filepath
+            .file_stem()
+            .ok_or(anyhow!("No valid filename for {}", image.filepath))?;
+        let values = TemplateValues {
+            capture_time: image.capture_time.or(fallback_time),
+            make: metadata.make,
+            model: metadata.model,
+            lens: metadata.lens,
+            filename: stem,
+            sequence,
+            job: &self.job,
+        };
+        let relative = self.template.render(&values)?;
+        let mut path = self.base_directory.join(&relative);
+        // The values are sanitised, however nothing should ever be placed outside of
+        // the base directory
+        let normal = relative
+            .components()
+            .all(|c| matches!(c, Utf8Component::Normal(_)));
+        if !normal || !path.starts_with(&self.base_directory) {
+            return Err(anyhow!(
+                "The destination {relative} is outside of {}",
+                self.base_directory
+            ));
+        }
+        if let Some(ext) = image.filepath.extension() {
+            path.set_extension(ext);
+        }
+        Ok(path)
+    }
+
+    /// Whether the location of an image depends on its sequence number.
+    pub(crate) fn uses_sequence(&self) -> bool {
+        self.template.uses_sequence()
     }
 }
 
 impl Default for ImportStructure {
     fn default() -> Self {
-        Self {
-            base_directory: dirs::picture_dir().unwrap().try_into().unwrap(),
-        }
+        Self::new(&ImportSettings::default()).expect("Default import settings are valid")
+    }
+}
+
+/// Combine the files sharing a name into pictures.
+///
+/// A RAW file is attached to the processed file it accompanies, preferring a JPEG,
+/// rather than becoming a separate picture. A RAW file on its own is the picture.
+fn group_companions(paths: Vec<Utf8PathBuf>) -> Vec<PictureData> {
+    let (raw, mut processed): (Vec<_>, Vec<_>) = paths
+        .into_iter()
+        .partition(|p| PictureFormat::from_path(p).is_some_and(|f| f.is_raw()));
+    processed.sort_by_key(|p| PictureFormat::from_path(p).map(|f| f.priority()));
+
+    let mut raw = raw.into_iter();
+    let mut pictures: Vec<PictureData> = processed.into_iter().map(PictureData::from).collect();
+    if let Some(first) = pictures.first_mut() {
+        first.raw_extension = raw.next().and_then(|p| p.extension().map(str::to_owned));
     }
+    // Any remaining RAW files don't have anything to accompany, so are pictures of their own
+    pictures.extend(raw.map(PictureData::from));
+    pictures
 }
 
 /// Find all images nested within a directory.
@@ -54,34 +164,15 @@ pub fn find_directory_images(directory: &Utf8Path) -> Vec<PictureData> {
         .into_iter()
         .filter_map(|e| e.ok())
         .filter(is_image)
-        .map(|p| p.into_path().try_into().expect("Invalid UTF-8 path."))
+        .filter_map(|p| {
+            Utf8PathBuf::try_from(p.into_path())
+                .inspect_err(|e| tracing::warn!("Skipping invalid UTF-8 path: {e}"))
+                .ok()
+        })
         // Group by the filenames without extensions, grouping the raw and jpeg files together.
         .chunk_by(|p: &Utf8PathBuf| p.with_extension(""))
         .into_iter()
-        .filter_map(|(_key, group)| {
-            group.fold(None, |data: Option<PictureData>, path| {
-                match (data, path.extension()) {
-                    // When we haven't created the image we don't care what the filetype is
-                    (None, _) => Some(PictureData::from(path)),
-                    // We have created the PictureData from the RAW file so need to re-generate
-                    (Some(p), Some("jpg" | "JPG")) => {
-                        let mut output = PictureData::from(path);
-                        output.raw_extension = Some(
-                            p.filepath
-                                .extension()
-                                .expect("There must be a file extension set.")
-                                .to_owned(),
-                        );
-                        Some(output)
-                    }
-                    (Some(mut p), Some(e)) => {
-                        p.raw_extension = Some(e.to_owned());
-                        Some(p)
-                    }
-                    (Some(_), None) => unreachable!(),
-                }
-            })
-        })
+        .flat_map(|(_key, group)| group_companions(group.collect()))
         .map(|mut p: PictureData| {
             p.update_from_exif().unwrap_or_else(|e| {
                 tracing::warn!(
@@ -94,158 +185,156 @@ pub fn find_directory_images(directory: &Utf8Path) -> Vec<PictureData> {
         .collect()
 }
 
-/// Copy files from an existing location creating a new folder structure skipping existing files.
-///
-/// This performs a check for the existing files that are within the current database.
+/// A picture already within the library that could be a duplicate of an imported file.
+#[derive(Debug, Clone)]
+struct ExistingPicture {
+    filepath: Utf8PathBuf,
+    full_hash: Option<Vec<u8>>,
+}
+
+/// Lookup of the pictures already within the library.
 ///
-pub async fn import(db: &DatabaseConnection, directory: &Utf8PathBuf) -> Result<(), Error> {
-    // Load all existing pictures from the database. We want to do the checks within rust, rather than
-    // potentially having large numbers of database queries.
-    // The list of all the pictures that currently exist within the database.
-    // TODO: Remove image data from this view of the PictureData
-    let pictures_existing = query_existing_pictures(db, &Utf8PathBuf::from(""))
-        .await
-        .unwrap()
-        .into_iter()
-        .map(PictureData::from)
-        .collect::<Vec<_>>();
-
-    // To make the lookup process simpler, we first want to convert to a hashmap to make the
-    // act of looking up whether a picture already exists within the database a quick process.
-    // Currently this is only using the filename, that is the name given to the file by the camera
-    // as the lookup key, however, this lookup process will be improved over time.
-
-    // A complicating factor is the updating of exif metadata. If this gets updated then the files
-    // will not be exactly the same, however the part we are interested in--the image--will be the
-    // same.
-
-    // TODO: Use hashes to check for uniqueness within the database.
-    // Check the filename
-    // Check the capture time
-    // Full Method
-
-    // 0. Check the size of the files
-    //     This should just involve reading the metadata
-    // 1. Check the short hash of the files
-    //     This involves reading just the first n bytes of the new file. This should
-    //     be a very quick operation.
-    // 2. Check the long hash of the files
-    //     This involves just reading the new file, the hashes of the previous files
-    //     should already be computed.
-    // 3. Check the files are equal
-    //     Now we also need to read in the old file to check.
-    let hash_existing = tokio::task::spawn_blocking(move || {
-        let mut hash_existing: HashMap<String, Vec<PictureData>> = HashMap::new();
-        for picture in pictures_existing.into_iter() {
-            if let Some(f) = hash_existing.get_mut(&picture.filename()) {
-                f.push(picture);
-            } else {
-                hash_existing.insert(picture.filename(), vec![picture]);
+/// Pictures are keyed by their short hash so finding the candidate duplicates of a new
+/// file is a single lookup. Pictures added before hashes were computed don't have a
+/// short hash, so these fall back to being keyed on the filename.
+#[derive(Debug, Default)]
+struct LibraryIndex {
+    by_short_hash: HashMap<Vec<u8>, Vec<ExistingPicture>>,
+    unhashed_by_filename: HashMap<String, Vec<ExistingPicture>>,
+}
+
+impl LibraryIndex {
+    fn new(pictures: Vec<PictureHashes>) -> Self {
+        let mut index = Self::default();
+        for picture in pictures.into_iter() {
+            let existing = ExistingPicture {
+                filepath: picture.filepath(),
+                full_hash: picture.full_hash,
+            };
+            match picture.short_hash {
+                Some(h) => index.by_short_hash.entry(h).or_default().push(existing),
+                None => index
+                    .unhashed_by_filename
+                    .entry(picture.filename)
+                    .or_default()
+                    .push(existing),
             }
         }
-        hash_existing
-    })
-    .await
-    .expect("Issue unwrapping future");
+        index
+    }
 
-    // Determine whether the new images we are importing already exist within the database.
-    let new_images: Vec<_> = find_directory_images(directory)
-        .into_iter()
-        // TODO: Improve this filter beyond being very basic
-        .filter(|p| !hash_existing.contains_key(&p.filename()))
-        .collect();
-
-    let new_images = new_images
-        // Spawns a concurrent stream to
-        .into_co_stream()
-        .limit(NonZero::new(16))
-        .map(|mut image| {
-            let db_inner = db.clone();
-            async move {
-                // tracing::debug!("{:?}", &image);
-                // Create the directory structure
-                let structure = ImportStructure::default();
-
-                let new_path = structure
-                    .build_filename(&image)
-                    .expect("Unable to build filename");
-
-                let parent = new_path.parent();
-                tracing::debug!("Importing {} into {}", image.filepath, &new_path);
-
-                // Where the new path is the same as the old one we are actually adding the
-                // file rather than importing, so we can skip all the import steps.
-                if image.filepath != new_path {
-                    // Copy to new location
-
-                    // Firstly we have to be sure that the directory already exists we are
-                    // going to be copying to. This creates the entire directory structure
-                    // where it doesn't already exist.
-                    // Within tokio this is guaranteed not to fail in a race condition with
-                    // itself. https://docs.rs/tokio/latest/tokio/fs/fn.create_dir_all.html
-                    tokio::fs::create_dir_all(parent.unwrap())
-                        .await
-                        .expect("Unable to create directory.");
-
-                    // Where there is a file that already exists within the new locaton,
-                    // we don't want to overwrite it, which does result in the contents
-                    // of the file being removed.
-                    if new_path.try_exists().expect("Error checking path exists") {
-                        tracing::warn!("File {} already exists, not copying", &new_path);
-                    } else {
-                        tokio::fs::copy(&image.filepath, &new_path)
-                            .await
-                            .expect("Unable to copy file");
-                        // Also copy across the raw file
-                        if let Some(ref ext) = image.raw_extension {
-                            tokio::fs::copy(
-                                &image.filepath.with_extension(ext),
-                                &new_path.with_extension(ext),
-                            )
-                            .await
-                            .expect("Unable to copy file");
-                        }
-                    }
-                }
+    /// Find the picture within the library with identical contents to the candidate.
+    ///
+    /// The candidate must already have its hashes computed. The checks are staged
+    /// from cheapest to most expensive, only moving onto the next stage when all the
+    /// previous stages match.
+    ///
+    /// 0. Check the size of the files
+    ///    This should just involve reading the metadata
+    /// 1. Check the short hash of the files
+    ///    This is already computed for the new file and stored for the existing ones.
+    /// 2. Check the full hash of the files
+    ///    The hashes of the existing files should already be computed, falling back
+    ///    to reading the existing file for those imported before hashing.
+    /// 3. Check the files are equal
+    ///    Now we also need to read in the old file to check.
+    fn find_duplicate(&self, candidate: &PictureData) -> Result<Option<Utf8PathBuf>, Error> {
+        let (Some(short), Some(full)) = (&candidate.short_hash, &candidate.full_hash) else {
+            return Err(anyhow!(
+                "Hashes have not been computed for {}",
+                candidate.filepath
+            ));
+        };
+        let size = candidate.filepath.metadata()?.len();
 
-                image.filepath = new_path;
-                image.directory_id = get_parent_directory(&db_inner, &image.directory().into())
-                    .await
-                    .expect("");
-                image
+        let matches = self.by_short_hash.get(short).into_iter().flatten().chain(
+            self.unhashed_by_filename
+                .get(&candidate.filename())
+                .into_iter()
+                .flatten(),
+        );
+        for existing in matches {
+            // Where the existing file is missing it can't be the same file.
+            match existing.filepath.metadata() {
+                Ok(m) if m.len() == size => {}
+                _ => continue,
             }
-        })
-        .collect()
-        .await;
-
-    // Create entry in the database / import
-    add_new_images(db, new_images).await.unwrap();
-    Ok(())
+            let existing_full = match &existing.full_hash {
+                Some(h) => h.clone(),
+                None => full_hash(&existing.filepath)?,
+            };
+            if &existing_full != full {
+                continue;
+            }
+            if files_equal(&candidate.filepath, &existing.filepath)? {
+                return Ok(Some(existing.filepath.clone()));
+            }
+        }
+        Ok(None)
+    }
 }
 
-pub async fn find_new_images(db: &DatabaseConnection, directory: &Utf8PathBuf) {
-    let existing_pictures = query_existing_pictures(db, directory).await.unwrap();
+/// Add the images within a directory to the database, leaving them in place.
+///
+/// Images which are already within the database are skipped.
+pub async fn find_new_images(
+    db: &DatabaseConnection,
+    directory: &Utf8PathBuf,
+) -> Result<ImportReport, Error> {
+    let existing_pictures = query_existing_pictures(db, directory).await?;
 
     tracing::info!(
         "Found {} existing files within directory",
         existing_pictures.len()
     );
 
+    // Changes made by other tools to the pictures already within the library are
+    // picked up from their sidecars.
+    let settings = Settings::load()?;
+    let timezone = settings.import.timezone()?;
+    let naming = settings.sidecar.naming;
+    match sync_sidecars(db, directory, &settings.sidecar).await {
+        Ok(count) => tracing::info!("Updated {count} pictures from their sidecars"),
+        Err(e) => tracing::warn!("Unable to synchronise sidecars: {e}"),
+    }
+
     let dir = directory.clone();
     let images: Vec<_> = tokio::task::spawn_blocking(move || {
         find_directory_images(&dir)
             .into_iter()
             .filter(|p| !existing_pictures.contains(&p.filepath))
+            .map(|mut p| {
+                p.fill_offset(timezone);
+                match read_sidecar(&p.filepath, naming) {
+                    Ok(Some(values)) => values.apply(&mut p),
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("Unable to read sidecar of {}: {e}", p.filepath),
+                }
+                p
+            })
             .collect()
     })
-    .await
-    .unwrap();
+    .await?;
 
+    let mut report = ImportReport::default();
     if images.is_empty() {
         tracing::info!("No new images found in directory {directory}");
-        return;
+        return Ok(report);
     }
     tracing::info!("Adding {} new images to the database.", images.len());
 
-    add_new_images(db, images).await.unwrap();
+    let mut new_images = vec![];
+    for mut image in images.into_iter() {
+        match get_parent_directory(db, &image.directory().into()).await {
+            Ok(directory_id) => {
+                image.directory_id = directory_id;
+                report.push(image.filepath.clone(), ImportOutcome::Imported);
+                new_images.push(image);
+            }
+            Err(e) => report.push(image.filepath, ImportOutcome::Failed(e.to_string())),
+        }
+    }
+
+    add_new_images(db, new_images).await?;
+    Ok(report)
 }
@@ -0,0 +1,533 @@
+//! Planning of an import before any files are touched
+//
+// An import is split into two phases. The planning phase works out where every
+// picture is going to be placed and whether it already exists within the library,
+// without modifying anything on disk or in the database. Once the plan has been
+// reviewed, the same plan is executed to copy the files and create the database entries.
+
+use std::collections::HashSet;
+use std::num::NonZero;
+
+use anyhow::{anyhow, Error};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_concurrency::prelude::*;
+use iced::widget::{button, column, container, horizontal_space, row, scrollable, text, toggler};
+use iced::{Color, Element, Length};
+use rayon::prelude::*;
+use sea_orm::DatabaseConnection;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use super::{find_directory_images, ImportOutcome, ImportReport, ImportStructure, LibraryIndex};
+use crate::data::{add_new_images, query_picture_hashes};
+use crate::directory::DirectoryMessage;
+use crate::get_parent_directory;
+use crate::hash::full_hash;
+use crate::picture::PictureData;
+use crate::settings::{ImportMode, SidecarNaming};
+use crate::sidecar::sidecar_path;
+use crate::Message;
+
+/// What will happen to a file when the plan is executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportAction {
+    /// The picture will be copied or moved into the library
+    New,
+    /// The picture already exists within the library at the path
+    Duplicate(Utf8PathBuf),
+    /// A different file already exists at the destination, so the picture is not copied
+    Collision,
+    /// The picture couldn't be read, so isn't imported
+    Failed(String),
+}
+
+/// A single picture within an [`ImportPlan`].
+#[derive(Debug, Clone)]
+pub struct PlannedImport {
+    /// The picture at its source location, including the hashes of the contents
+    pub picture: PictureData,
+    pub destination: Utf8PathBuf,
+    pub action: ImportAction,
+    /// The time used in place of the capture time to determine the destination, for
+    /// pictures which don't have the capture time in their exif data.
+    pub fallback_time: Option<PrimitiveDateTime>,
+}
+
+impl PlannedImport {
+    pub fn source(&self) -> &Utf8PathBuf {
+        &self.picture.filepath
+    }
+
+    /// The RAW file accompanying the picture, which is copied alongside it.
+    pub fn raw_source(&self) -> Option<Utf8PathBuf> {
+        self.picture
+            .raw_extension
+            .as_ref()
+            .map(|ext| self.source().with_extension(ext))
+    }
+
+    pub fn raw_destination(&self) -> Option<Utf8PathBuf> {
+        self.picture
+            .raw_extension
+            .as_ref()
+            .map(|ext| self.destination.with_extension(ext))
+    }
+
+    /// The sidecars of the picture along with where they are copied to, which are
+    /// carried along whichever way they are named.
+    pub fn sidecars(&self) -> Vec<(Utf8PathBuf, Utf8PathBuf)> {
+        SidecarNaming::ALL
+            .into_iter()
+            .map(|naming| {
+                (
+                    sidecar_path(self.source(), naming),
+                    sidecar_path(&self.destination, naming),
+                )
+            })
+            .filter(|(source, _)| source.exists())
+            .collect()
+    }
+
+    /// Whether the picture is being added in place rather than copied.
+    pub fn in_place(&self) -> bool {
+        self.source() == &self.destination
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let (status, color) = match &self.action {
+            ImportAction::New if self.fallback_time.is_some() => (
+                "New, no exif date".to_string(),
+                Color::from_rgb(0.9, 0.8, 0.3),
+            ),
+            ImportAction::New => ("New".to_string(), Color::from_rgb(0.4, 0.8, 0.4)),
+            ImportAction::Duplicate(existing) => (
+                format!("Duplicate of {existing}"),
+                Color::from_rgb(0.6, 0.6, 0.6),
+            ),
+            ImportAction::Collision => ("Collision".to_string(), Color::from_rgb(0.9, 0.4, 0.3)),
+            ImportAction::Failed(reason) => {
+                (format!("Failed: {reason}"), Color::from_rgb(0.9, 0.4, 0.3))
+            }
+        };
+        let raw = self
+            .picture
+            .raw_extension
+            .as_ref()
+            .map(|ext| format!("+ {ext}"))
+            .unwrap_or_default();
+        row![
+            text(self.picture.filename()).width(Length::FillPortion(2)),
+            text(raw).width(60),
+            text(self.destination.as_str()).width(Length::FillPortion(4)),
+            text(status).color(color).width(Length::FillPortion(2)),
+        ]
+        .spacing(10)
+        .into()
+    }
+}
+
+/// The reviewable plan of an import.
+#[derive(Debug, Clone, Default)]
+pub struct ImportPlan {
+    pub source_directory: Utf8PathBuf,
+    pub items: Vec<PlannedImport>,
+    /// Whether the source files are kept or removed once imported
+    pub mode: ImportMode,
+}
+
+impl ImportPlan {
+    fn count(&self, predicate: impl Fn(&ImportAction) -> bool) -> usize {
+        self.items.iter().filter(|i| predicate(&i.action)).count()
+    }
+
+    pub fn new_count(&self) -> usize {
+        self.count(|a| a == &ImportAction::New)
+    }
+
+    pub fn duplicate_count(&self) -> usize {
+        self.count(|a| matches!(a, ImportAction::Duplicate(_)))
+    }
+
+    pub fn collision_count(&self) -> usize {
+        self.count(|a| a == &ImportAction::Collision)
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.count(|a| matches!(a, ImportAction::Failed(_)))
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let summary = text(format!(
+            "Importing from {}: {} new, {} duplicates, {} collisions, {} unreadable",
+            self.source_directory,
+            self.new_count(),
+            self.duplicate_count(),
+            self.collision_count(),
+            self.failed_count(),
+        ));
+        let items = scrollable(column(self.items.iter().map(PlannedImport::view)).spacing(4))
+            .direction(scrollable::Direction::Vertical(
+                scrollable::Scrollbar::new().width(2.).scroller_width(10.),
+            ))
+            .height(Length::Fill);
+        let confirm: Option<Message> =
+            (self.new_count() > 0).then_some(DirectoryMessage::ImportConfirm.into());
+        container(
+            column![
+                summary,
+                items,
+                row![
+                    toggler(self.mode == ImportMode::Move)
+                        .label("Move files")
+                        .on_toggle(|m| DirectoryMessage::ImportMoveFiles(m).into()),
+                    horizontal_space(),
+                    button(text("Cancel")).on_press(DirectoryMessage::ImportCancel.into()),
+                    button(text("Import")).on_press_maybe(confirm),
+                ]
+                .spacing(10)
+            ]
+            .spacing(10),
+        )
+        .style(container::rounded_box)
+        .padding(20)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+    }
+}
+
+/// Create the plan for importing all the pictures within a directory.
+///
+/// This doesn't modify anything on disk or within the database.
+pub async fn plan_import(
+    db: &DatabaseConnection,
+    directory: &Utf8PathBuf,
+    structure: &ImportStructure,
+) -> Result<ImportPlan, Error> {
+    // Load the hashes of all existing pictures from the database. We want to do the
+    // checks within rust, rather than potentially having large numbers of database queries.
+    let library = LibraryIndex::new(query_picture_hashes(db).await?);
+
+    let dir = directory.clone();
+    let structure = structure.clone();
+    let mut items: Vec<PlannedImport> = tokio::task::spawn_blocking(move || {
+        find_directory_images(&dir)
+            .into_par_iter()
+            .enumerate()
+            // Hashing requires reading every file in full, so is spread across all the cores.
+            // The sequence numbers of the import start from 1
+            .map(|(index, image)| plan_picture(&library, &structure, image, index + 1))
+            .collect()
+    })
+    .await?;
+
+    // Where there is a file that already exists within the new location, we don't want
+    // to overwrite it. Since we know the contents differ from anything in the library,
+    // this is a different picture with the same name. The same applies to pictures
+    // within this import that would be given the same destination.
+    let mut destinations = HashSet::new();
+    for item in items
+        .iter_mut()
+        .filter(|i| i.action == ImportAction::New && !i.in_place())
+    {
+        item.action = match destination_exists(item) {
+            Ok(exists) if exists || !destinations.insert(item.destination.clone()) => {
+                ImportAction::Collision
+            }
+            Ok(_) => ImportAction::New,
+            Err(e) => ImportAction::Failed(format!("Unable to check the destination: {e}")),
+        };
+    }
+
+    Ok(ImportPlan {
+        source_directory: directory.clone(),
+        items,
+        mode: ImportMode::default(),
+    })
+}
+
+/// Whether a file already exists at the destination of the picture or its RAW file.
+fn destination_exists(item: &PlannedImport) -> Result<bool, Error> {
+    if item.destination.try_exists()? {
+        return Ok(true);
+    }
+    Ok(item
+        .raw_destination()
+        .map(|p| p.try_exists())
+        .transpose()?
+        .unwrap_or(false))
+}
+
+/// Determine what will happen to a single picture when importing.
+///
+/// Problems reading the picture are recorded within the plan, rather than stopping the
+/// entire import.
+fn plan_picture(
+    library: &LibraryIndex,
+    structure: &ImportStructure,
+    mut image: PictureData,
+    sequence: usize,
+) -> PlannedImport {
+    let mut item = PlannedImport {
+        destination: image.filepath.clone(),
+        action: ImportAction::New,
+        fallback_time: None,
+        picture: image.clone(),
+    };
+    if let Err(e) = image.update_hashes() {
+        tracing::warn!("Unable to hash {}, got error {e}", image.filepath);
+        item.action = ImportAction::Failed(format!("Unable to read file: {e}"));
+        return item;
+    }
+    image.fill_offset(structure.timezone);
+    // Without a capture time the modification time of the file is the best
+    // approximation we have for where the picture belongs.
+    if image.capture_time.is_none() {
+        match modified_time(&image.filepath) {
+            Ok(time) => item.fallback_time = Some(time),
+            Err(e) => tracing::warn!("Unable to read modified time of {}: {e}", image.filepath),
+        }
+    }
+    item.destination = match structure.build_filename(&image, item.fallback_time, sequence) {
+        Ok(d) => d,
+        Err(e) => {
+            item.action = ImportAction::Failed(format!("Unable to build filename: {e}"));
+            return item;
+        }
+    };
+    item.action = match library.find_duplicate(&image) {
+        Ok(Some(existing)) => ImportAction::Duplicate(existing),
+        Ok(None) => ImportAction::New,
+        Err(e) => ImportAction::Failed(format!("Unable to check for duplicates: {e}")),
+    };
+    item.picture = image;
+    item
+}
+
+/// The time the file was last modified, as a UTC time.
+fn modified_time(filepath: &Utf8Path) -> Result<PrimitiveDateTime, Error> {
+    let modified = OffsetDateTime::from(filepath.metadata()?.modified()?);
+    Ok(PrimitiveDateTime::new(modified.date(), modified.time()))
+}
+
+/// Compute the full hash of a file without blocking the runtime.
+async fn hash_file(filepath: &Utf8Path) -> Result<Vec<u8>, Error> {
+    let filepath = filepath.to_owned();
+    tokio::task::spawn_blocking(move || full_hash(&filepath)).await?
+}
+
+/// Copy a file, checking the contents of the copy match the expected hash.
+///
+/// Where the copy doesn't match, it is removed.
+async fn copy_verified(
+    source: &Utf8Path,
+    destination: &Utf8Path,
+    expected: &[u8],
+) -> Result<(), Error> {
+    // The plan may have been created a while ago, so check again we are
+    // not going to overwrite anything.
+    if destination.try_exists()? {
+        return Err(anyhow!("File {destination} already exists, not copying"));
+    }
+    tokio::fs::copy(source, destination).await?;
+    if hash_file(destination).await? != expected {
+        tokio::fs::remove_file(destination)
+            .await
+            .unwrap_or_else(|e| tracing::error!("Unable to remove {destination}: {e}"));
+        return Err(anyhow!(
+            "Checksum of {destination} doesn't match {source}, source kept"
+        ));
+    }
+    Ok(())
+}
+
+/// Copy a single picture and its RAW companion into the library.
+///
+/// The copies are checked against the checksums of the source files, and where any
+/// part of the copy fails, the files that have already been copied are removed so the
+/// picture is not left half imported.
+async fn copy_picture(db: &DatabaseConnection, item: &PlannedImport) -> Result<PictureData, Error> {
+    tracing::debug!("Importing {} into {}", item.source(), &item.destination);
+
+    // Where the new path is the same as the old one we are actually adding the
+    // file rather than importing, so we can skip all the import steps.
+    if !item.in_place() {
+        // The duplicate check of the plan relies on the hash, so we can't import a file
+        // that has been modified since the plan was created.
+        let source_hash = hash_file(item.source()).await?;
+        if Some(&source_hash) != item.picture.full_hash.as_ref() {
+            return Err(anyhow!(
+                "{} has changed since the import was planned",
+                item.source()
+            ));
+        }
+
+        // Firstly we have to be sure that the directory already exists we are
+        // going to be copying to. This creates the entire directory structure
+        // where it doesn't already exist.
+        // Within tokio this is guaranteed not to fail in a race condition with
+        // itself. https://docs.rs/tokio/latest/tokio/fs/fn.create_dir_all.html
+        let parent = item
+            .destination
+            .parent()
+            .ok_or(anyhow!("No parent directory of {}", item.destination))?;
+        tokio::fs::create_dir_all(parent).await?;
+
+        copy_verified(item.source(), &item.destination, &source_hash).await?;
+        // Also copy across the raw file
+        if let (Some(raw_source), Some(raw_destination)) =
+            (item.raw_source(), item.raw_destination())
+        {
+            let raw_copy = match hash_file(&raw_source).await {
+                Ok(raw_hash) => copy_verified(&raw_source, &raw_destination, &raw_hash).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = raw_copy {
+                tokio::fs::remove_file(&item.destination)
+                    .await
+                    .unwrap_or_else(|e| tracing::error!("Unable to remove partial import: {e}"));
+                return Err(e);
+            }
+        }
+        if let Err(e) = copy_sidecars(item).await {
+            for path in std::iter::once(item.destination.clone()).chain(item.raw_destination()) {
+                tokio::fs::remove_file(&path)
+                    .await
+                    .unwrap_or_else(|e| tracing::error!("Unable to remove partial import: {e}"));
+            }
+            return Err(e);
+        }
+    }
+
+    let mut image = item.picture.clone();
+    image.filepath = item.destination.clone();
+    image.directory_id = get_parent_directory(db, &image.directory().into()).await?;
+    Ok(image)
+}
+
+/// Copy the sidecars of a picture alongside it, removing those already copied where
+/// one of them can't be.
+async fn copy_sidecars(item: &PlannedImport) -> Result<(), Error> {
+    let sidecars = item.sidecars();
+    for (index, (source, destination)) in sidecars.iter().enumerate() {
+        let copied = match tokio::fs::try_exists(destination).await {
+            Ok(false) => tokio::fs::copy(source, destination)
+                .await
+                .map_err(|e| anyhow!("Unable to copy {source}: {e}")),
+            Ok(true) => Err(anyhow!("File {destination} already exists, not copying")),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = copied {
+            for (_, destination) in sidecars.iter().take(index) {
+                tokio::fs::remove_file(destination)
+                    .await
+                    .unwrap_or_else(|e| tracing::error!("Unable to remove partial import: {e}"));
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Remove the source files of a picture that has been moved into the library,
+/// including its RAW file and sidecars.
+async fn remove_source(sources: Vec<Utf8PathBuf>) -> Result<(), Error> {
+    for path in sources.into_iter() {
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| anyhow!("Unable to remove {path}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Copy the new pictures of a plan into the library and add them to the database.
+///
+/// Failing to import a single picture doesn't stop the import, with the outcome for
+/// each picture in the plan recorded within the report. When moving pictures, the
+/// source files are only removed once every picture has been verified and recorded
+/// within the database.
+pub async fn execute_import(
+    db: &DatabaseConnection,
+    plan: ImportPlan,
+) -> Result<ImportReport, Error> {
+    let mode = plan.mode;
+    let results: Vec<(PlannedImport, Option<Result<PictureData, Error>>)> = plan
+        .items
+        // Spawns a concurrent stream to
+        .into_co_stream()
+        .limit(NonZero::new(16))
+        .map(|item| {
+            let db_inner = db.clone();
+            async move {
+                let result = if item.action == ImportAction::New {
+                    Some(copy_picture(&db_inner, &item).await)
+                } else {
+                    None
+                };
+                (item, result)
+            }
+        })
+        .collect()
+        .await;
+
+    let mut report = ImportReport {
+        mode,
+        ..Default::default()
+    };
+    let mut new_images = vec![];
+    // The index within the report of each of the new images
+    let mut imported = vec![];
+    // The index within the report along with the source files to remove
+    let mut to_remove = vec![];
+    for (item, result) in results.into_iter() {
+        let source = item.source().clone();
+        let sources: Vec<Utf8PathBuf> = std::iter::once(source.clone())
+            .chain(item.raw_source())
+            .chain(item.sidecars().into_iter().map(|(sidecar, _)| sidecar))
+            .collect();
+        let in_place = item.in_place();
+        let outcome = match (item.action, result) {
+            (_, Some(Ok(image))) => {
+                imported.push(report.outcomes.len());
+                new_images.push(image);
+                if mode == ImportMode::Move && !in_place {
+                    to_remove.push((report.outcomes.len(), sources));
+                }
+                match item.fallback_time {
+                    Some(time) => ImportOutcome::ImportedWithFallbackDate(time),
+                    None => ImportOutcome::Imported,
+                }
+            }
+            (_, Some(Err(e))) => {
+                tracing::warn!("Unable to import {source}: {e}");
+                ImportOutcome::Failed(e.to_string())
+            }
+            (ImportAction::Duplicate(existing), None) => ImportOutcome::SkippedDuplicate(existing),
+            (ImportAction::Collision, None) => ImportOutcome::SkippedCollision(item.destination),
+            (ImportAction::Failed(reason), None) => ImportOutcome::Failed(reason),
+            (ImportAction::New, None) => unreachable!("New pictures are always copied"),
+        };
+        report.push(source, outcome);
+    }
+
+    // Create entry in the database / import. The files have already been copied, so
+    // where they can't be added the report still lists where each of them went.
+    let destinations: Vec<Utf8PathBuf> = new_images.iter().map(|i| i.filepath.clone()).collect();
+    if let Err(e) = add_new_images(db, new_images).await {
+        tracing::error!("Unable to add the imported pictures to the database: {e}");
+        for (index, destination) in imported.into_iter().zip(destinations) {
+            report.outcomes[index].1 = ImportOutcome::Failed(format!(
+                "Copied to {destination}, but unable to add it to the library: {e}"
+            ));
+        }
+        return Ok(report);
+    }
+
+    // Only once the pictures are within the database is it safe to remove the sources.
+    for (index, sources) in to_remove.into_iter() {
+        if let Err(e) = remove_source(sources).await {
+            tracing::warn!("{e}");
+            report.outcomes[index].1 = ImportOutcome::SourceKept(e.to_string());
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,152 @@
+//! The outcome of each file within an import
+//
+// Problems with individual files don't stop an import, instead they are recorded
+// within the report which is shown to the user once the import has finished.
+
+use camino::Utf8PathBuf;
+use iced::widget::{button, column, container, horizontal_space, row, scrollable, text};
+use iced::{Color, Element, Length};
+use time::PrimitiveDateTime;
+
+use crate::directory::DirectoryMessage;
+use crate::settings::ImportMode;
+use crate::Message;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Imported,
+    /// The picture was imported, however without a capture time in the exif data the
+    /// fallback time was used to determine where it was placed.
+    ImportedWithFallbackDate(PrimitiveDateTime),
+    /// The picture was imported, however the source files couldn't be removed
+    SourceKept(String),
+    /// The picture already exists within the library at the path
+    SkippedDuplicate(Utf8PathBuf),
+    /// A different file already exists at the destination path
+    SkippedCollision(Utf8PathBuf),
+    Failed(String),
+}
+
+impl ImportOutcome {
+    fn is_imported(&self) -> bool {
+        matches!(
+            self,
+            ImportOutcome::Imported
+                | ImportOutcome::ImportedWithFallbackDate(_)
+                | ImportOutcome::SourceKept(_)
+        )
+    }
+
+    fn is_skipped(&self) -> bool {
+        matches!(
+            self,
+            ImportOutcome::SkippedDuplicate(_) | ImportOutcome::SkippedCollision(_)
+        )
+    }
+
+    fn is_failed(&self) -> bool {
+        matches!(self, ImportOutcome::Failed(_))
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let (status, color) = match self {
+            ImportOutcome::Imported => ("Imported".to_string(), Color::from_rgb(0.4, 0.8, 0.4)),
+            ImportOutcome::ImportedWithFallbackDate(time) => (
+                format!("Imported, no exif date so used {time}"),
+                Color::from_rgb(0.9, 0.8, 0.3),
+            ),
+            ImportOutcome::SourceKept(reason) => (
+                format!("Imported, source kept: {reason}"),
+                Color::from_rgb(0.9, 0.8, 0.3),
+            ),
+            ImportOutcome::SkippedDuplicate(existing) => (
+                format!("Skipped, duplicate of {existing}"),
+                Color::from_rgb(0.6, 0.6, 0.6),
+            ),
+            ImportOutcome::SkippedCollision(destination) => (
+                format!("Skipped, {destination} already exists"),
+                Color::from_rgb(0.9, 0.6, 0.3),
+            ),
+            ImportOutcome::Failed(reason) => {
+                (format!("Failed: {reason}"), Color::from_rgb(0.9, 0.4, 0.3))
+            }
+        };
+        text(status).color(color).into()
+    }
+}
+
+/// The outcome of every file within an import.
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub outcomes: Vec<(Utf8PathBuf, ImportOutcome)>,
+    pub mode: ImportMode,
+}
+
+impl ImportReport {
+    pub fn push(&mut self, source: Utf8PathBuf, outcome: ImportOutcome) {
+        self.outcomes.push((source, outcome));
+    }
+
+    fn count(&self, predicate: impl Fn(&ImportOutcome) -> bool) -> usize {
+        self.outcomes.iter().filter(|(_, o)| predicate(o)).count()
+    }
+
+    pub fn imported_count(&self) -> usize {
+        self.count(ImportOutcome::is_imported)
+    }
+
+    pub fn skipped_count(&self) -> usize {
+        self.count(ImportOutcome::is_skipped)
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.count(ImportOutcome::is_failed)
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let verb = match self.mode {
+            ImportMode::Copy => "Imported",
+            ImportMode::Move => "Moved",
+        };
+        let summary = text(format!(
+            "{verb} {}, skipped {}, failed {}",
+            self.imported_count(),
+            self.skipped_count(),
+            self.failed_count()
+        ));
+        // Successful imports aren't interesting, so they are listed last.
+        let outcomes = self
+            .outcomes
+            .iter()
+            .filter(|(_, o)| !o.is_imported())
+            .chain(self.outcomes.iter().filter(|(_, o)| o.is_imported()))
+            .map(|(source, outcome)| {
+                row![
+                    text(source.as_str()).width(Length::FillPortion(3)),
+                    container(outcome.view()).width(Length::FillPortion(4)),
+                ]
+                .spacing(10)
+                .into()
+            });
+        container(
+            column![
+                summary,
+                scrollable(column(outcomes).spacing(4))
+                    .direction(scrollable::Direction::Vertical(
+                        scrollable::Scrollbar::new().width(2.).scroller_width(10.),
+                    ))
+                    .height(Length::Fill),
+                row![
+                    horizontal_space(),
+                    button(text("Close")).on_press(DirectoryMessage::ImportReportClose.into()),
+                ]
+            ]
+            .spacing(10),
+        )
+        .style(container::rounded_box)
+        .padding(20)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+    }
+}
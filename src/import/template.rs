@@ -0,0 +1,406 @@
+//! Templates describing where imported pictures are placed
+//
+// A template is a path relative to the import base directory, where the names
+// surrounded by braces are replaced by values for each picture, for example
+// `{year}/{year}-{month}-{day}/{filename}`. The extension of the original file is
+// always appended to the expanded template, keeping the RAW and JPEG files of a
+// picture together.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use camino::Utf8PathBuf;
+use time::PrimitiveDateTime;
+
+/// The values which can be substituted into a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Make,
+    Model,
+    Lens,
+    /// The original filename without the extension
+    Filename,
+    /// The position of the picture within the import, starting from 1
+    Sequence,
+    /// The free-text job name from the import settings
+    Job,
+}
+
+impl Token {
+    const ALL: [Token; 12] = [
+        Token::Year,
+        Token::Month,
+        Token::Day,
+        Token::Hour,
+        Token::Minute,
+        Token::Second,
+        Token::Make,
+        Token::Model,
+        Token::Lens,
+        Token::Filename,
+        Token::Sequence,
+        Token::Job,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Token::Year => "year",
+            Token::Month => "month",
+            Token::Day => "day",
+            Token::Hour => "hour",
+            Token::Minute => "minute",
+            Token::Second => "second",
+            Token::Make => "make",
+            Token::Model => "model",
+            Token::Lens => "lens",
+            Token::Filename => "filename",
+            Token::Sequence => "sequence",
+            Token::Job => "job",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// Whether the token requires the camera information from the exif data.
+    fn is_camera(&self) -> bool {
+        matches!(self, Token::Make | Token::Model | Token::Lens)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Token(Token),
+}
+
+/// Errors from parsing a template, which are found before any files are copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    Empty,
+    /// An opening brace at the position without a matching closing brace
+    UnclosedBrace(usize),
+    /// A closing brace at the position without a matching opening brace
+    UnexpectedBrace(usize),
+    UnknownToken(String),
+    AbsolutePath,
+    ParentDirectory,
+    /// The filename has to contain either `{filename}` or `{sequence}`, otherwise
+    /// every picture would be given the same name.
+    MissingUniqueName,
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::Empty => write!(f, "The template is empty"),
+            TemplateError::UnclosedBrace(i) => write!(f, "Unclosed brace at position {i}"),
+            TemplateError::UnexpectedBrace(i) => write!(f, "Unexpected brace at position {i}"),
+            TemplateError::UnknownToken(name) => write!(
+                f,
+                "Unknown token {{{name}}}, expected one of {}",
+                Token::ALL
+                    .iter()
+                    .map(|t| format!("{{{}}}", t.name()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            TemplateError::AbsolutePath => {
+                write!(f, "The template must be relative to the base directory")
+            }
+            TemplateError::ParentDirectory => {
+                write!(f, "The template can't contain a parent directory (..)")
+            }
+            TemplateError::MissingUniqueName => write!(
+                f,
+                "The filename must contain either the {{filename}} or {{sequence}} token"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// The values of a single picture used to expand a template.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateValues<'a> {
+    pub capture_time: Option<PrimitiveDateTime>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    pub filename: &'a str,
+    pub sequence: usize,
+    pub job: &'a str,
+}
+
+/// Replace characters which would change the directory structure.
+///
+/// Leading dots are also replaced, since `.` and `..` refer to the current and
+/// parent directories, and other names starting with a dot are hidden.
+fn sanitise(value: &str) -> String {
+    let value = value.trim().replace(['/', '\\'], "_");
+    let name = value.trim_start_matches('.');
+    if value.is_empty() {
+        "Unknown".to_string()
+    } else {
+        "_".repeat(value.len() - name.len()) + name
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+}
+
+impl PathTemplate {
+    /// Whether expanding the template requires the camera information.
+    pub fn uses_camera(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, Segment::Token(t) if t.is_camera()))
+    }
+
+    /// Whether the path changes with the sequence number of the picture.
+    pub fn uses_sequence(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, Segment::Token(Token::Sequence)))
+    }
+
+    /// Expand the template for a picture, giving the path relative to the base directory
+    /// without the file extension.
+    pub fn render(&self, values: &TemplateValues) -> Result<Utf8PathBuf, Error> {
+        let mut path = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(l) => path.push_str(l),
+                Segment::Token(token) => {
+                    let value = match token {
+                        Token::Year
+                        | Token::Month
+                        | Token::Day
+                        | Token::Hour
+                        | Token::Minute
+                        | Token::Second => {
+                            let time = values.capture_time.ok_or(anyhow!(
+                                "No capture time for {}, required by {{{}}}",
+                                values.filename,
+                                token.name()
+                            ))?;
+                            match token {
+                                Token::Year => format!("{:04}", time.year()),
+                                Token::Month => format!("{:02}", time.month() as u8),
+                                Token::Day => format!("{:02}", time.day()),
+                                Token::Hour => format!("{:02}", time.hour()),
+                                Token::Minute => format!("{:02}", time.minute()),
+                                _ => format!("{:02}", time.second()),
+                            }
+                        }
+                        Token::Make => sanitise(values.make.as_deref().unwrap_or_default()),
+                        Token::Model => sanitise(values.model.as_deref().unwrap_or_default()),
+                        Token::Lens => sanitise(values.lens.as_deref().unwrap_or_default()),
+                        Token::Filename => sanitise(values.filename),
+                        Token::Sequence => format!("{:04}", values.sequence),
+                        Token::Job => sanitise(values.job),
+                    };
+                    path.push_str(&value);
+                }
+            }
+        }
+        Ok(path.into())
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        crate::settings::ImportSettings::default()
+            .template
+            .parse()
+            .expect("The default template is valid")
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err(TemplateError::Empty);
+        }
+        if s.starts_with('/') {
+            return Err(TemplateError::AbsolutePath);
+        }
+        if s.split('/').any(|component| component == "..") {
+            return Err(TemplateError::ParentDirectory);
+        }
+
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = s.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((_, '{')) | None => {
+                                return Err(TemplateError::UnclosedBrace(index))
+                            }
+                            Some((_, c)) => name.push(c),
+                        }
+                    }
+                    let token =
+                        Token::from_name(name.trim()).ok_or(TemplateError::UnknownToken(name))?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Token(token));
+                }
+                '}' => return Err(TemplateError::UnexpectedBrace(index)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        // Only the tokens after the final directory separator make up the filename.
+        let unique_name = segments
+            .iter()
+            .rev()
+            .take_while(|s| !matches!(s, Segment::Literal(l) if l.contains('/')))
+            .any(|s| matches!(s, Segment::Token(Token::Filename | Token::Sequence)));
+        if !unique_name {
+            return Err(TemplateError::MissingUniqueName);
+        }
+
+        Ok(Self { segments })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn values<'a>(filename: &'a str, sequence: usize, job: &'a str) -> TemplateValues<'a> {
+        TemplateValues {
+            capture_time: Some(datetime!(2024-03-01 10:05:09)),
+            filename,
+            sequence,
+            job,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_date() {
+        let template: PathTemplate = "{year}/{year}-{month}-{day}/{filename}".parse().unwrap();
+        assert_eq!(
+            template.render(&values("IMG_0001", 1, "")).unwrap(),
+            "2024/2024-03-01/IMG_0001"
+        );
+        assert!(!template.uses_camera());
+        assert!(!template.uses_sequence());
+    }
+
+    #[test]
+    fn test_render_sequence() {
+        let template: PathTemplate = "{job}/{hour}{minute}{second}_{ sequence }".parse().unwrap();
+        assert!(template.uses_sequence());
+        assert_eq!(
+            template.render(&values("IMG_0001", 7, "Wedding")).unwrap(),
+            "Wedding/100509_0007"
+        );
+        assert_eq!(
+            template.render(&values("IMG_0001", 12345, "")).unwrap(),
+            "Unknown/100509_12345"
+        );
+    }
+
+    #[test]
+    fn test_render_camera() {
+        let template: PathTemplate = "{make}/{model}/{filename}".parse().unwrap();
+        assert!(template.uses_camera());
+        let values = TemplateValues {
+            make: Some(" Canon/EOS ".to_string()),
+            ..values("a/b", 1, "")
+        };
+        assert_eq!(template.render(&values).unwrap(), "Canon_EOS/Unknown/a_b");
+    }
+
+    #[test]
+    fn test_render_dots() {
+        let template: PathTemplate = "{job}/{filename}".parse().unwrap();
+        assert_eq!(
+            template.render(&values("IMG_0001", 1, "..")).unwrap(),
+            "__/IMG_0001"
+        );
+        assert_eq!(
+            template.render(&values(".hidden", 1, ".")).unwrap(),
+            "_/_hidden"
+        );
+        assert_eq!(
+            template.render(&values("IMG.0001", 1, "../..")).unwrap(),
+            "___../IMG.0001"
+        );
+    }
+
+    #[test]
+    fn test_render_without_capture_time() {
+        let template: PathTemplate = "{year}/{filename}".parse().unwrap();
+        let values = TemplateValues {
+            capture_time: None,
+            ..values("IMG_0001", 1, "")
+        };
+        assert!(template.render(&values).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |s: &str| s.parse::<PathTemplate>().unwrap_err();
+        assert_eq!(error(" "), TemplateError::Empty);
+        assert_eq!(error("/pictures/{filename}"), TemplateError::AbsolutePath);
+        assert_eq!(
+            error("{year}/../{filename}"),
+            TemplateError::ParentDirectory
+        );
+        assert_eq!(error("{year/{filename}"), TemplateError::UnclosedBrace(0));
+        assert_eq!(error("{year"), TemplateError::UnclosedBrace(0));
+        assert_eq!(
+            error("{year}}/{filename}"),
+            TemplateError::UnexpectedBrace(6)
+        );
+        assert_eq!(
+            error("{week}/{filename}"),
+            TemplateError::UnknownToken("week".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_unique_name() {
+        let error = |s: &str| s.parse::<PathTemplate>().unwrap_err();
+        // The filename or sequence has to be after the last directory separator
+        assert_eq!(error("{filename}/{year}"), TemplateError::MissingUniqueName);
+        assert_eq!(error("{sequence}/photo"), TemplateError::MissingUniqueName);
+        assert!("{year}/IMG_{sequence}".parse::<PathTemplate>().is_ok());
+        assert!("{filename}".parse::<PathTemplate>().is_ok());
+    }
+
+    #[test]
+    fn test_default() {
+        let template = PathTemplate::default();
+        assert!(!template.uses_camera());
+        assert!(!template.uses_sequence());
+    }
+}
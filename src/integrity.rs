@@ -0,0 +1,775 @@
+//! Checking the library for problems and repairing them
+//
+// The database and the filesystem can drift apart, like when files are removed by
+// another application or an update of the application is interrupted. Checking the
+// library finds each of these problems, grouping them by kind so all the problems
+// of a kind can be repaired together.
+//
+// A directory which can't be found, like one on a drive that isn't mounted, is
+// reported once rather than as every picture within it being missing, so it can be
+// located again instead. The repairs which remove from the library are confirmed by
+// the user and a snapshot is taken first, so they can be undone.
+//
+// Both the check and the repairs can take a while for a large library, so they are
+// run in the background reporting their progress.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::Error;
+use camino::{Utf8Path, Utf8PathBuf};
+use entity::{directory, picture};
+use futures::StreamExt;
+use iced::task::{sipper, Straw};
+use iced::widget::{
+    button, column, container, horizontal_space, progress_bar, row, scrollable, text,
+};
+use iced::{Color, Element, Length, Task};
+use image::ImageFormat;
+use sea_orm::prelude::*;
+use sea_orm::sea_query::Expr;
+use uuid::Uuid;
+
+use crate::backup::{create_snapshot, SnapshotReason};
+use crate::data::{
+    clear_raw_extensions, query_picture_directory_ids, query_unplaced_pictures, relink_directories,
+    remove_directories, remove_pictures, replace_thumbnail, Progress,
+};
+use crate::directory::DirectoryMessage;
+use crate::settings::BackupSettings;
+use crate::{DownloadState, Message};
+
+/// The number of problems repaired at once, between updates of the progress.
+const REPAIR_CHUNK: usize = 64;
+
+/// The kinds of problems found within the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IssueKind {
+    /// The directory no longer exists, along with all the pictures within it
+    MissingDirectory,
+    /// The file of the picture no longer exists
+    MissingFile,
+    /// The RAW file alongside the picture no longer exists
+    MissingRaw,
+    /// The picture isn't within any directory of the library
+    UnplacedPicture,
+    /// The parent of the directory isn't the directory containing it
+    BrokenParent,
+    /// The directory has no pictures within it or any of its subdirectories
+    OrphanDirectory,
+    /// The stored thumbnail of the picture can't be decoded
+    InvalidThumbnail,
+}
+
+impl IssueKind {
+    pub const ALL: [IssueKind; 7] = [
+        IssueKind::MissingDirectory,
+        IssueKind::MissingFile,
+        IssueKind::MissingRaw,
+        IssueKind::UnplacedPicture,
+        IssueKind::BrokenParent,
+        IssueKind::OrphanDirectory,
+        IssueKind::InvalidThumbnail,
+    ];
+
+    /// The action taken to repair all the problems of this kind, where they can be
+    /// repaired together.
+    fn repair_label(&self) -> Option<&'static str> {
+        match self {
+            // Each directory is located by the user
+            IssueKind::MissingDirectory => None,
+            IssueKind::MissingFile | IssueKind::UnplacedPicture => Some("Remove from library"),
+            IssueKind::MissingRaw => Some("Forget RAW files"),
+            IssueKind::BrokenParent => Some("Relink directories"),
+            IssueKind::OrphanDirectory => Some("Remove directories"),
+            IssueKind::InvalidThumbnail => Some("Recreate thumbnails"),
+        }
+    }
+
+    /// Whether the repair removes pictures or directories from the library.
+    fn is_destructive(&self) -> bool {
+        matches!(
+            self,
+            IssueKind::MissingFile | IssueKind::UnplacedPicture | IssueKind::OrphanDirectory
+        )
+    }
+}
+
+impl std::fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            IssueKind::MissingDirectory => "Missing directories",
+            IssueKind::MissingFile => "Missing files",
+            IssueKind::MissingRaw => "Missing RAW files",
+            IssueKind::UnplacedPicture => "Pictures without a directory",
+            IssueKind::BrokenParent => "Directories with the wrong parent",
+            IssueKind::OrphanDirectory => "Directories without pictures",
+            IssueKind::InvalidThumbnail => "Unreadable thumbnails",
+        };
+        write!(f, "{value}")
+    }
+}
+
+/// A single problem within the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// The id of the picture or directory with the problem
+    pub id: Uuid,
+    /// Where the problem is, as shown to the user
+    pub location: String,
+}
+
+/// All the problems found when checking the library.
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub issues: Vec<Issue>,
+}
+
+impl IntegrityReport {
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn of_kind(&self, kind: IssueKind) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(move |i| i.kind == kind)
+    }
+
+    /// The locations of the problems of a kind, where the missing files are grouped
+    /// by their directory so a large number of them can still be read.
+    fn locations_view(&self, kind: IssueKind) -> Element<'_, IntegrityMessage> {
+        let location = |value: String| text(value).color(Color::from_rgb(0.6, 0.6, 0.6));
+        let locations: Vec<Element<'_, IntegrityMessage>> = match kind {
+            IssueKind::MissingDirectory => self
+                .of_kind(kind)
+                .map(|issue| {
+                    row![
+                        location(issue.location.clone()),
+                        button(text("Locate")).on_press(IntegrityMessage::Locate(issue.id)),
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Alignment::Center)
+                    .into()
+                })
+                .collect(),
+            IssueKind::MissingFile | IssueKind::MissingRaw => {
+                let mut directories: BTreeMap<&str, usize> = BTreeMap::new();
+                for issue in self.of_kind(kind) {
+                    let directory = Utf8Path::new(&issue.location)
+                        .parent()
+                        .map_or("", Utf8Path::as_str);
+                    *directories.entry(directory).or_default() += 1;
+                }
+                directories
+                    .into_iter()
+                    .map(|(directory, count)| {
+                        location(format!("{directory} ({count} files)")).into()
+                    })
+                    .collect()
+            }
+            _ => self
+                .of_kind(kind)
+                .map(|issue| location(issue.location.clone()).into())
+                .collect(),
+        };
+        column(locations).spacing(2).padding([0, 20]).into()
+    }
+
+    /// The problems found, along with the repair waiting for confirmation by the user.
+    pub fn view(&self, confirm: Option<IssueKind>) -> Element<'_, IntegrityMessage> {
+        let summary = if self.is_empty() {
+            text("No problems were found within the library.")
+        } else {
+            text(format!(
+                "Found {} problems within the library.",
+                self.issues.len()
+            ))
+        };
+        let kinds = IssueKind::ALL.into_iter().filter_map(|kind| {
+            let count = self.of_kind(kind).count();
+            if count == 0 {
+                return None;
+            }
+            let repair: Element<'_, IntegrityMessage> = match kind.repair_label() {
+                Some(_) if confirm == Some(kind) => row![
+                    text(format!(
+                        "Remove {count} items from the library? A snapshot is taken first."
+                    )),
+                    button(text("Cancel")).on_press(IntegrityMessage::RepairCancel),
+                    button(text("Remove")).on_press(IntegrityMessage::RepairConfirm),
+                ]
+                .spacing(10)
+                .align_y(iced::Alignment::Center)
+                .into(),
+                Some(label) => button(text(label))
+                    .on_press(IntegrityMessage::Repair(kind))
+                    .into(),
+                None => column![].into(),
+            };
+            Some(
+                column![
+                    row![
+                        text(format!("{kind} ({count})")),
+                        horizontal_space(),
+                        repair,
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Alignment::Center),
+                    self.locations_view(kind),
+                ]
+                .spacing(4)
+                .into(),
+            )
+        });
+        container(
+            column![
+                summary,
+                scrollable(column(kinds).spacing(10))
+                    .direction(scrollable::Direction::Vertical(
+                        scrollable::Scrollbar::new().width(2.).scroller_width(10.),
+                    ))
+                    .height(Length::Fill),
+                row![
+                    horizontal_space(),
+                    button(text("Close")).on_press(IntegrityMessage::Close),
+                ]
+            ]
+            .spacing(10),
+        )
+        .style(container::rounded_box)
+        .padding(20)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum IntegrityError {
+    IntegrityFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for IntegrityError {
+    fn from(error: anyhow::Error) -> Self {
+        IntegrityError::IntegrityFailed(Arc::new(error))
+    }
+}
+
+impl From<sea_orm::DbErr> for IntegrityError {
+    fn from(error: sea_orm::DbErr) -> Self {
+        IntegrityError::IntegrityFailed(Arc::new(error.into()))
+    }
+}
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityError::IntegrityFailed(e) => write!(f, "Checking the library failed: {e}"),
+        }
+    }
+}
+
+/// The problems with the directories of the library.
+///
+/// The parent of each directory is expected to be the directory containing it,
+/// matching how directories are created when adding pictures, where the directories
+/// at the root of the filesystem have no parent.
+fn check_directories(
+    directories: &HashMap<Uuid, directory::Model>,
+    with_pictures: &HashSet<Uuid>,
+) -> Vec<Issue> {
+    let by_path: HashMap<&str, Uuid> = directories
+        .values()
+        .map(|d| (d.directory.as_str(), d.id))
+        .collect();
+    let occupied: Vec<&Utf8Path> = with_pictures
+        .iter()
+        .filter_map(|id| directories.get(id))
+        .map(|d| Utf8Path::new(&d.directory))
+        .collect();
+
+    let mut issues = vec![];
+    for directory in directories.values() {
+        let path = Utf8Path::new(&directory.directory);
+        let expected = match path.parent() {
+            None => Some(None),
+            Some(parent) => match by_path.get(parent.as_str()) {
+                Some(id) => Some(Some(*id)),
+                None if parent.parent().is_none() => Some(None),
+                // The parent is missing from the library
+                None => None,
+            },
+        };
+        let issue = |kind| Issue {
+            kind,
+            id: directory.id,
+            location: directory.directory.clone(),
+        };
+        if expected != Some(directory.parent_id) {
+            issues.push(issue(IssueKind::BrokenParent));
+        }
+        if !occupied.iter().any(|p| p.starts_with(path)) {
+            issues.push(issue(IssueKind::OrphanDirectory));
+        }
+    }
+    issues
+}
+
+/// The directories which can't be found, where only the topmost of them is reported
+/// since everything beneath a missing directory is missing along with it.
+fn check_missing_directories(
+    directories: &HashMap<Uuid, directory::Model>,
+    missing: &HashSet<Uuid>,
+) -> Vec<Issue> {
+    let missing_paths: HashSet<&str> = missing
+        .iter()
+        .filter_map(|id| directories.get(id))
+        .map(|d| d.directory.as_str())
+        .collect();
+    let mut issues: Vec<Issue> = missing
+        .iter()
+        .filter_map(|id| directories.get(id))
+        .filter(|d| {
+            !Utf8Path::new(&d.directory)
+                .ancestors()
+                .skip(1)
+                .any(|a| missing_paths.contains(a.as_str()))
+        })
+        .map(|d| Issue {
+            kind: IssueKind::MissingDirectory,
+            id: d.id,
+            location: d.directory.clone(),
+        })
+        .collect();
+    issues.sort_by(|a, b| a.location.cmp(&b.location));
+    issues
+}
+
+/// The problems with the files and thumbnail of a picture.
+///
+/// This is only used for pictures within directories that exist, so a missing file
+/// is the file itself being removed rather than the drive containing it being
+/// unavailable. Files which can't be read are not reported as missing.
+fn check_picture(picture: &picture::Model, filepath: &Utf8PathBuf) -> Vec<Issue> {
+    let issue = |kind, location: &Utf8Path| Issue {
+        kind,
+        id: picture.id,
+        location: location.to_string(),
+    };
+    let mut issues = vec![];
+    if matches!(filepath.try_exists(), Ok(false)) {
+        issues.push(issue(IssueKind::MissingFile, filepath));
+    } else if let Some(extension) = &picture.raw_extension {
+        let raw = filepath.with_extension(extension);
+        if matches!(raw.try_exists(), Ok(false)) {
+            issues.push(issue(IssueKind::MissingRaw, &raw));
+        }
+    }
+    if let Some(thumbnail) = &picture.thumbnail {
+        if image::load_from_memory_with_format(thumbnail, ImageFormat::Jpeg).is_err() {
+            issues.push(issue(IssueKind::InvalidThumbnail, filepath));
+        }
+    }
+    issues
+}
+
+/// Check the whole library for problems.
+///
+/// The directories are checked first, then the progress follows the pictures as
+/// each of their files is checked.
+pub(crate) fn check_library(
+    db: &DatabaseConnection,
+) -> impl Straw<IntegrityReport, Progress, IntegrityError> {
+    let db = db.clone();
+    sipper(async move |mut progress| {
+        let directories: HashMap<Uuid, directory::Model> = directory::Entity::find()
+            .all(&db)
+            .await?
+            .into_iter()
+            .map(|d| (d.id, d))
+            .collect();
+        let with_pictures: HashSet<Uuid> = query_picture_directory_ids(&db)
+            .await?
+            .into_iter()
+            .collect();
+        let paths: Vec<(Uuid, Utf8PathBuf)> = directories
+            .values()
+            .map(|d| (d.id, Utf8PathBuf::from(&d.directory)))
+            .collect();
+        let missing: HashSet<Uuid> = tokio::task::spawn_blocking(move || {
+            paths
+                .into_iter()
+                .filter(|(_, path)| matches!(path.try_exists(), Ok(false)))
+                .map(|(id, _)| id)
+                .collect()
+        })
+        .await
+        .map_err(Error::from)?;
+        let mut issues = check_missing_directories(&directories, &missing);
+        issues.extend(check_directories(&directories, &with_pictures));
+        issues.extend(
+            query_unplaced_pictures(&db)
+                .await?
+                .into_iter()
+                .map(|(id, filename)| Issue {
+                    kind: IssueKind::UnplacedPicture,
+                    id,
+                    location: filename,
+                }),
+        );
+
+        // The pictures without a directory have already been reported, and can't be
+        // loaded without one. The pictures within missing directories are reported
+        // along with their directory.
+        let query = picture::Entity::find().filter(Expr::cust(
+            "pictures.directory_id IN (SELECT id FROM directories)",
+        ));
+        let num_items = query.clone().count(&db).await?;
+        let mut pictures = query.stream(&db).await?;
+        let mut index = 0;
+        while let Some(picture) = pictures.next().await {
+            let picture = picture?;
+            index += 1;
+            if let Some(directory) = directories
+                .get(&picture.directory_id)
+                .filter(|d| !missing.contains(&d.id))
+            {
+                let filepath = picture.filepath(directory);
+                let found = tokio::task::spawn_blocking(move || check_picture(&picture, &filepath))
+                    .await
+                    .map_err(Error::from)?;
+                issues.extend(found);
+            }
+            let _ = progress
+                .send(Progress {
+                    percent: 100.0 * index as f32 / num_items as f32,
+                })
+                .await;
+        }
+        tracing::info!("Found {} problems within the library", issues.len());
+        Ok(IntegrityReport { issues })
+    })
+}
+
+/// Repair a group of problems of the same kind, returning how many were repaired.
+async fn repair(db: &DatabaseConnection, kind: IssueKind, ids: Vec<Uuid>) -> Result<u64, Error> {
+    match kind {
+        // The missing directories are located by the user instead
+        IssueKind::MissingDirectory => Ok(0),
+        IssueKind::MissingFile | IssueKind::UnplacedPicture => remove_pictures(db, ids).await,
+        IssueKind::MissingRaw => clear_raw_extensions(db, ids).await,
+        IssueKind::BrokenParent => relink_directories(db, ids).await,
+        IssueKind::OrphanDirectory => remove_directories(db, ids).await,
+        IssueKind::InvalidThumbnail => {
+            let mut count = 0;
+            for id in ids.into_iter() {
+                replace_thumbnail(db, id).await?;
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+}
+
+/// Repair all the problems of a kind.
+///
+/// A snapshot is taken before removing anything from the library, and the repair
+/// doesn't go ahead where the snapshot can't be taken.
+pub(crate) fn repair_issues(
+    db: &DatabaseConnection,
+    kind: IssueKind,
+    issues: Vec<Issue>,
+    keep: usize,
+) -> impl Straw<u64, Progress, IntegrityError> {
+    let db = db.clone();
+    sipper(async move |mut progress| {
+        if kind.is_destructive() {
+            create_snapshot(&db, SnapshotReason::Repair, keep)
+                .await
+                .map_err(|e| anyhow::anyhow!("Unable to take a snapshot before repairing: {e}"))?;
+        }
+        let ids: Vec<Uuid> = issues.iter().map(|i| i.id).collect();
+        let mut repaired = 0;
+        for (index, group) in ids.chunks(REPAIR_CHUNK).enumerate() {
+            repaired += repair(&db, kind, group.to_vec()).await?;
+            let _ = progress
+                .send(Progress {
+                    percent: 100.0 * ((index + 1) * REPAIR_CHUNK).min(ids.len()) as f32
+                        / ids.len() as f32,
+                })
+                .await;
+        }
+        tracing::info!("Repaired {repaired} of {} problems", ids.len());
+        Ok(repaired)
+    })
+}
+
+#[derive(Debug, Clone)]
+pub enum IntegrityMessage {
+    /// Check the whole library for problems
+    Check,
+    Progress(Progress),
+    Checked(Result<IntegrityReport, IntegrityError>),
+    /// Repair all the problems of a kind within the report, which is confirmed by
+    /// the user first where the repair removes from the library
+    Repair(IssueKind),
+    RepairConfirm,
+    RepairCancel,
+    Repaired(Result<u64, IntegrityError>),
+    /// Choose the new location of the missing directory
+    Locate(Uuid),
+    Close,
+}
+
+impl From<IntegrityMessage> for Message {
+    fn from(val: IntegrityMessage) -> Self {
+        Message::Integrity(val)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IntegrityView {
+    database: DatabaseConnection,
+    /// The check or repair running in the background
+    task: DownloadState,
+    /// The problems found by the most recent check, until dismissed by the user
+    pub report: Option<IntegrityReport>,
+    /// The repair waiting for confirmation before it is run
+    confirm: Option<IssueKind>,
+}
+
+impl IntegrityView {
+    pub fn new(database: DatabaseConnection) -> Self {
+        Self {
+            database,
+            task: Default::default(),
+            report: None,
+            confirm: None,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(self.task, DownloadState::Downloading { .. })
+    }
+
+    /// Run the task in the background, keeping the handle so it stops when replaced.
+    fn start<T: Send + 'static>(
+        &mut self,
+        straw: impl Straw<T, Progress, IntegrityError> + Send + 'static,
+        finished: impl FnOnce(Result<T, IntegrityError>) -> IntegrityMessage + Send + 'static,
+    ) -> Task<Message> {
+        let (task, handle) = Task::sip(straw, IntegrityMessage::Progress, finished).abortable();
+        self.task = DownloadState::Downloading {
+            progress: 0.,
+            _task: handle.abort_on_drop(),
+        };
+        task.map(Message::Integrity)
+    }
+
+    pub fn update(&mut self, message: IntegrityMessage, backup: &BackupSettings) -> Task<Message> {
+        match message {
+            IntegrityMessage::Check => {
+                if self.is_running() {
+                    return Task::none();
+                }
+                self.start(check_library(&self.database), IntegrityMessage::Checked)
+            }
+            IntegrityMessage::Progress(new_progress) => {
+                if let DownloadState::Downloading { progress, .. } = &mut self.task {
+                    *progress = new_progress.percent;
+                }
+                Task::none()
+            }
+            IntegrityMessage::Checked(result) => {
+                match result {
+                    Ok(report) => {
+                        self.task = DownloadState::Finished;
+                        self.report = Some(report);
+                    }
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        self.task = DownloadState::Errored;
+                    }
+                }
+                Task::none()
+            }
+            IntegrityMessage::Repair(kind) if kind.is_destructive() => {
+                self.confirm = Some(kind);
+                Task::none()
+            }
+            IntegrityMessage::RepairCancel => {
+                self.confirm = None;
+                Task::none()
+            }
+            IntegrityMessage::Repair(kind) => self.repair(kind, backup.keep),
+            IntegrityMessage::RepairConfirm => match self.confirm.take() {
+                Some(kind) => self.repair(kind, backup.keep),
+                None => Task::none(),
+            },
+            IntegrityMessage::Repaired(result) => {
+                self.task = match &result {
+                    Ok(_) => DownloadState::Finished,
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        DownloadState::Errored
+                    }
+                };
+                // The repairs can change the directories, and the library is checked
+                // again so the remaining problems are shown.
+                Task::batch([
+                    Task::done(DirectoryMessage::QueryDirectories).map(Message::Directory),
+                    Task::done(IntegrityMessage::Check).map(Message::Integrity),
+                ])
+            }
+            IntegrityMessage::Locate(id) => {
+                // The prompt to locate the directory is shown once the report is closed
+                self.report = None;
+                self.confirm = None;
+                Task::done(DirectoryMessage::LocateDirectory(id)).map(Message::Directory)
+            }
+            IntegrityMessage::Close => {
+                self.report = None;
+                self.confirm = None;
+                Task::none()
+            }
+        }
+    }
+
+    /// Repair all the problems of a kind within the report, closing the report until
+    /// the library has been checked again.
+    fn repair(&mut self, kind: IssueKind, keep: usize) -> Task<Message> {
+        let Some(report) = &self.report else {
+            return Task::none();
+        };
+        if self.is_running() {
+            return Task::none();
+        }
+        let issues: Vec<Issue> = report.of_kind(kind).cloned().collect();
+        self.report = None;
+        self.start(
+            repair_issues(&self.database, kind, issues, keep),
+            IntegrityMessage::Repaired,
+        )
+    }
+
+    /// The problems found by the most recent check.
+    pub fn view(&self) -> Element<'_, IntegrityMessage> {
+        match &self.report {
+            Some(report) => report.view(self.confirm),
+            None => column![].into(),
+        }
+    }
+
+    /// The button to check the library, along with the progress of a running check.
+    pub fn menu_view(&self) -> Element<'_, Message> {
+        let check = button(text("Check Library"))
+            .on_press_maybe((!self.is_running()).then_some(IntegrityMessage::Check.into()));
+        match self.task {
+            DownloadState::Downloading { progress, .. } => {
+                row![check, progress_bar(0.0..=100.0, progress)]
+                    .spacing(10)
+                    .align_y(iced::Alignment::Center)
+                    .into()
+            }
+            _ => check.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The directories of a library, each with the path of its parent.
+    fn library(directories: &[(&str, Option<&str>)]) -> HashMap<Uuid, directory::Model> {
+        let ids: HashMap<&str, Uuid> = directories
+            .iter()
+            .map(|(path, _)| (*path, Uuid::new_v4()))
+            .collect();
+        directories
+            .iter()
+            .map(|(path, parent)| {
+                let model = directory::Model {
+                    id: ids[path],
+                    directory: path.to_string(),
+                    parent_id: parent.map(|p| ids[p]),
+                    expanded: false,
+                };
+                (model.id, model)
+            })
+            .collect()
+    }
+
+    fn ids(directories: &HashMap<Uuid, directory::Model>, paths: &[&str]) -> HashSet<Uuid> {
+        directories
+            .values()
+            .filter(|d| paths.contains(&d.directory.as_str()))
+            .map(|d| d.id)
+            .collect()
+    }
+
+    fn locations(mut issues: Vec<Issue>) -> Vec<(String, IssueKind)> {
+        issues.sort_by(|a, b| a.location.cmp(&b.location));
+        issues.into_iter().map(|i| (i.location, i.kind)).collect()
+    }
+
+    #[test]
+    fn test_check_directories() {
+        let directories = library(&[
+            ("/photos", None),
+            ("/photos/2024", Some("/photos")),
+            // The parent should be /photos/2024
+            ("/photos/2024/March", None),
+            ("/photos/empty", Some("/photos")),
+            // The parent is missing from the library
+            ("/mnt/drive/photos", None),
+        ]);
+        let with_pictures = ids(&directories, &["/photos/2024/March", "/mnt/drive/photos"]);
+        assert_eq!(
+            locations(check_directories(&directories, &with_pictures)),
+            [
+                ("/mnt/drive/photos".to_string(), IssueKind::BrokenParent),
+                ("/photos/2024/March".to_string(), IssueKind::BrokenParent),
+                ("/photos/empty".to_string(), IssueKind::OrphanDirectory),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_directories_valid() {
+        let directories = library(&[
+            ("/photos", None),
+            ("/photos/2024", Some("/photos")),
+            ("/photos/2025", Some("/photos")),
+        ]);
+        let with_pictures = ids(&directories, &["/photos/2024", "/photos/2025"]);
+        assert!(check_directories(&directories, &with_pictures).is_empty());
+    }
+
+    #[test]
+    fn test_check_missing_directories() {
+        let directories = library(&[
+            ("/photos", None),
+            ("/photos/2024", Some("/photos")),
+            ("/photos/2024/March", Some("/photos/2024")),
+            ("/photos/2025", Some("/photos")),
+            ("/mnt/drive", None),
+        ]);
+        let missing = ids(
+            &directories,
+            &["/photos/2024", "/photos/2024/March", "/mnt/drive"],
+        );
+        // Only the topmost of the missing directories are reported
+        let issues = check_missing_directories(&directories, &missing);
+        assert_eq!(
+            locations(issues.clone()),
+            [
+                ("/mnt/drive".to_string(), IssueKind::MissingDirectory),
+                ("/photos/2024".to_string(), IssueKind::MissingDirectory),
+            ]
+        );
+        assert!(issues
+            .iter()
+            .all(|i| directories[&i.id].directory == i.location));
+    }
+}
@@ -0,0 +1,503 @@
+//! Undoing and redoing the changes made to pictures
+//
+// Every change to the selection, rating, flag, hidden state, caption and tags of a
+// picture is recorded within the `edits` table, so the changes can be undone and
+// redone. The journal is kept within the database rather than in memory so the
+// changes made before the application was closed, or crashed, can still be undone.
+//
+// The edits made by a single action of the user are grouped together, so tagging
+// a hundred pictures is undone in one step. Making a new change after undoing
+// discards the undone edits, in the same way as a text editor.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use entity::{edit, picture, EditField, Flag, Rating, Selection};
+use sea_orm::prelude::*;
+use sea_orm::query::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveEnum, ActiveValue, ConnectionTrait, Statement, TransactionTrait};
+
+use crate::data::{add_picture_tags, remove_picture_tags};
+use crate::picture::PictureData;
+
+/// The number of actions kept within the journal, with the oldest actions removed
+/// once this is exceeded.
+const JOURNAL_ACTIONS: u64 = 1000;
+
+/// A change to a single property of a picture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Selection {
+        before: Selection,
+        after: Selection,
+    },
+    Rating {
+        before: Option<Rating>,
+        after: Option<Rating>,
+    },
+    Flag {
+        before: Option<Flag>,
+        after: Option<Flag>,
+    },
+    Hidden {
+        before: bool,
+        after: bool,
+    },
+    Caption {
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// The tag was added to the picture, or removed when `added` is false
+    Tag {
+        name: String,
+        added: bool,
+    },
+}
+
+impl Change {
+    /// The change which reverts this change.
+    pub fn inverse(&self) -> Self {
+        match self.clone() {
+            Change::Selection { before, after } => Change::Selection {
+                before: after,
+                after: before,
+            },
+            Change::Rating { before, after } => Change::Rating {
+                before: after,
+                after: before,
+            },
+            Change::Flag { before, after } => Change::Flag {
+                before: after,
+                after: before,
+            },
+            Change::Hidden { before, after } => Change::Hidden {
+                before: after,
+                after: before,
+            },
+            Change::Caption { before, after } => Change::Caption {
+                before: after,
+                after: before,
+            },
+            Change::Tag { name, added } => Change::Tag {
+                name,
+                added: !added,
+            },
+        }
+    }
+
+    /// Whether the change leaves the picture as it was.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Change::Selection { before, after } => before == after,
+            Change::Rating { before, after } => before == after,
+            Change::Flag { before, after } => before == after,
+            Change::Hidden { before, after } => before == after,
+            Change::Caption { before, after } => before == after,
+            Change::Tag { .. } => false,
+        }
+    }
+
+    /// Make the change to the picture in memory.
+    pub fn apply(&self, picture: &mut PictureData) {
+        match self {
+            Change::Selection { after, .. } => picture.selection = *after,
+            Change::Rating { after, .. } => picture.rating = *after,
+            Change::Flag { after, .. } => picture.flag = *after,
+            Change::Hidden { after, .. } => picture.hidden = *after,
+            Change::Caption { after, .. } => picture.caption = after.clone(),
+            Change::Tag { name, added: true } => {
+                if !picture.tags.contains(name) {
+                    picture.tags.push(name.clone());
+                    picture.tags.sort();
+                }
+            }
+            Change::Tag { name, added: false } => picture.tags.retain(|t| t != name),
+        }
+    }
+
+    fn field(&self) -> EditField {
+        match self {
+            Change::Selection { .. } => EditField::Selection,
+            Change::Rating { .. } => EditField::Rating,
+            Change::Flag { .. } => EditField::Flag,
+            Change::Hidden { .. } => EditField::Hidden,
+            Change::Caption { .. } => EditField::Caption,
+            Change::Tag { .. } => EditField::Tag,
+        }
+    }
+
+    /// The values before and after the change, as they are stored within the journal.
+    fn values(&self) -> (Option<String>, Option<String>) {
+        match self {
+            Change::Selection { before, after } => {
+                (Some(before.to_value()), Some(after.to_value()))
+            }
+            Change::Rating { before, after } => {
+                (before.map(|r| r.to_value()), after.map(|r| r.to_value()))
+            }
+            Change::Flag { before, after } => {
+                (before.map(|f| f.to_value()), after.map(|f| f.to_value()))
+            }
+            Change::Hidden { before, after } => (Some(before.to_string()), Some(after.to_string())),
+            Change::Caption { before, after } => (before.clone(), after.clone()),
+            Change::Tag { name, added } => {
+                let name = Some(name.clone());
+                if *added {
+                    (None, name)
+                } else {
+                    (name, None)
+                }
+            }
+        }
+    }
+}
+
+/// Read a required value from the journal.
+fn required<T>(value: Option<T>) -> Result<T, Error> {
+    value.ok_or(anyhow!("The edit is missing a value"))
+}
+
+fn parse_bool(value: Option<String>) -> Result<bool, Error> {
+    Ok(required(value)?.parse()?)
+}
+
+fn parse_enum<T: ActiveEnum<Value = String>>(value: Option<String>) -> Result<Option<T>, Error> {
+    Ok(value.map(|v| T::try_from_value(&v)).transpose()?)
+}
+
+impl TryFrom<edit::Model> for Change {
+    type Error = Error;
+
+    fn try_from(value: edit::Model) -> Result<Self, Self::Error> {
+        let edit::Model {
+            field,
+            before,
+            after,
+            ..
+        } = value;
+        Ok(match field {
+            EditField::Selection => Change::Selection {
+                before: required(parse_enum(before)?)?,
+                after: required(parse_enum(after)?)?,
+            },
+            EditField::Rating => Change::Rating {
+                before: parse_enum(before)?,
+                after: parse_enum(after)?,
+            },
+            EditField::Flag => Change::Flag {
+                before: parse_enum(before)?,
+                after: parse_enum(after)?,
+            },
+            EditField::Hidden => Change::Hidden {
+                before: parse_bool(before)?,
+                after: parse_bool(after)?,
+            },
+            EditField::Caption => Change::Caption { before, after },
+            EditField::Tag => match (before, after) {
+                (None, Some(name)) => Change::Tag { name, added: true },
+                (Some(name), None) => Change::Tag { name, added: false },
+                _ => return Err(anyhow!("The edit of a tag needs exactly one name")),
+            },
+        })
+    }
+}
+
+/// A change made to a picture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub picture_id: Uuid,
+    pub change: Change,
+}
+
+impl Edit {
+    pub fn new(picture_id: Uuid, change: Change) -> Self {
+        Self { picture_id, change }
+    }
+
+    fn inverse(&self) -> Self {
+        Self::new(self.picture_id, self.change.inverse())
+    }
+}
+
+impl TryFrom<edit::Model> for Edit {
+    type Error = Error;
+
+    fn try_from(value: edit::Model) -> Result<Self, Self::Error> {
+        Ok(Edit::new(value.picture_id, value.try_into()?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum JournalError {
+    JournalFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for JournalError {
+    fn from(error: anyhow::Error) -> Self {
+        JournalError::JournalFailed(Arc::new(error))
+    }
+}
+
+impl From<sea_orm::DbErr> for JournalError {
+    fn from(error: sea_orm::DbErr) -> Self {
+        JournalError::JournalFailed(Arc::new(error.into()))
+    }
+}
+
+impl std::fmt::Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalError::JournalFailed(e) => write!(f, "Unable to undo or redo the change: {e}"),
+        }
+    }
+}
+
+/// Record the edits made by a single action of the user.
+///
+/// The edits are recorded within the same transaction as the changes they make, so
+/// the journal never holds an edit which wasn't written. The edits which have been
+/// undone can no longer be redone once a new action is recorded.
+#[tracing::instrument(name = "Recording edits", skip(db, edits))]
+pub async fn record_edits<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    edits: Vec<Edit>,
+) -> Result<(), Error> {
+    let edits: Vec<Edit> = edits.into_iter().filter(|e| !e.change.is_empty()).collect();
+    if edits.is_empty() {
+        return Ok(());
+    }
+    let action = Uuid::new_v4();
+    let txn = db.begin().await?;
+    edit::Entity::delete_many()
+        .filter(edit::Column::Undone.eq(true))
+        .exec(&txn)
+        .await?;
+    edit::Entity::insert_many(edits.into_iter().map(|e| {
+        let (before, after) = e.change.values();
+        edit::ActiveModel {
+            id: ActiveValue::NotSet,
+            action: ActiveValue::Set(action),
+            picture_id: ActiveValue::Set(e.picture_id),
+            field: ActiveValue::Set(e.change.field()),
+            before: ActiveValue::Set(before),
+            after: ActiveValue::Set(after),
+            undone: ActiveValue::Set(false),
+        }
+    }))
+    .exec(&txn)
+    .await?;
+
+    // Remove the oldest actions, keeping the whole of each action which remains
+    let statement = Statement::from_sql_and_values(
+        txn.get_database_backend(),
+        r#"
+        DELETE FROM edits WHERE action IN (
+            SELECT action FROM edits GROUP BY action ORDER BY max(id) DESC LIMIT -1 OFFSET ?
+        )
+        "#,
+        [JOURNAL_ACTIONS.into()],
+    );
+    txn.execute(statement).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Write the changes to the pictures within the database.
+async fn write_edits<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    edits: &[Edit],
+) -> Result<(), Error> {
+    for edit in edits.iter() {
+        let update = picture::Entity::update_many().filter(picture::Column::Id.eq(edit.picture_id));
+        let update = match &edit.change {
+            Change::Selection { after, .. } => {
+                update.col_expr(picture::Column::Selection, Expr::value(*after))
+            }
+            Change::Rating { after, .. } => {
+                update.col_expr(picture::Column::Rating, Expr::value(*after))
+            }
+            Change::Flag { after, .. } => {
+                update.col_expr(picture::Column::Flag, Expr::value(*after))
+            }
+            Change::Hidden { after, .. } => {
+                update.col_expr(picture::Column::Hidden, Expr::value(*after))
+            }
+            Change::Caption { after, .. } => {
+                update.col_expr(picture::Column::Caption, Expr::value(after.clone()))
+            }
+            Change::Tag { name, added: true } => {
+                add_picture_tags(db, name, vec![edit.picture_id]).await?;
+                continue;
+            }
+            Change::Tag { name, added: false } => {
+                remove_picture_tags(db, name, vec![edit.picture_id]).await?;
+                continue;
+            }
+        };
+        update.exec(db).await?;
+    }
+    Ok(())
+}
+
+/// Revert the most recent action which hasn't been undone.
+///
+/// This returns the changes made to the pictures, which is empty when there is
+/// nothing left to undo.
+#[tracing::instrument(name = "Undoing edits", skip(db))]
+pub async fn undo_edits(db: &DatabaseConnection) -> Result<Vec<Edit>, JournalError> {
+    step_journal(db, false).await
+}
+
+/// Make the changes of the earliest action which has been undone once more.
+#[tracing::instrument(name = "Redoing edits", skip(db))]
+pub async fn redo_edits(db: &DatabaseConnection) -> Result<Vec<Edit>, JournalError> {
+    step_journal(db, true).await
+}
+
+/// Move through the journal by one action, undoing the last action or redoing the
+/// next one.
+async fn step_journal(db: &DatabaseConnection, redo: bool) -> Result<Vec<Edit>, JournalError> {
+    let txn = db.begin().await?;
+    let next = edit::Entity::find().filter(edit::Column::Undone.eq(redo));
+    let next = if redo {
+        next.order_by_asc(edit::Column::Id)
+    } else {
+        next.order_by_desc(edit::Column::Id)
+    };
+    let Some(next) = next.one(&txn).await? else {
+        return Ok(vec![]);
+    };
+
+    let rows = edit::Entity::find().filter(edit::Column::Action.eq(next.action));
+    // The edits of an action are undone in the reverse of the order they were made
+    let rows = if redo {
+        rows.order_by_asc(edit::Column::Id)
+    } else {
+        rows.order_by_desc(edit::Column::Id)
+    };
+    let edits = rows
+        .all(&txn)
+        .await?
+        .into_iter()
+        .map(|row| {
+            let edit = Edit::try_from(row)?;
+            Ok(if redo { edit } else { edit.inverse() })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    write_edits(&txn, &edits).await?;
+    edit::Entity::update_many()
+        .col_expr(edit::Column::Undone, Expr::value(!redo))
+        .filter(edit::Column::Action.eq(next.action))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    Ok(edits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn model(field: EditField, before: Option<String>, after: Option<String>) -> edit::Model {
+        edit::Model {
+            id: 1,
+            action: Uuid::new_v4(),
+            picture_id: Uuid::new_v4(),
+            field,
+            before,
+            after,
+            undone: false,
+        }
+    }
+
+    /// The change as it is read back from the journal.
+    fn round_trip(change: &Change) -> Change {
+        let (before, after) = change.values();
+        Change::try_from(model(change.field(), before, after)).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let changes = [
+            Change::Selection {
+                before: Selection::Ordinary,
+                after: Selection::Pick,
+            },
+            Change::Rating {
+                before: None,
+                after: Some(Rating::Four),
+            },
+            Change::Rating {
+                before: Some(Rating::One),
+                after: None,
+            },
+            Change::Flag {
+                before: Some(Flag::Red),
+                after: Some(Flag::Purple),
+            },
+            Change::Hidden {
+                before: false,
+                after: true,
+            },
+            Change::Caption {
+                before: None,
+                after: Some("Sunset over the harbour".to_string()),
+            },
+            Change::Tag {
+                name: "Places/Sydney".to_string(),
+                added: true,
+            },
+            Change::Tag {
+                name: "Places/Sydney".to_string(),
+                added: false,
+            },
+        ];
+        for change in changes.iter() {
+            assert_eq!(&round_trip(change), change);
+            assert_eq!(&round_trip(&change.inverse()), &change.inverse());
+            assert_eq!(&change.inverse().inverse(), change);
+        }
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let value = |s: &str| Some(s.to_string());
+        assert!(Change::try_from(model(EditField::Selection, None, value("Pick"))).is_err());
+        assert!(Change::try_from(model(EditField::Rating, None, value("Six"))).is_err());
+        assert!(Change::try_from(model(EditField::Hidden, value("yes"), value("no"))).is_err());
+        assert!(Change::try_from(model(EditField::Tag, value("a"), value("b"))).is_err());
+        assert!(Change::try_from(model(EditField::Tag, None, None)).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut picture = PictureData::default();
+        let changes = [
+            Change::Rating {
+                before: None,
+                after: Some(Rating::Three),
+            },
+            Change::Tag {
+                name: "b".to_string(),
+                added: true,
+            },
+            Change::Tag {
+                name: "a".to_string(),
+                added: true,
+            },
+        ];
+        for change in changes.iter() {
+            change.apply(&mut picture);
+        }
+        assert_eq!(picture.rating, Some(Rating::Three));
+        assert_eq!(picture.tags, ["a", "b"]);
+
+        for change in changes.iter().rev() {
+            change.inverse().apply(&mut picture);
+        }
+        assert_eq!(picture.rating, None);
+        assert!(picture.tags.is_empty());
+    }
+}
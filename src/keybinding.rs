@@ -0,0 +1,707 @@
+//! Keyboard shortcuts mapping key chords to the actions of the application
+//
+// The shortcuts start from one of the presets, which the user can change within
+// the `keybindings.toml` file of the configuration directory. Each action listed
+// within the file replaces all the chords of the preset for that action, like
+//
+// ```toml
+// preset = "lightroom"
+//
+// [bindings]
+// pick = ["p", "shift+p"]
+// zoom-reset = []
+// ```
+//
+// A chord bound to more than one action is a conflict, which leaves the chord
+// unbound rather than guessing which action was meant. The conflicts are listed
+// within the cheat sheet so they can be fixed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use camino::Utf8PathBuf;
+use entity::{Flag, Rating, Selection};
+use iced::keyboard::key::Named;
+use iced::keyboard::{self, Key};
+use iced::widget::{button, column, horizontal_space, row, scrollable, text};
+use iced::{Color, Element, Length};
+use serde::{Deserialize, Serialize};
+
+use crate::directory::DirectoryMessage;
+use crate::settings::config_directory;
+use crate::thumbnail::{Movement, ThumbnailMessage};
+use crate::{AppView, Message};
+
+const KEYBINDINGS_FILE: &str = "keybindings.toml";
+
+/// The names of the keys which don't produce a character.
+const NAMED_KEYS: [(&str, Named); 14] = [
+    ("left", Named::ArrowLeft),
+    ("right", Named::ArrowRight),
+    ("up", Named::ArrowUp),
+    ("down", Named::ArrowDown),
+    ("home", Named::Home),
+    ("end", Named::End),
+    ("pageup", Named::PageUp),
+    ("pagedown", Named::PageDown),
+    ("enter", Named::Enter),
+    ("escape", Named::Escape),
+    ("space", Named::Space),
+    ("tab", Named::Tab),
+    ("backspace", Named::Backspace),
+    ("delete", Named::Delete),
+];
+
+/// Something the user can do with a keyboard shortcut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveFirst,
+    MoveLast,
+    Open,
+    Close,
+    SelectAll,
+    Pick,
+    Ordinary,
+    Ignore,
+    ClearRating,
+    Rate1,
+    Rate2,
+    Rate3,
+    Rate4,
+    Rate5,
+    FlagRed,
+    FlagYellow,
+    FlagGreen,
+    FlagBlue,
+    FlagPurple,
+    Hide,
+    Show,
+    ToggleHidden,
+    Undo,
+    Redo,
+    ShowGrid,
+    ShowPreview,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    PreviousDirectory,
+    NextDirectory,
+    AddDirectory,
+    Import,
+    Export,
+    CheatSheet,
+}
+
+impl Action {
+    /// The message performing the action.
+    pub fn message(&self) -> Message {
+        match self {
+            Action::MoveLeft => ThumbnailMessage::Move(Movement::Left).into(),
+            Action::MoveRight => ThumbnailMessage::Move(Movement::Right).into(),
+            Action::MoveUp => ThumbnailMessage::Move(Movement::Up).into(),
+            Action::MoveDown => ThumbnailMessage::Move(Movement::Down).into(),
+            Action::MoveFirst => ThumbnailMessage::Move(Movement::First).into(),
+            Action::MoveLast => ThumbnailMessage::Move(Movement::Last).into(),
+            Action::Open => ThumbnailMessage::OpenCurrent.into(),
+            Action::Close => ThumbnailMessage::CloseCurrent.into(),
+            Action::SelectAll => ThumbnailMessage::SelectAll.into(),
+            Action::Pick => ThumbnailMessage::SetSelectionCurrent(Selection::Pick).into(),
+            Action::Ordinary => ThumbnailMessage::SetSelectionCurrent(Selection::Ordinary).into(),
+            Action::Ignore => ThumbnailMessage::SetSelectionCurrent(Selection::Ignore).into(),
+            Action::ClearRating => ThumbnailMessage::SetRatingCurrent(None).into(),
+            Action::Rate1 => ThumbnailMessage::SetRatingCurrent(Some(Rating::One)).into(),
+            Action::Rate2 => ThumbnailMessage::SetRatingCurrent(Some(Rating::Two)).into(),
+            Action::Rate3 => ThumbnailMessage::SetRatingCurrent(Some(Rating::Three)).into(),
+            Action::Rate4 => ThumbnailMessage::SetRatingCurrent(Some(Rating::Four)).into(),
+            Action::Rate5 => ThumbnailMessage::SetRatingCurrent(Some(Rating::Five)).into(),
+            Action::FlagRed => ThumbnailMessage::ToggleFlagCurrent(Flag::Red).into(),
+            Action::FlagYellow => ThumbnailMessage::ToggleFlagCurrent(Flag::Yellow).into(),
+            Action::FlagGreen => ThumbnailMessage::ToggleFlagCurrent(Flag::Green).into(),
+            Action::FlagBlue => ThumbnailMessage::ToggleFlagCurrent(Flag::Blue).into(),
+            Action::FlagPurple => ThumbnailMessage::ToggleFlagCurrent(Flag::Purple).into(),
+            Action::Hide => ThumbnailMessage::SetHiddenSelected(true).into(),
+            Action::Show => ThumbnailMessage::SetHiddenSelected(false).into(),
+            Action::ToggleHidden => ThumbnailMessage::ToggleDisplayHidden.into(),
+            Action::Undo => ThumbnailMessage::Undo.into(),
+            Action::Redo => ThumbnailMessage::Redo.into(),
+            Action::ShowGrid => Message::SetView(AppView::Grid),
+            Action::ShowPreview => Message::SetView(AppView::Preview),
+            Action::ZoomIn => ThumbnailMessage::ZoomIn.into(),
+            Action::ZoomOut => ThumbnailMessage::ZoomOut.into(),
+            Action::ZoomReset => ThumbnailMessage::ZoomReset.into(),
+            Action::PreviousDirectory => DirectoryMessage::DirectoryPrev.into(),
+            Action::NextDirectory => DirectoryMessage::DirectoryNext.into(),
+            Action::AddDirectory => DirectoryMessage::DirectoryAdd.into(),
+            Action::Import => DirectoryMessage::DirectoryImport.into(),
+            Action::Export => Message::SelectionExport,
+            Action::CheatSheet => Message::ToggleCheatSheet,
+        }
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Action::MoveLeft => "Move to the previous picture",
+            Action::MoveRight => "Move to the next picture",
+            Action::MoveUp => "Move up a row of the grid",
+            Action::MoveDown => "Move down a row of the grid",
+            Action::MoveFirst => "Move to the first picture",
+            Action::MoveLast => "Move to the last picture",
+            Action::Open => "Open the picture",
+            Action::Close => "Close the picture, or clear the selection",
+            Action::SelectAll => "Select all the pictures shown",
+            Action::Pick => "Pick",
+            Action::Ordinary => "Mark as ordinary",
+            Action::Ignore => "Ignore",
+            Action::ClearRating => "Clear the rating",
+            Action::Rate1 => "Rate one star",
+            Action::Rate2 => "Rate two stars",
+            Action::Rate3 => "Rate three stars",
+            Action::Rate4 => "Rate four stars",
+            Action::Rate5 => "Rate five stars",
+            Action::FlagRed => "Toggle the red flag",
+            Action::FlagYellow => "Toggle the yellow flag",
+            Action::FlagGreen => "Toggle the green flag",
+            Action::FlagBlue => "Toggle the blue flag",
+            Action::FlagPurple => "Toggle the purple flag",
+            Action::Hide => "Hide the selected pictures",
+            Action::Show => "Stop hiding the selected pictures",
+            Action::ToggleHidden => "Hide or show the hidden pictures",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::ShowGrid => "Show the grid",
+            Action::ShowPreview => "Show the preview",
+            Action::ZoomIn => "Zoom in on the preview",
+            Action::ZoomOut => "Zoom out of the preview",
+            Action::ZoomReset => "Fit the preview to the window",
+            Action::PreviousDirectory => "Open the previous directory",
+            Action::NextDirectory => "Open the next directory",
+            Action::AddDirectory => "Add a directory",
+            Action::Import => "Import a directory",
+            Action::Export => "Export the selected pictures",
+            Action::CheatSheet => "Show the keyboard shortcuts",
+        };
+        write!(f, "{description}")
+    }
+}
+
+/// A key pressed along with the modifiers held, like `ctrl+shift+z`.
+///
+/// Shift only forms part of the chord for letters and named keys, since the other
+/// characters already differ with shift held, like `+` and `=`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Chord {
+    key: ChordKey,
+    command: bool,
+    alt: bool,
+    shift: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ChordKey {
+    Character(String),
+    Named(Named),
+}
+
+impl Chord {
+    fn new(key: ChordKey, command: bool, alt: bool, shift: bool) -> Self {
+        let shift = shift
+            && match &key {
+                ChordKey::Character(c) => c.chars().all(char::is_alphabetic),
+                ChordKey::Named(_) => true,
+            };
+        Self {
+            key,
+            command,
+            alt,
+            shift,
+        }
+    }
+
+    /// The chord of a key pressed, where it can be bound to an action.
+    pub fn from_key(key: &Key, modifiers: keyboard::Modifiers) -> Option<Self> {
+        let key = match key.as_ref() {
+            Key::Named(named) => ChordKey::Named(named),
+            Key::Character(c) => ChordKey::Character(c.to_lowercase()),
+            Key::Unidentified => return None,
+        };
+        Some(Self::new(
+            key,
+            modifiers.command(),
+            modifiers.alt(),
+            modifiers.shift(),
+        ))
+    }
+}
+
+impl std::str::FromStr for Chord {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        // The plus key is written as `+`, or like `ctrl++` along with modifiers
+        let (modifiers, key) = if s == "+" {
+            ("", "+")
+        } else if let Some(modifiers) = s.strip_suffix("++") {
+            (modifiers, "+")
+        } else {
+            s.rsplit_once('+').unwrap_or(("", s.as_str()))
+        };
+
+        let (mut command, mut alt, mut shift) = (false, false, false);
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            match modifier {
+                "ctrl" | "cmd" => command = true,
+                "alt" => alt = true,
+                "shift" => shift = true,
+                _ => return Err(anyhow!("Unknown modifier {modifier} in {s}")),
+            }
+        }
+        let key = if let Some((_, named)) = NAMED_KEYS.iter().find(|(name, _)| *name == key) {
+            ChordKey::Named(*named)
+        } else if key.chars().count() == 1 {
+            ChordKey::Character(key.to_string())
+        } else {
+            return Err(anyhow!("Unknown key {key} in {s}"));
+        };
+        Ok(Self::new(key, command, alt, shift))
+    }
+}
+
+impl std::fmt::Display for Chord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.command {
+            write!(f, "ctrl+")?;
+        }
+        if self.alt {
+            write!(f, "alt+")?;
+        }
+        if self.shift {
+            write!(f, "shift+")?;
+        }
+        match &self.key {
+            ChordKey::Character(c) => write!(f, "{c}"),
+            ChordKey::Named(named) => {
+                let name = NAMED_KEYS
+                    .iter()
+                    .find(|(_, n)| n == named)
+                    .map_or("unknown", |(name, _)| name);
+                write!(f, "{name}")
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Chord {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Chord> for String {
+    fn from(chord: Chord) -> Self {
+        chord.to_string()
+    }
+}
+
+/// The shortcuts the bindings of the user start from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Moving with `hjkl` along with the arrow keys, picking with `p`, `o` and `i`
+    #[default]
+    Vim,
+    /// The shortcuts of the library module of Lightroom, picking with `p`, `u` and `x`
+    Lightroom,
+}
+
+impl Preset {
+    fn chords(&self) -> Vec<(Action, &'static [&'static str])> {
+        let shared: [(Action, &'static [&'static str]); 21] = [
+            (Action::MoveFirst, &["home"]),
+            (Action::Open, &["enter"]),
+            (Action::Close, &["escape"]),
+            (Action::SelectAll, &["ctrl+a"]),
+            (Action::ClearRating, &["0"]),
+            (Action::Rate1, &["1"]),
+            (Action::Rate2, &["2"]),
+            (Action::Rate3, &["3"]),
+            (Action::Rate4, &["4"]),
+            (Action::Rate5, &["5"]),
+            (Action::FlagRed, &["6"]),
+            (Action::FlagYellow, &["7"]),
+            (Action::FlagGreen, &["8"]),
+            (Action::FlagBlue, &["9"]),
+            (Action::FlagPurple, &["v"]),
+            (Action::Hide, &["ctrl+h"]),
+            (Action::Show, &["ctrl+shift+h"]),
+            (Action::ToggleHidden, &["shift+h"]),
+            (Action::ShowGrid, &["g"]),
+            (Action::ShowPreview, &["e"]),
+            (Action::AddDirectory, &["ctrl+o"]),
+        ];
+        let preset: Vec<(Action, &'static [&'static str])> = match self {
+            Preset::Vim => vec![
+                (Action::MoveLeft, &["h", "left"]),
+                (Action::MoveRight, &["l", "right"]),
+                (Action::MoveUp, &["k", "up"]),
+                (Action::MoveDown, &["j", "down"]),
+                (Action::MoveLast, &["end", "shift+g"]),
+                (Action::Pick, &["p"]),
+                (Action::Ordinary, &["o"]),
+                (Action::Ignore, &["i"]),
+                (Action::Undo, &["ctrl+z", "u"]),
+                (Action::Redo, &["ctrl+shift+z", "ctrl+r"]),
+                (Action::ZoomIn, &["=", "+"]),
+                (Action::ZoomOut, &["-"]),
+                (Action::ZoomReset, &["f"]),
+                (Action::PreviousDirectory, &["[", "pageup"]),
+                (Action::NextDirectory, &["]", "pagedown"]),
+                (Action::Import, &["ctrl+i"]),
+                (Action::Export, &["ctrl+e"]),
+                (Action::CheatSheet, &["?"]),
+            ],
+            Preset::Lightroom => vec![
+                (Action::MoveLeft, &["left"]),
+                (Action::MoveRight, &["right"]),
+                (Action::MoveUp, &["up"]),
+                (Action::MoveDown, &["down"]),
+                (Action::MoveLast, &["end"]),
+                (Action::Pick, &["p"]),
+                (Action::Ordinary, &["u"]),
+                (Action::Ignore, &["x"]),
+                (Action::Undo, &["ctrl+z"]),
+                (Action::Redo, &["ctrl+shift+z"]),
+                (Action::ZoomIn, &["ctrl+=", "ctrl++"]),
+                (Action::ZoomOut, &["ctrl+-"]),
+                (Action::ZoomReset, &["z"]),
+                (Action::PreviousDirectory, &["ctrl+up"]),
+                (Action::NextDirectory, &["ctrl+down"]),
+                (Action::Import, &["ctrl+shift+i"]),
+                (Action::Export, &["ctrl+shift+e"]),
+                (Action::CheatSheet, &["ctrl+/"]),
+            ],
+        };
+        shared.into_iter().chain(preset).collect()
+    }
+
+    /// The chords bound to each action by the preset.
+    fn bindings(&self) -> BTreeMap<Action, Vec<Chord>> {
+        self.chords()
+            .into_iter()
+            .map(|(action, chords)| {
+                let chords = chords
+                    .iter()
+                    .map(|c| c.parse().expect("The chords of the presets are valid"))
+                    .collect();
+                (action, chords)
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Preset::Vim => write!(f, "Vim"),
+            Preset::Lightroom => write!(f, "Lightroom"),
+        }
+    }
+}
+
+/// The keyboard shortcuts chosen by the user.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeybindingSettings {
+    /// The shortcuts used for the actions which aren't within the bindings.
+    pub preset: Preset,
+    /// The chords of each action, replacing those of the preset.
+    pub bindings: BTreeMap<Action, Vec<Chord>>,
+}
+
+impl KeybindingSettings {
+    pub fn path() -> Result<Utf8PathBuf, Error> {
+        Ok(config_directory()?.join(KEYBINDINGS_FILE))
+    }
+
+    /// Load the shortcuts from the configuration file, using the default preset
+    /// when the file doesn't exist.
+    pub fn load() -> Result<Self, Error> {
+        let path = Self::path()?;
+        if !path.try_exists()? {
+            tracing::debug!("No keybindings found at {path}, using defaults");
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(&path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum KeymapError {
+    LoadFailed(Arc<anyhow::Error>),
+    /// The chord is bound to all of the actions, so is left unbound
+    Conflict {
+        chord: Chord,
+        actions: Vec<Action>,
+    },
+}
+
+impl From<anyhow::Error> for KeymapError {
+    fn from(error: anyhow::Error) -> Self {
+        KeymapError::LoadFailed(Arc::new(error))
+    }
+}
+
+impl std::fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeymapError::LoadFailed(e) => write!(f, "Loading the keybindings failed: {e}"),
+            KeymapError::Conflict { chord, actions } => {
+                // The actions are named as they are within the configuration file
+                let actions = actions
+                    .iter()
+                    .map(|a| serde_json::to_string(a).unwrap_or_default())
+                    .collect::<Vec<_>>();
+                write!(f, "{chord} is bound to {}", actions.join(" and "))
+            }
+        }
+    }
+}
+
+/// The actions bound to each chord
+#[derive(Debug)]
+pub struct Keymap {
+    preset: Preset,
+    // The chords of each action, including those which conflict
+    chords: BTreeMap<Action, Vec<Chord>>,
+    bindings: HashMap<Chord, Action>,
+    // The problems with the keybindings of the user, shown within the cheat sheet
+    errors: Vec<KeymapError>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(&KeybindingSettings::default())
+    }
+}
+
+impl Keymap {
+    pub fn new(settings: &KeybindingSettings) -> Self {
+        let mut chords = settings.preset.bindings();
+        chords.extend(settings.bindings.clone());
+
+        let mut actions: HashMap<&Chord, Vec<Action>> = HashMap::new();
+        for (action, chords) in chords.iter() {
+            for chord in chords.iter() {
+                actions.entry(chord).or_default().push(*action);
+            }
+        }
+        let mut bindings = HashMap::new();
+        let mut errors = vec![];
+        for (chord, mut actions) in actions.into_iter() {
+            actions.dedup();
+            if let [action] = actions.as_slice() {
+                bindings.insert(chord.clone(), *action);
+            } else {
+                tracing::warn!("The chord {chord} is bound to the actions {actions:?}");
+                errors.push(KeymapError::Conflict {
+                    chord: chord.clone(),
+                    actions,
+                });
+            }
+        }
+        errors.sort_by_key(|e| e.to_string());
+
+        Self {
+            preset: settings.preset,
+            chords,
+            bindings,
+            errors,
+        }
+    }
+
+    /// Load the keybindings of the user, falling back to the default preset where
+    /// they can't be loaded.
+    pub fn load() -> Self {
+        match KeybindingSettings::load() {
+            Ok(settings) => Self::new(&settings),
+            Err(e) => {
+                tracing::error!("Unable to load the keybindings: {e}");
+                let mut keymap = Self::default();
+                keymap.errors.insert(0, e.into());
+                keymap
+            }
+        }
+    }
+
+    /// The action bound to the key pressed.
+    pub fn action(&self, key: &Key, modifiers: keyboard::Modifiers) -> Option<Action> {
+        self.bindings
+            .get(&Chord::from_key(key, modifiers)?)
+            .copied()
+    }
+
+    /// The cheat sheet listing the active bindings.
+    pub fn view(&self) -> Element<'_, Message> {
+        let errors = self.errors.iter().map(|e| {
+            text(e.to_string())
+                .color(Color::from_rgb(0.8, 0., 0.))
+                .into()
+        });
+        let bindings = self.chords.iter().filter_map(|(action, chords)| {
+            let chords: Vec<String> = chords
+                .iter()
+                .filter(|c| self.bindings.contains_key(c))
+                .map(|c| c.to_string())
+                .collect();
+            if chords.is_empty() {
+                return None;
+            }
+            Some(
+                row![
+                    text(action.to_string()).width(Length::Fill),
+                    text(chords.join(", ")).width(Length::Fixed(200.)),
+                ]
+                .into(),
+            )
+        });
+        let path = KeybindingSettings::path()
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        column![
+            row![
+                text(format!("Keyboard shortcuts: {}", self.preset)).size(20),
+                horizontal_space(),
+                button(text("Close")).on_press(Message::ToggleCheatSheet),
+            ]
+            .align_y(iced::Alignment::Center),
+            text(format!("The shortcuts can be changed within {path}")).size(12),
+            column(errors).spacing(5),
+            scrollable(column(bindings).spacing(5)),
+        ]
+        .spacing(10)
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chord(s: &str) -> Chord {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_chord() {
+        let redo = chord("Ctrl+Shift+Z");
+        assert_eq!(
+            redo,
+            Chord::new(ChordKey::Character("z".to_string()), true, false, true)
+        );
+        assert_eq!(redo.to_string(), "ctrl+shift+z");
+        assert_eq!(chord("cmd+a"), chord("ctrl+a"));
+        assert_eq!(
+            chord("alt+pageup"),
+            Chord::new(ChordKey::Named(Named::PageUp), false, true, false)
+        );
+        assert_eq!(chord("alt+pageup").to_string(), "alt+pageup");
+    }
+
+    #[test]
+    fn test_parse_plus() {
+        let plus = ChordKey::Character("+".to_string());
+        assert_eq!(chord("+"), Chord::new(plus.clone(), false, false, false));
+        assert_eq!(chord("ctrl++"), Chord::new(plus, true, false, false));
+        assert_eq!(chord("ctrl++").to_string(), "ctrl++");
+    }
+
+    #[test]
+    fn test_parse_shift() {
+        // Shift only changes the chord of letters and named keys
+        assert_eq!(chord("shift+1"), chord("1"));
+        assert_ne!(chord("shift+g"), chord("g"));
+        assert_ne!(chord("shift+tab"), chord("tab"));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("".parse::<Chord>().is_err());
+        assert!("hyper+a".parse::<Chord>().is_err());
+        assert!("ctrl+pagedn".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn test_chord_from_key() {
+        let key = Key::Character("G".into());
+        assert_eq!(
+            Chord::from_key(&key, keyboard::Modifiers::SHIFT),
+            Some(chord("shift+g"))
+        );
+        assert_eq!(
+            Chord::from_key(&Key::Unidentified, keyboard::Modifiers::SHIFT),
+            None
+        );
+    }
+
+    #[test]
+    fn test_presets_without_conflicts() {
+        for preset in [Preset::Vim, Preset::Lightroom] {
+            let keymap = Keymap::new(&KeybindingSettings {
+                preset,
+                bindings: BTreeMap::new(),
+            });
+            assert!(keymap.errors.is_empty(), "{preset}: {:?}", keymap.errors);
+        }
+    }
+
+    #[test]
+    fn test_replace_preset() {
+        let settings: KeybindingSettings = toml::from_str(
+            r#"
+            preset = "lightroom"
+
+            [bindings]
+            pick = ["y", "shift+y"]
+            "#,
+        )
+        .unwrap();
+        let keymap = Keymap::new(&settings);
+        assert!(keymap.errors.is_empty());
+        assert_eq!(keymap.bindings.get(&chord("shift+y")), Some(&Action::Pick));
+        assert_eq!(keymap.bindings.get(&chord("p")), None);
+        assert_eq!(keymap.bindings.get(&chord("x")), Some(&Action::Ignore));
+    }
+
+    #[test]
+    fn test_conflict() {
+        let settings = KeybindingSettings {
+            preset: Preset::Vim,
+            bindings: BTreeMap::from([(Action::Pick, vec![chord("h")])]),
+        };
+        let keymap = Keymap::new(&settings);
+        // The conflicting chord is left unbound, leaving the other chords
+        assert_eq!(keymap.bindings.get(&chord("h")), None);
+        assert_eq!(keymap.bindings.get(&chord("left")), Some(&Action::MoveLeft));
+        let [KeymapError::Conflict {
+            chord: conflict,
+            actions,
+        }] = keymap.errors.as_slice()
+        else {
+            panic!("Expected a single conflict, got {:?}", keymap.errors);
+        };
+        assert_eq!(conflict, &chord("h"));
+        assert_eq!(actions, &[Action::MoveLeft, Action::Pick]);
+    }
+}
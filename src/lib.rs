@@ -1,38 +1,53 @@
 use anyhow::Error;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
-use data::{update_thumbnails, Progress};
+use data::{query_search_pictures, update_missing_metadata, update_thumbnails, Progress};
 use entity::directory as entity_directory;
-use entity::picture as entity_picture;
-use entity::Selection;
-use futures::StreamExt;
-use iced::keyboard::key::Named;
 use iced::keyboard::{self, Key};
-use iced::widget::{button, column, container, row, text};
-use iced::Color;
+use iced::widget::{button, column, container, horizontal_space, opaque, row, stack, text};
 use iced::Event::Keyboard;
-use iced::Theme;
-use iced::{event, task, Element, Length, Subscription, Task};
+use iced::{event, task, window, Element, Length, Subscription, Task};
 use sea_orm::entity::*;
 use sea_orm::prelude::*;
 use sea_orm::ActiveValue;
 use sea_orm::DatabaseConnection;
 use uuid::Uuid;
 
+pub mod backup;
+mod capture_time;
 mod data;
+pub mod database;
 pub mod directory;
+mod hash;
 mod import;
+mod integrity;
+mod journal;
+mod keybinding;
 // The menu is not currently working with the iced master branch
 mod menu;
+mod persistence;
 pub mod picture;
+pub mod settings;
+mod sidecar;
+mod tag;
 pub mod telemetry;
 mod thumbnail;
 mod widget;
 
+use backup::{automatic_snapshot, BackupMessage, BackupView, SnapshotReason};
+use database::{DatabaseError, DatabaseReport};
 use directory::{DirectoryMessage, DirectoryView};
+use integrity::{IntegrityMessage, IntegrityView};
+use journal::Edit;
+use keybinding::{Action, Keymap};
+use persistence::{Persistence, PersistenceMessage};
 use picture::PictureData;
+use settings::Settings;
 use thumbnail::{ThumbnailMessage, ThumbnailView};
 
+/// The application identifier, used for the data and configuration directories.
+pub const APP_ID: &str = "com.malramsay.Decimator";
+
 #[derive(Debug, Clone)]
 pub enum AppMessage {}
 
@@ -44,7 +59,9 @@ impl From<AppMessage> for Message {
 
 #[derive(Debug, Clone)]
 pub enum DatabaseMessage {
-    UpdateImage(PictureData),
+    // The pictures changed by a single action of the user, along with the edits to
+    // record within the journal
+    UpdateImages((Vec<PictureData>, Vec<Edit>)),
     LoadThumbnail(Uuid),
 }
 
@@ -59,26 +76,9 @@ pub struct DirectoryDataDB {
     directory: Utf8PathBuf,
     parent_id: Option<Uuid>,
     children: Vec<Uuid>,
+    expanded: bool,
 }
 
-fn directory_style(theme: &Theme, status: button::Status) -> button::Style {
-    let palette = theme.extended_palette();
-
-    let mut style = match status {
-        button::Status::Active => {
-            button::Style::default().with_background(palette.background.base.color)
-        }
-        button::Status::Hovered => {
-            button::Style::default().with_background(palette.background.strong.color)
-        }
-        button::Status::Pressed => {
-            button::Style::default().with_background(palette.primary.base.color)
-        }
-        _ => button::primary(theme, status),
-    };
-    style.text_color = Color::WHITE;
-    style
-}
 impl DirectoryDataDB {
     fn new(model: entity::directory::Model, children: Vec<entity::directory::Model>) -> Self {
         Self {
@@ -86,6 +86,7 @@ impl DirectoryDataDB {
             directory: model.directory.into(),
             parent_id: model.parent_id,
             children: children.into_iter().map(|i| i.id).collect(),
+            expanded: model.expanded,
         }
     }
     fn into_active(self) -> entity::directory::ActiveModel {
@@ -93,6 +94,7 @@ impl DirectoryDataDB {
             id: ActiveValue::Unchanged(self.id),
             directory: ActiveValue::Set(self.directory.to_string()),
             parent_id: ActiveValue::Set(self.parent_id),
+            expanded: ActiveValue::Set(self.expanded),
         }
     }
     pub fn strip_prefix(&self) -> &Utf8Path {
@@ -100,17 +102,6 @@ impl DirectoryDataDB {
         // .strip_prefix(dirs::home_dir().unwrap())
         // .unwrap()
     }
-    pub fn view(&self, selected: bool) -> Element<'_, Message> {
-        let message = if selected {
-            None
-        } else {
-            Some(DirectoryMessage::SelectDirectory(self.clone()).into())
-        };
-        button(text(self.strip_prefix().as_str()).width(Length::Fill))
-            .on_press_maybe(message)
-            .style(directory_style)
-            .into()
-    }
 }
 
 impl PartialOrd for DirectoryDataDB {
@@ -126,12 +117,13 @@ impl From<entity::directory::Model> for DirectoryDataDB {
             directory: value.directory.into(),
             parent_id: value.parent_id,
             children: vec![],
+            expanded: value.expanded,
         }
     }
 }
 
-async fn get_parent_directory(
-    db: &DatabaseConnection,
+async fn get_parent_directory<C: ConnectionTrait>(
+    db: &C,
     current_dir: &Utf8PathBuf,
 ) -> Result<Option<Uuid>, Error> {
     let d = entity::directory::Entity::find()
@@ -152,6 +144,7 @@ async fn get_parent_directory(
             id,
             directory: current_dir.clone(),
             children: vec![],
+            expanded: false,
         };
         tracing::debug!("Adding directory {db_dir:?} from parent: {parent:?}");
         entity::directory::Entity::insert(db_dir.into_active())
@@ -166,24 +159,8 @@ async fn get_parent_directory(
 }
 
 async fn update_database(database: &DatabaseConnection) -> Result<(), Error> {
-    let mut stream = entity_picture::Entity::find().stream(database).await?;
-    while let Some(p) = stream.next().await {
-        let p = p.expect("Value not loaded correctly.");
-        let directory: Utf8PathBuf = p.directory.clone().into();
-        tracing::debug!("Updating Picture: {}", p.filename);
-        let d = get_parent_directory(database, &directory).await?;
-        tracing::debug!("Found Parent Directory: {:?}", d);
-        let picture_update = entity::picture::ActiveModel {
-            id: ActiveValue::Unchanged(p.id),
-            directory_id: ActiveValue::Set(d),
-            ..Default::default()
-        };
-
-        entity_picture::Entity::update(picture_update)
-            .exec(database)
-            .await
-            .inspect_err(|e| tracing::error!("{e:?}"))?;
-    }
+    let updated = update_missing_metadata(database).await?;
+    tracing::info!("Updated the metadata of {updated} pictures");
     Ok(())
 }
 
@@ -195,6 +172,9 @@ pub enum Message {
     Thumbnail(ThumbnailMessage),
     Database(DatabaseMessage),
     Directory(DirectoryMessage),
+    Integrity(IntegrityMessage),
+    Backup(BackupMessage),
+    Persistence(PersistenceMessage),
     App(AppMessage),
     UpdateThumbnails(bool),
     // Signal to emit when we want to export, this creates the export dialog
@@ -202,8 +182,14 @@ pub enum Message {
     SelectionExport,
     // Contains the path where the files are being exported to
     SelectionPrint,
+    SearchInput(String),
+    Search,
     Ignore,
     Update,
+    DatabaseReportClose,
+    // A key pressed which wasn't used by any of the widgets
+    KeyPressed((Key, keyboard::Modifiers)),
+    ToggleCheatSheet,
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -233,7 +219,22 @@ pub struct App {
     app_view: AppView,
     thumbnail_view: ThumbnailView,
     directory_view: DirectoryView,
+    integrity_view: IntegrityView,
+    backup_view: BackupView,
+    // The changes to pictures which are yet to be written to the database
+    persistence: Persistence,
     thumbnail_import: DownloadState,
+    // The search of the whole library entered by the user
+    search: String,
+    // The settings of the user, loaded once when the application starts
+    settings: Settings,
+    // The actions bound to the keys pressed
+    keymap: Keymap,
+    // Whether the keyboard shortcuts are shown over the application
+    cheat_sheet: bool,
+    // The outcome of preparing the database on startup, where the database can only
+    // be used once this has succeeded.
+    database_status: Result<DatabaseReport, DatabaseError>,
 }
 
 impl App {
@@ -242,13 +243,43 @@ impl App {
     }
 
     #[tracing::instrument(name = "Initialising App")]
-    pub fn new(database: DatabaseConnection) -> Self {
+    pub fn new(
+        database: DatabaseConnection,
+        database_status: Result<DatabaseReport, DatabaseError>,
+    ) -> Self {
         Self {
             database: database.clone(),
             directory_view: DirectoryView::new(database.clone()),
+            integrity_view: IntegrityView::new(database.clone()),
+            backup_view: BackupView::new(database.clone()),
+            persistence: Persistence::new(database.clone()),
             app_view: Default::default(),
             thumbnail_view: ThumbnailView::new(database, 20.try_into().unwrap()),
             thumbnail_import: Default::default(),
+            search: String::new(),
+            settings: Settings::load().unwrap_or_else(|e| {
+                tracing::error!("Unable to load the settings, using the defaults: {e}");
+                Settings::default()
+            }),
+            keymap: Keymap::load(),
+            cheat_sheet: false,
+            database_status,
+        }
+    }
+
+    /// The tasks to run once the application has started.
+    pub fn startup(&self) -> Task<Message> {
+        let database = self.database.clone();
+        let backup = self.settings.backup.clone();
+        match &self.database_status {
+            Ok(_) => Task::batch([
+                Task::done(DirectoryMessage::QueryDirectories).map(Message::Directory),
+                Task::perform(
+                    async move { automatic_snapshot(&database, SnapshotReason::Daily, &backup).await },
+                    |_| Message::Ignore,
+                ),
+            ]),
+            Err(_) => Task::none(),
         }
     }
 
@@ -256,12 +287,70 @@ impl App {
     pub fn update(&mut self, message: Message) -> Task<Message> {
         let database = self.database.clone();
         match message {
+            Message::Database(DatabaseMessage::UpdateImages((pictures, edits))) => {
+                let persist = self.persistence.queue(pictures.clone(), edits);
+                if !self.settings.sidecar.write {
+                    return persist;
+                }
+                let naming = self.settings.sidecar.naming;
+                let sidecar = Task::perform(
+                    async move {
+                        // A failure with one sidecar shouldn't stop the others being written
+                        tokio::task::spawn_blocking(move || {
+                            for picture in pictures.iter() {
+                                if let Err(e) = sidecar::write_sidecar(picture, naming) {
+                                    tracing::warn!(
+                                        "Unable to write sidecar of {}: {e}",
+                                        picture.filepath
+                                    );
+                                }
+                            }
+                        })
+                        .await?;
+                        Ok(())
+                    },
+                    |result: Result<(), Error>| {
+                        if let Err(e) = result {
+                            tracing::warn!("Unable to write sidecars: {e}");
+                        }
+                        Message::Ignore
+                    },
+                );
+                Task::batch([persist, sidecar])
+            }
             Message::Database(_m) => Task::none(),
             Message::Thumbnail(m) => self.thumbnail_view.update(m),
             Message::App(_m) => Task::none(),
-            Message::Directory(m) => self.directory_view.update(m),
+            Message::Directory(m) => self.directory_view.update(m, &self.settings.backup),
+            Message::Integrity(m) => self.integrity_view.update(m, &self.settings.backup),
+            // The restored database replaces everything loaded from the previous one
+            Message::Backup(BackupMessage::Restored(Ok(restored))) => {
+                *self = App::new(restored.database, restored.status);
+                match restored.failed {
+                    // The library was opened again as it was, and the failure is shown
+                    // along with the snapshots
+                    Some(e) => Task::batch([
+                        self.startup(),
+                        Task::done(BackupMessage::ShowSnapshots.into()),
+                        Task::done(BackupMessage::Restored(Err(e)).into()),
+                    ]),
+                    None => self.startup(),
+                }
+            }
+            Message::Backup(m) => self.backup_view.update(m, &self.settings.backup),
+            Message::Persistence(m) => {
+                // Saving changes the picks counted within the directories
+                let saved = matches!(m, PersistenceMessage::Saved((_, Ok(()))));
+                let task = self.persistence.update(m);
+                if saved {
+                    Task::batch([task, Task::done(DirectoryMessage::QueryCounts.into())])
+                } else {
+                    task
+                }
+            }
             Message::SetView(view) => {
                 self.app_view = view;
+                self.thumbnail_view.set_preview(view == AppView::Preview);
                 Task::none()
             }
             Message::ThumbnailUpdate(new_progress) => {
@@ -293,7 +382,11 @@ impl App {
             }
             // Modify Thumbnail filters
             Message::SelectionExport => {
-                let items: Vec<_> = self.thumbnail_view.get_view().cloned().collect();
+                let items: Vec<_> = self
+                    .thumbnail_view
+                    .get_multiple_or_view()
+                    .cloned()
+                    .collect();
                 Task::perform(
                     async move {
                         let dir: Utf8PathBuf = rfd::AsyncFileDialog::new()
@@ -318,6 +411,41 @@ impl App {
                 )
             }
             Message::SelectionPrint => Task::none(),
+            Message::SearchInput(search) => {
+                self.search = search;
+                Task::none()
+            }
+            Message::Search => {
+                let search = self.search.clone();
+                Task::perform(
+                    async move { query_search_pictures(&database, &search).await },
+                    |result| match result {
+                        Ok(thumbnails) => ThumbnailMessage::SetThumbnails(thumbnails).into(),
+                        Err(e) => {
+                            tracing::error!("Unable to search pictures: {e}");
+                            Message::Ignore
+                        }
+                    },
+                )
+            }
+            Message::DatabaseReportClose => {
+                if let Ok(report) = &mut self.database_status {
+                    *report = DatabaseReport::default();
+                }
+                Task::none()
+            }
+            Message::KeyPressed((key, modifiers)) => match self.keymap.action(&key, modifiers) {
+                Some(Action::Close) if self.cheat_sheet => {
+                    self.cheat_sheet = false;
+                    Task::none()
+                }
+                Some(action) => self.update(action.message()),
+                None => Task::none(),
+            },
+            Message::ToggleCheatSheet => {
+                self.cheat_sheet = !self.cheat_sheet;
+                Task::none()
+            }
             Message::Ignore => Task::none(),
             Message::Update => {
                 let database = self.database.clone();
@@ -329,6 +457,16 @@ impl App {
     }
 
     pub fn view(&self) -> Element<Message> {
+        // Without a usable database there is nothing else we can show
+        let database_report = match &self.database_status {
+            Ok(report) => report,
+            Err(e) => {
+                return container(text(e.to_string()))
+                    .center(Length::Fill)
+                    .padding(40)
+                    .into();
+            }
+        };
         let content: Element<Message> = row![
             self.directory_view.view(),
             match self.app_view {
@@ -348,6 +486,60 @@ impl App {
             }
         ]
         .into();
+        // The import plan is shown over the top of the application until it has been
+        // confirmed or cancelled, with the report shown once the import has finished.
+        // The keyboard shortcuts are shown above everything else while asked for.
+        let content: Element<Message> = if self.cheat_sheet {
+            let cheat_sheet = container(self.keymap.view())
+                .style(container::rounded_box)
+                .padding(20);
+            stack![content, opaque(container(cheat_sheet).padding(40))].into()
+        } else if let Some(plan) = &self.directory_view.import_plan {
+            stack![content, opaque(container(plan.view()).padding(40))].into()
+        } else if let Some(report) = &self.directory_view.import_report {
+            stack![content, opaque(container(report.view()).padding(40))].into()
+        } else if let Some(error) = &self.directory_view.import_error {
+            let notice = container(
+                column![
+                    text(error.to_string()),
+                    row![
+                        horizontal_space(),
+                        button(text("Close")).on_press(DirectoryMessage::ImportErrorClose.into())
+                    ]
+                ]
+                .spacing(10),
+            )
+            .style(container::rounded_box)
+            .padding(20);
+            stack![content, opaque(container(notice).center(Length::Fill))].into()
+        } else if self.backup_view.snapshots.is_some() {
+            let snapshots = container(self.backup_view.view().map(Message::Backup)).padding(40);
+            stack![content, opaque(snapshots)].into()
+        } else if self.integrity_view.report.is_some() {
+            let report = container(self.integrity_view.view().map(Message::Integrity)).padding(40);
+            stack![content, opaque(report)].into()
+        } else if !database_report.is_empty() {
+            let notice = container(
+                column![
+                    text(database_report.to_string()),
+                    row![
+                        horizontal_space(),
+                        button(text("Close")).on_press(Message::DatabaseReportClose)
+                    ]
+                ]
+                .spacing(10),
+            )
+            .style(container::rounded_box)
+            .padding(20);
+            stack![content, opaque(container(notice).center(Length::Fill))].into()
+        } else if self.directory_view.missing.is_some() {
+            let prompt = container(self.directory_view.missing_view())
+                .center(Length::Fill)
+                .padding(40);
+            stack![content, opaque(prompt)].into()
+        } else {
+            content
+        };
         container(content)
             .center_x(Length::Fill)
             .center_y(Length::Fill)
@@ -355,32 +547,20 @@ impl App {
     }
 
     pub fn subscription(&self) -> Subscription<Message> {
-        let keyboard_sub = event::listen_with(|event, _, _| match event {
-            Keyboard(keyboard::Event::KeyPressed { key, .. }) => {
-                match key.as_ref() {
-                    Key::Character("h") | Key::Named(Named::ArrowLeft) => {
-                        Some(ThumbnailMessage::Prev.into())
-                    }
-                    Key::Character("l") | Key::Named(Named::ArrowRight) => {
-                        Some(ThumbnailMessage::Next.into())
-                    }
-                    // TODO: Keyboard Navigation of directories
-                    // KeyCode::H | KeyCode::Left => Some(Message::DirectoryPrev),
-                    // KeyCode::H | KeyCode::Left => Some(Message::DirectoryNext),
-                    Key::Character("p") => {
-                        Some(ThumbnailMessage::SetSelectionCurrent(Selection::Pick).into())
-                    }
-                    Key::Character("o") => {
-                        Some(ThumbnailMessage::SetSelectionCurrent(Selection::Ordinary).into())
-                    }
-                    Key::Character("i") => {
-                        Some(ThumbnailMessage::SetSelectionCurrent(Selection::Ignore).into())
-                    }
-                    _ => None,
-                }
+        let keyboard_sub = event::listen_with(|event, status, _| match event {
+            // The keys are left for the text inputs while they are focused
+            Keyboard(keyboard::Event::KeyPressed { key, modifiers, .. })
+                if status == event::Status::Ignored =>
+            {
+                Some(Message::KeyPressed((key, modifiers)))
+            }
+            Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+                Some(ThumbnailMessage::ModifiersChanged(modifiers).into())
             }
             _ => None,
         });
-        keyboard_sub
+        // The pending changes are written before the window is closed
+        let close_sub = window::close_requests().map(|_| PersistenceMessage::Close.into());
+        Subscription::batch([keyboard_sub, close_sub, self.persistence.subscription()])
     }
 }
@@ -1,13 +1,9 @@
-const APP_ID: &str = "com.malramsay.Decimator";
-
-use decimator::directory::DirectoryMessage;
+use decimator::database::{
+    connect, data_directory, database_path, prepare_database, DatabaseError,
+};
 use decimator::telemetry::{get_subscriber_terminal, init_subscriber};
-use decimator::{App, Message};
-use futures::StreamExt;
-use iced::Task;
-use sea_orm::entity::*;
-use sea_orm::query::*;
-use sea_orm::{ConnectOptions, Database, EntityTrait};
+use decimator::{App, APP_ID};
+use sea_orm::DatabaseConnection;
 
 fn main() -> Result<(), iced::Error> {
     // Configure tracing information
@@ -15,40 +11,49 @@ fn main() -> Result<(), iced::Error> {
     init_subscriber(subscriber);
 
     // Set up the database we are running from
-    let mut path = dirs::data_local_dir().expect("Unable to find local data dir");
-    path.push(crate::APP_ID);
+    let path = data_directory().expect("Unable to find local data dir");
     std::fs::create_dir_all(&path).expect("Could not create directory.");
-    let database_path = format!("sqlite://{}/database.db?mode=rwc", path.display());
-    dbg!(&database_path);
-
-    let mut connection_options = ConnectOptions::new(database_path);
-    // The minimum number of connections is rather important. There are cases within the application where
-    // we have multiple connections open simultaneously to handle the streaming of data from the database
-    // while performing operations on the data. This doesn't work if we don't increase the minimum number
-    // of connections resulting in a lock on the connections.
-    connection_options.max_connections(20).min_connections(4);
-    tracing::debug!("Connection Options: {:?}", connection_options);
-    let handle = Database::connect(connection_options);
+    let database_path = database_path().expect("Unable to find local data dir");
+    tracing::debug!("Using the database at {database_path}");
 
-    let connection = {
+    // Problems with the database are shown within the application rather than
+    // stopping it from starting.
+    let (connection, status) = {
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .worker_threads(1)
             .enable_all()
             .build()
             .unwrap();
-        runtime
-            .block_on(handle)
-            .expect("Unable to initialise sqlite database")
+        runtime.block_on(async {
+            match connect(&database_path).await {
+                Ok(connection) => {
+                    let status = prepare_database(&connection).await;
+                    (connection, status)
+                }
+                Err(e) => (
+                    DatabaseConnection::Disconnected,
+                    Err(DatabaseError::from(e)),
+                ),
+            }
+        })
     };
+    match &status {
+        Ok(report) if !report.is_empty() => tracing::info!("{report}"),
+        Ok(_) => {}
+        Err(e) => tracing::error!("{e}"),
+    }
 
-    let app = App::new(connection);
+    let app = App::new(connection, status);
 
     iced::application("Decimator", App::update, App::view)
         .subscription(App::subscription)
+        .window(iced::window::Settings {
+            // Closing waits for the changes to pictures to be saved
+            exit_on_close_request: false,
+            ..Default::default()
+        })
         .run_with(|| {
-            (
-                app,
-                Task::done(DirectoryMessage::QueryDirectories).map(Message::Directory),
-            )
+            let startup = app.startup();
+            (app, startup)
         })
 }
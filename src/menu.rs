@@ -1,4 +1,6 @@
-use iced::widget::{button, column, horizontal_space, progress_bar, row, text, toggler, Button};
+use iced::widget::{
+    button, column, horizontal_space, progress_bar, row, text, text_input, toggler, Button,
+};
 use iced::{Element, Length};
 
 use crate::thumbnail::ThumbnailMessage;
@@ -23,10 +25,18 @@ pub fn menu_view(data: &App) -> Element<'_, Message> {
 
     let thumbnails = button("Generate Thumbnails").on_press(Message::UpdateThumbnails(false));
 
+    let search = text_input("Search library", &data.search)
+        .on_input(Message::SearchInput)
+        .on_submit(Message::Search)
+        .width(Length::Fixed(240.));
+
     let tabs = row!(
         Button::new(text("Preview")).on_press(Message::SetView(AppView::Preview)),
         Button::new(text("Grid")).on_press(Message::SetView(AppView::Grid)),
         Button::new("Update").on_press(Message::Update),
+        Button::new("Shortcuts").on_press(Message::ToggleCheatSheet),
+        data.integrity_view.menu_view(),
+        data.backup_view.menu_view(),
     )
     .padding(10);
     let tabs = if let DownloadState::Downloading { progress, .. } = data.thumbnail_import {
@@ -37,6 +47,8 @@ pub fn menu_view(data: &App) -> Element<'_, Message> {
     row!(
         tabs,
         horizontal_space(),
+        data.persistence.menu_view(),
+        search,
         thumbnails,
         menu.map(Message::Thumbnail)
     )
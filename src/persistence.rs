@@ -0,0 +1,357 @@
+//! Writing the changes made to pictures through to the database
+//
+// Culling a directory makes many small changes in quick succession, so rather than
+// writing each change as it is made, the latest state of each changed picture is
+// kept until it has been written. Only a single write is in flight at a time, with
+// the changes made while it is running collected into the next batch, so each
+// batch is written within a single transaction. The edits of each action are
+// recorded within the journal by the same transaction as the pictures they change,
+// so the journal can't get ahead of or fall behind the pictures.
+//
+// Another connection holding a lock on the database makes the write fail with
+// SQLITE_BUSY, which is retried a few times before giving up. The changes of a
+// failed write are kept to try again later, and are written before the application
+// closes.
+//
+// Work which replaces the database, like restoring a snapshot, first waits for all
+// the changes to be written so none of them are lost.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use iced::widget::{button, row, text};
+use iced::{Color, Element, Subscription, Task};
+use sea_orm::{DatabaseConnection, DbErr, RuntimeErr, TransactionTrait};
+use uuid::Uuid;
+
+use crate::data::update_picture_state;
+use crate::journal::{record_edits, Edit};
+use crate::picture::PictureData;
+use crate::Message;
+
+/// The number of times a write blocked by another connection is attempted.
+const WRITE_ATTEMPTS: u32 = 5;
+/// The delay before the first retry of a blocked write, which doubles each attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+/// How often the changes of a failed write are tried again.
+const FAILED_INTERVAL: Duration = Duration::from_secs(10);
+/// The primary result code of SQLite for a database locked by another connection.
+const SQLITE_BUSY: i32 = 5;
+
+#[derive(Debug, Clone)]
+pub enum PersistenceError {
+    WriteFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for PersistenceError {
+    fn from(error: anyhow::Error) -> Self {
+        PersistenceError::WriteFailed(Arc::new(error))
+    }
+}
+
+impl std::fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersistenceError::WriteFailed(e) => write!(f, "Saving changes failed: {e}"),
+        }
+    }
+}
+
+/// Whether the error is from another connection holding a lock on the database.
+fn is_busy(error: &Error) -> bool {
+    let Some(
+        DbErr::Exec(RuntimeErr::SqlxError(sqlx::Error::Database(e)))
+        | DbErr::Query(RuntimeErr::SqlxError(sqlx::Error::Database(e))),
+    ) = error.downcast_ref::<DbErr>()
+    else {
+        return false;
+    };
+    // The extended result codes keep the primary result code in the lowest byte
+    e.code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| code & 0xff == SQLITE_BUSY)
+}
+
+/// The changes written to the database within a single transaction
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pictures: Vec<PictureData>,
+    // The edits of each action, in the order the actions were made
+    actions: Vec<Vec<Edit>>,
+}
+
+/// Write the state of the pictures along with their edits within a single
+/// transaction.
+///
+/// Pictures removed from the library since they were changed are skipped along
+/// with their edits, rather than failing the whole batch.
+async fn write_batch(db: &DatabaseConnection, batch: &Batch) -> Result<(), Error> {
+    let txn = db.begin().await?;
+    let mut removed = HashSet::new();
+    for picture in batch.pictures.iter() {
+        if !update_picture_state(&txn, picture).await? {
+            tracing::warn!(
+                "Not saving {}, which is no longer within the library",
+                picture.filepath
+            );
+            removed.insert(picture.id);
+        }
+    }
+    for edits in batch.actions.iter() {
+        let edits = edits
+            .iter()
+            .filter(|e| !removed.contains(&e.picture_id))
+            .cloned()
+            .collect();
+        record_edits(&txn, edits).await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Write the changes, retrying when the database is busy.
+#[tracing::instrument(name = "Saving pictures", skip(db, batch), fields(count = batch.pictures.len()))]
+pub async fn save_batch(db: &DatabaseConnection, batch: &Batch) -> Result<(), PersistenceError> {
+    let mut attempt = 1;
+    let mut delay = RETRY_DELAY;
+    loop {
+        match write_batch(db, batch).await {
+            Err(e) if is_busy(&e) && attempt < WRITE_ATTEMPTS => {
+                tracing::warn!("The database is busy, retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                delay *= 2;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PersistenceMessage {
+    /// Write all the changes which haven't been written yet
+    Flush,
+    Saved((Batch, Result<(), PersistenceError>)),
+    /// Write all the changes, then send the message with whether they were written
+    Settle(fn(Result<(), PersistenceError>) -> Message),
+    /// Write the remaining changes before closing the application
+    Close,
+}
+
+impl From<PersistenceMessage> for Message {
+    fn from(val: PersistenceMessage) -> Self {
+        Message::Persistence(val)
+    }
+}
+
+/// The changes to pictures waiting to be written to the database
+#[derive(Debug)]
+pub struct Persistence {
+    database: DatabaseConnection,
+    // The latest state of each picture changed since it was last written
+    pending: HashMap<Uuid, PictureData>,
+    // The edits of the actions making the pending changes
+    actions: Vec<Vec<Edit>>,
+    // The number of pictures within the write in flight
+    saving: Option<usize>,
+    // Why the last write failed, which is cleared once a write succeeds
+    failed: Option<PersistenceError>,
+    // Whether the application closes once everything has been written
+    closing: bool,
+    // The messages sent once everything has been written, or a write has failed
+    settling: Vec<fn(Result<(), PersistenceError>) -> Message>,
+}
+
+impl Persistence {
+    pub fn new(database: DatabaseConnection) -> Self {
+        Self {
+            database,
+            pending: HashMap::new(),
+            actions: vec![],
+            saving: None,
+            failed: None,
+            closing: false,
+            settling: vec![],
+        }
+    }
+
+    /// Whether all the changes have been written.
+    pub fn is_settled(&self) -> bool {
+        self.saving.is_none() && self.pending.is_empty()
+    }
+
+    /// Send the messages waiting for the changes to be written.
+    fn settle(&mut self, result: Result<(), PersistenceError>) -> Task<Message> {
+        Task::batch(
+            self.settling
+                .drain(..)
+                .map(|settled| Task::done(settled(result.clone())))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Keep the changed pictures to be written along with the edits making the
+    /// changes, starting a write where none is running.
+    pub fn queue(&mut self, pictures: Vec<PictureData>, edits: Vec<Edit>) -> Task<Message> {
+        self.pending
+            .extend(pictures.into_iter().map(|picture| (picture.id, picture)));
+        if !edits.is_empty() {
+            self.actions.push(edits);
+        }
+        self.flush()
+    }
+
+    /// Write the pending changes as a single batch.
+    fn flush(&mut self) -> Task<Message> {
+        if self.saving.is_some() || self.pending.is_empty() {
+            return Task::none();
+        }
+        let batch = Batch {
+            pictures: self.pending.drain().map(|(_, p)| p).collect(),
+            actions: std::mem::take(&mut self.actions),
+        };
+        self.saving = Some(batch.pictures.len());
+        let database = self.database.clone();
+        Task::perform(
+            async move {
+                let result = save_batch(&database, &batch).await;
+                (batch, result)
+            },
+            PersistenceMessage::Saved,
+        )
+        .map(Message::Persistence)
+    }
+
+    pub fn update(&mut self, message: PersistenceMessage) -> Task<Message> {
+        match message {
+            PersistenceMessage::Flush => self.flush(),
+            PersistenceMessage::Saved((_, Ok(()))) => {
+                self.saving = None;
+                self.failed = None;
+                if self.closing && self.pending.is_empty() {
+                    return iced::exit();
+                }
+                if self.pending.is_empty() {
+                    return self.settle(Ok(()));
+                }
+                self.flush()
+            }
+            PersistenceMessage::Saved((batch, Err(e))) => {
+                tracing::error!("{e}");
+                self.saving = None;
+                // Changes made while writing are newer than those which failed
+                for picture in batch.pictures.into_iter() {
+                    self.pending.entry(picture.id).or_insert(picture);
+                }
+                self.actions.splice(0..0, batch.actions);
+                self.failed = Some(e.clone());
+                if self.closing {
+                    tracing::error!("Closing with {} unsaved changes", self.pending.len());
+                    return iced::exit();
+                }
+                self.settle(Err(e))
+            }
+            PersistenceMessage::Settle(settled) => {
+                if self.is_settled() {
+                    return Task::done(settled(Ok(())));
+                }
+                self.settling.push(settled);
+                self.flush()
+            }
+            PersistenceMessage::Close => {
+                self.closing = true;
+                if self.is_settled() {
+                    iced::exit()
+                } else {
+                    self.flush()
+                }
+            }
+        }
+    }
+
+    /// Try the changes of a failed write again every so often.
+    pub fn subscription(&self) -> Subscription<Message> {
+        if self.failed.is_some() {
+            iced::time::every(FAILED_INTERVAL).map(|_| PersistenceMessage::Flush.into())
+        } else {
+            Subscription::none()
+        }
+    }
+
+    /// The state of the changes which are yet to be written.
+    pub fn menu_view(&self) -> Element<'_, Message> {
+        let unsaved = self.pending.len() + self.saving.unwrap_or_default();
+        if let Some(e) = &self.failed {
+            row![
+                text(format!("{unsaved} unsaved changes: {e}")).color(Color::from_rgb(0.8, 0., 0.)),
+                button(text("Retry")).on_press_maybe(
+                    self.saving
+                        .is_none()
+                        .then_some(PersistenceMessage::Flush.into())
+                ),
+            ]
+            .spacing(10)
+            .align_y(iced::Alignment::Center)
+            .into()
+        } else if unsaved > 0 {
+            text(format!("Saving {unsaved} changes")).into()
+        } else {
+            row![].into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf;
+    use entity::{edit, picture, Rating};
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{Database, EntityTrait};
+
+    use super::*;
+    use crate::data::add_new_images;
+    use crate::get_parent_directory;
+    use crate::journal::Change;
+
+    #[tokio::test]
+    async fn test_write_removed_picture() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let mut kept = PictureData::from(Utf8PathBuf::from("/photos/IMG_0001.JPG"));
+        kept.directory_id = get_parent_directory(&db, &Utf8PathBuf::from("/photos"))
+            .await
+            .unwrap();
+        add_new_images(&db, vec![kept.clone()]).await.unwrap();
+        // Never added, like a picture removed from the library after it was changed
+        let removed = PictureData::from(Utf8PathBuf::from("/photos/IMG_0002.JPG"));
+
+        let rate = |picture: &PictureData| {
+            Edit::new(
+                picture.id,
+                Change::Rating {
+                    before: None,
+                    after: Some(Rating::Five),
+                },
+            )
+        };
+        kept.rating = Some(Rating::Five);
+        let batch = Batch {
+            pictures: vec![kept.clone(), removed.clone()],
+            actions: vec![vec![rate(&kept), rate(&removed)]],
+        };
+        write_batch(&db, &batch).await.unwrap();
+
+        // The picture still within the library is written along with its edit
+        let model = picture::Entity::find_by_id(kept.id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(model.rating, Some(Rating::Five));
+        let edits = edit::Entity::find().all(&db).await.unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].picture_id, kept.id);
+    }
+}
@@ -1,18 +1,21 @@
-use std::io::{BufReader, Cursor, Seek};
+use std::io::{BufReader, Cursor};
 
 use anyhow::Error;
 use camino::Utf8PathBuf;
-use entity::{picture, Flag, Rating, Selection};
-use exif::{In, Tag};
+use entity::{directory, picture, Flag, Rating, Selection};
 use image::imageops::{flip_horizontal, flip_vertical, rotate180, rotate270, rotate90, FilterType};
-use image::{ImageFormat, ImageReader, RgbImage, RgbaImage};
+use image::{ImageFormat, RgbImage, RgbaImage};
 use sea_orm::ActiveValue;
 use time::format_description::FormatItem;
 use time::macros::format_description;
-use time::PrimitiveDateTime;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
 use uuid::Uuid;
 use walkdir::DirEntry;
 
+use super::{decode_image, PictureFormat, PictureMetadata};
+use crate::capture_time::parse_offset;
+use crate::hash::{full_hash, short_hash};
+
 const DISPLAY_FORMAT: &[FormatItem<'_>] =
     format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
 
@@ -21,12 +24,27 @@ pub struct PictureData {
     pub id: Uuid,
     pub filepath: Utf8PathBuf,
     pub raw_extension: Option<String>,
+    /// The local time the picture was taken
     pub capture_time: Option<PrimitiveDateTime>,
+    /// The timezone of the capture time, where this is known
+    pub capture_offset: Option<UtcOffset>,
     pub selection: Selection,
     pub rating: Option<Rating>,
     pub flag: Option<Flag>,
     pub hidden: bool,
+    /// The caption written by the user, which is searched along with the filename
+    pub caption: Option<String>,
+    /// The directory containing the picture, which is only known once the picture
+    /// has been placed within the library.
     pub directory_id: Option<Uuid>,
+    pub short_hash: Option<Vec<u8>>,
+    pub full_hash: Option<Vec<u8>>,
+    /// The names of the tags applied to the picture, which are stored separately
+    /// to the other values within the database.
+    pub tags: Vec<String>,
+    /// The metadata from the exif data of the picture, which is also stored
+    /// separately within the database.
+    pub metadata: Option<PictureMetadata>,
 }
 
 impl PictureData {
@@ -51,22 +69,80 @@ impl PictureData {
 
     #[tracing::instrument(name = "Updating exif data from file")]
     pub fn update_from_exif(&mut self) -> Result<(), Error> {
-        // Get the image capture date
         let file = std::fs::File::open(&self.filepath)?;
         let mut bufreader = BufReader::new(&file);
 
         let exifreader = exif::Reader::new();
-        let exif = exifreader.read_from_container(&mut bufreader)?;
+        let exif = exifreader.read_from_container(&mut bufreader);
+
+        // Pictures without exif data still have dimensions we can record
+        let mut metadata = exif
+            .as_ref()
+            .map(PictureMetadata::from_exif)
+            .unwrap_or_default();
+        metadata.update_dimensions(&self.filepath);
+        self.metadata = Some(metadata);
+        let exif = exif?;
 
+        // Get the image capture date
         let capture_datetime = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY);
 
         self.capture_time = if let Some(f) = capture_datetime {
             let v = f.value.display_as(exif::Tag::DateTimeOriginal).to_string();
-            Some(PrimitiveDateTime::parse(&v, DISPLAY_FORMAT).expect("Unable to parse datetime"))
+            Some(PrimitiveDateTime::parse(&v, DISPLAY_FORMAT)?)
         } else {
             None
         };
 
+        // The timezone is only included by newer cameras
+        self.capture_offset = match exif
+            .get_field(exif::Tag::OffsetTimeOriginal, exif::In::PRIMARY)
+            .map(|f| &f.value)
+        {
+            Some(exif::Value::Ascii(values)) => values
+                .first()
+                .map(|v| String::from_utf8_lossy(v).trim_matches('\0').to_string())
+                .and_then(|v| {
+                    parse_offset(&v)
+                        .inspect_err(|e| tracing::warn!("{e} within {}", self.filepath))
+                        .ok()
+                }),
+            _ => None,
+        };
+
+        Ok(())
+    }
+
+    /// Use the timezone chosen by the user for a capture time without one.
+    pub fn fill_offset(&mut self, offset: Option<UtcOffset>) {
+        if self.capture_time.is_some() && self.capture_offset.is_none() {
+            self.capture_offset = offset;
+        }
+    }
+
+    /// The moment the picture was taken, allowing pictures from different timezones
+    /// to be compared. Capture times without a timezone are treated as UTC.
+    pub fn capture_instant(&self) -> Option<OffsetDateTime> {
+        self.capture_time
+            .map(|t| t.assume_offset(self.capture_offset.unwrap_or(UtcOffset::UTC)))
+    }
+
+    /// The RAW file of the picture, which is either the file itself or the RAW file
+    /// accompanying it.
+    pub fn raw_filepath(&self) -> Option<Utf8PathBuf> {
+        match &self.raw_extension {
+            Some(ext) => Some(self.filepath.with_extension(ext)),
+            None => PictureFormat::from_path(&self.filepath)
+                .is_some_and(|f| f.is_raw())
+                .then(|| self.filepath.clone()),
+        }
+    }
+
+    /// Compute the short and full content hashes of the file.
+    #[tracing::instrument(name = "Updating hashes from file", level = "debug")]
+    pub fn update_hashes(&mut self) -> Result<(), Error> {
+        self.short_hash = Some(short_hash(&self.filepath)?);
+        self.full_hash = Some(full_hash(&self.filepath)?);
         Ok(())
     }
 
@@ -79,51 +155,47 @@ impl PictureData {
         scale_x: u32,
         scale_y: u32,
     ) -> Result<RgbImage, Error> {
-        let file = std::fs::File::open(filepath)?;
-        let mut cursor = std::io::BufReader::new(file);
-        let exif_data = exif::Reader::new().read_from_container(&mut cursor)?;
-
-        // Reset the buffer to the start to read the image file
-        cursor.rewind()?;
-        let image = ImageReader::new(cursor)
-            .with_guessed_format()?
-            .decode()?
+        let (image, orientation) = decode_image(filepath)?;
+        let image = image
             .resize(scale_x, scale_y, FilterType::Triangle)
             .into_rgb8();
         // Apply Exif image transformations
         // https://sirv.com/help/articles/rotate-photos-to-be-upright/
-        Ok(
-            match exif_data
-                .get_field(Tag::Orientation, In::PRIMARY)
-                .and_then(|e| e.value.get_uint(0))
-            {
-                Some(1) => image,
-                Some(2) => flip_horizontal(&image),
-                Some(3) => rotate180(&image),
-                Some(4) => flip_vertical(&image),
-                Some(5) => rotate270(&flip_horizontal(&image)),
-                Some(6) => rotate90(&image),
-                Some(7) => rotate90(&flip_horizontal(&image)),
-                Some(8) => rotate270(&image),
-                // Where we can't interpret the exif data, we revert to the base image
-                _ => image,
-            },
-        )
+        Ok(match orientation {
+            Some(1) => image,
+            Some(2) => flip_horizontal(&image),
+            Some(3) => rotate180(&image),
+            Some(4) => flip_vertical(&image),
+            Some(5) => rotate270(&flip_horizontal(&image)),
+            Some(6) => rotate90(&image),
+            Some(7) => rotate90(&flip_horizontal(&image)),
+            Some(8) => rotate270(&image),
+            // Where we can't interpret the exif data, we revert to the base image
+            _ => image,
+        })
     }
 }
 
-impl From<picture::Model> for PictureData {
-    fn from(value: picture::Model) -> Self {
+impl From<(picture::Model, &directory::Model)> for PictureData {
+    fn from((value, directory): (picture::Model, &directory::Model)) -> Self {
         Self {
             id: value.id,
-            filepath: value.filepath(),
+            filepath: value.filepath(directory),
             raw_extension: value.raw_extension,
             capture_time: value.capture_time,
+            capture_offset: value
+                .capture_offset
+                .and_then(|o| UtcOffset::from_whole_seconds(o).ok()),
             selection: value.selection,
             rating: value.rating,
             flag: value.flag,
             hidden: value.hidden,
-            directory_id: value.directory_id,
+            caption: value.caption,
+            directory_id: Some(value.directory_id),
+            short_hash: value.short_hash,
+            full_hash: value.full_hash,
+            tags: vec![],
+            metadata: None,
         }
     }
 }
@@ -132,18 +204,27 @@ impl PictureData {
     pub fn into_active(self) -> picture::ActiveModel {
         picture::ActiveModel {
             id: ActiveValue::Unchanged(self.id),
-            short_hash: ActiveValue::not_set(),
-            full_hash: ActiveValue::not_set(),
-            directory: ActiveValue::Set(self.directory()),
+            // Only write the hashes when they have been computed, so we don't remove
+            // existing values when updating other fields.
+            short_hash: self
+                .short_hash
+                .map_or(ActiveValue::not_set(), |h| ActiveValue::Set(Some(h))),
+            full_hash: self
+                .full_hash
+                .map_or(ActiveValue::not_set(), |h| ActiveValue::Set(Some(h))),
             filename: ActiveValue::Set(self.filename()),
             raw_extension: ActiveValue::Set(self.raw_extension),
             capture_time: ActiveValue::Set(self.capture_time),
+            capture_offset: ActiveValue::Set(self.capture_offset.map(UtcOffset::whole_seconds)),
             selection: ActiveValue::Set(self.selection),
             rating: ActiveValue::Set(self.rating),
             flag: ActiveValue::Set(self.flag),
             hidden: ActiveValue::Set(self.hidden),
             thumbnail: ActiveValue::not_set(),
-            directory_id: ActiveValue::Set(self.directory_id),
+            directory_id: self
+                .directory_id
+                .map_or(ActiveValue::not_set(), ActiveValue::Set),
+            caption: ActiveValue::Set(self.caption),
         }
     }
 }
@@ -175,10 +256,13 @@ impl std::fmt::Debug for PictureData {
             .field("path", &self.filepath)
             .field("raw_extension", &self.raw_extension)
             .field("capture_time", &self.capture_time)
+            .field("capture_offset", &self.capture_offset)
             .field("selection", &self.selection)
             .field("rating", &self.rating)
             .field("flag", &self.flag)
             .field("hidden", &self.hidden)
+            .field("tags", &self.tags)
+            .field("metadata", &self.metadata)
             .finish()
     }
 }
@@ -207,41 +291,29 @@ impl ThumbnailData {
         scale_x: u32,
         scale_y: u32,
     ) -> Result<RgbImage, Error> {
-        let file = std::fs::File::open(filepath)?;
-        let mut cursor = std::io::BufReader::new(file);
-        let exif_data = exif::Reader::new().read_from_container(&mut cursor)?;
-
-        // Reset the buffer to the start to read the image file
-        cursor.rewind()?;
-        let image = ImageReader::new(cursor)
-            .with_guessed_format()?
-            .decode()?
+        let (image, orientation) = decode_image(filepath)?;
+        let image = image
             .resize(scale_x, scale_y, FilterType::Triangle)
             .into_rgb8();
         // Apply Exif image transformations
         // https://sirv.com/help/articles/rotate-photos-to-be-upright/
-        Ok(
-            match exif_data
-                .get_field(Tag::Orientation, In::PRIMARY)
-                .and_then(|e| e.value.get_uint(0))
-            {
-                Some(1) => image,
-                Some(2) => flip_horizontal(&image),
-                Some(3) => rotate180(&image),
-                Some(4) => flip_vertical(&image),
-                Some(5) => rotate270(&flip_horizontal(&image)),
-                Some(6) => rotate90(&image),
-                Some(7) => rotate90(&flip_horizontal(&image)),
-                Some(8) => rotate270(&image),
-                // Where we can't interpret the exif data, we revert to the base image
-                _ => image,
-            },
-        )
+        Ok(match orientation {
+            Some(1) => image,
+            Some(2) => flip_horizontal(&image),
+            Some(3) => rotate180(&image),
+            Some(4) => flip_vertical(&image),
+            Some(5) => rotate270(&flip_horizontal(&image)),
+            Some(6) => rotate90(&image),
+            Some(7) => rotate90(&flip_horizontal(&image)),
+            Some(8) => rotate270(&image),
+            // Where we can't interpret the exif data, we revert to the base image
+            _ => image,
+        })
     }
 }
 
-impl From<picture::Model> for ThumbnailData {
-    fn from(value: picture::Model) -> Self {
+impl From<(picture::Model, &directory::Model)> for ThumbnailData {
+    fn from((value, directory): (picture::Model, &directory::Model)) -> Self {
         let thumbnail = value.thumbnail.as_ref().and_then(|data| {
             image::load_from_memory_with_format(data, image::ImageFormat::Jpeg)
                 .ok()
@@ -249,7 +321,7 @@ impl From<picture::Model> for ThumbnailData {
         });
         Self {
             id: value.id,
-            filepath: value.filepath(),
+            filepath: value.filepath(directory),
             thumbnail,
         }
     }
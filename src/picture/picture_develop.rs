@@ -0,0 +1,340 @@
+//! Development of the sensor data within RAW files
+//
+// The embedded previews are small on many cameras and have in-camera sharpening
+// applied, so aren't suitable for judging the focus of a picture. Instead we decode
+// the sensor data and develop it ourselves with a simple pipeline:
+//
+// 1. Scale the sensor values between the black and white levels
+// 2. Apply the white balance multipliers of the camera
+// 3. Demosaic the colour filter array using bilinear interpolation
+// 4. Convert from the camera colour space to sRGB
+// 5. Apply the sRGB transfer function along with a basic tone curve
+//
+// The aim is an accurate rendering of the details rather than a pleasing image, so
+// there is no noise reduction, sharpening or highlight recovery.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use rawloader::{Orientation, RawImage, RawImageData};
+use rayon::prelude::*;
+
+/// The conversion from linear sRGB to XYZ using the D65 white point
+const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175_0],
+    [0.019_333_9, 0.119_192_0, 0.950_304_1],
+];
+const IDENTITY: [[f32; 3]; 3] = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+
+/// The number of entries within the lookup table of the tone curve
+const TONE_CURVE_SIZE: usize = 4096;
+/// The amount of contrast added by the tone curve, between 0 (none) and 1.
+const TONE_CONTRAST: f32 = 0.25;
+
+/// Develop the sensor data of a RAW file into an image.
+///
+/// The image is returned at the full resolution of the sensor, along with the exif
+/// orientation to be applied.
+#[tracing::instrument(name = "Developing RAW file", level = "info")]
+pub fn develop_raw(filepath: &Path) -> Result<(RgbaImage, Option<u32>)> {
+    let raw = rawloader::decode_file(filepath)
+        .map_err(|e| anyhow!("Unable to decode RAW file {}: {e:?}", filepath.display()))?;
+    if raw.cpp != 1 && raw.cpp != 3 {
+        return Err(anyhow!(
+            "Unsupported RAW file {} with {} components per pixel",
+            filepath.display(),
+            raw.cpp
+        ));
+    }
+    if raw.cpp == 1 && !raw.cfa.is_valid() {
+        return Err(anyhow!(
+            "Unsupported colour filter array in {}",
+            filepath.display()
+        ));
+    }
+
+    let sensor = normalise(&raw);
+    let matrix = camera_to_srgb(&raw.xyz_to_cam);
+    let curve = tone_curve();
+
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.saturating_sub(left + right);
+    let height = raw.height.saturating_sub(top + bottom);
+    if width == 0 || height == 0 {
+        return Err(anyhow!("No image data within {}", filepath.display()));
+    }
+
+    let mut buffer = vec![0u8; width * height * 4];
+    buffer
+        .par_chunks_exact_mut(width * 4)
+        .enumerate()
+        .for_each(|(y, output_row)| {
+            for (x, pixel) in output_row.chunks_exact_mut(4).enumerate() {
+                let camera = match raw.cpp {
+                    1 => demosaic(&raw, &sensor, y + top, x + left),
+                    _ => {
+                        let index = ((y + top) * raw.width + x + left) * 3;
+                        [sensor[index], sensor[index + 1], sensor[index + 2]]
+                    }
+                };
+                let rgb = multiply(&matrix, camera);
+                for (output, value) in pixel.iter_mut().zip(rgb) {
+                    *output = curve[lookup_index(value)];
+                }
+                pixel[3] = u8::MAX;
+            }
+        });
+
+    let image = RgbaImage::from_raw(width as u32, height as u32, buffer)
+        .ok_or(anyhow!("Invalid image data within {}", filepath.display()))?;
+    Ok((image, exif_orientation(raw.orientation)))
+}
+
+/// The colour of the filter at a location, treating a fourth colour as green.
+fn colour_at(raw: &RawImage, row: usize, col: usize) -> usize {
+    match raw.cfa.color_at(row, col) {
+        3 => 1,
+        c => c.min(2),
+    }
+}
+
+/// The white balance multipliers of the camera, scaled relative to green.
+fn white_balance(wb_coeffs: [f32; 4]) -> [f32; 3] {
+    let [red, green, blue, _] = wb_coeffs;
+    if !(green.is_finite() && green > 0.) {
+        return [1.; 3];
+    }
+    [red / green, 1., blue / green].map(|c| if c.is_finite() && c > 0. { c } else { 1. })
+}
+
+/// Scale the sensor values between the black and white levels, applying the white
+/// balance so a value of 1 is the brightest value of all the channels.
+fn normalise(raw: &RawImage) -> Vec<f32> {
+    let wb = white_balance(raw.wb_coeffs);
+    let colour = |index: usize| match raw.cpp {
+        1 => colour_at(raw, index / raw.width, index % raw.width),
+        _ => index % raw.cpp,
+    };
+    let scale = |index: usize, value: f32| {
+        let c = colour(index);
+        let black = f32::from(raw.blacklevels[c]);
+        let white = f32::from(raw.whitelevels[c]);
+        let range = (white - black).max(1.);
+        // Clipping after the white balance ensures blown highlights are white
+        (((value - black) / range).max(0.) * wb[c]).min(1.)
+    };
+    match &raw.data {
+        RawImageData::Integer(data) => data
+            .par_iter()
+            .enumerate()
+            .map(|(i, &v)| scale(i, f32::from(v)))
+            .collect(),
+        RawImageData::Float(data) => data
+            .par_iter()
+            .enumerate()
+            .map(|(i, &v)| scale(i, v))
+            .collect(),
+    }
+}
+
+/// Interpolate the colour at a location from the neighbouring pixels.
+///
+/// Each colour is the average of the pixels with that filter within the surrounding
+/// 3x3 block, which covers all the colours of both Bayer and X-Trans sensors.
+fn demosaic(raw: &RawImage, sensor: &[f32], row: usize, col: usize) -> [f32; 3] {
+    let mut sums = [0.; 3];
+    let mut counts = [0u32; 3];
+    for r in row.saturating_sub(1)..=(row + 1).min(raw.height - 1) {
+        for c in col.saturating_sub(1)..=(col + 1).min(raw.width - 1) {
+            let colour = colour_at(raw, r, c);
+            sums[colour] += sensor[r * raw.width + c];
+            counts[colour] += 1;
+        }
+    }
+    // The pixel itself is the most accurate value of its own colour
+    let own = colour_at(raw, row, col);
+    sums[own] = sensor[row * raw.width + col];
+    counts[own] = 1;
+
+    std::array::from_fn(|i| match counts[i] {
+        0 => 0.,
+        n => sums[i] / n as f32,
+    })
+}
+
+/// Compute the matrix converting the white balanced camera colours to linear sRGB.
+///
+/// This follows the approach of dcraw, where the rows of the sRGB to camera matrix
+/// are normalised so white in sRGB is white for the camera, before inverting.
+fn camera_to_srgb(xyz_to_cam: &[[f32; 3]; 4]) -> [[f32; 3]; 3] {
+    let mut srgb_to_cam = [[0.; 3]; 3];
+    for (i, row) in srgb_to_cam.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| xyz_to_cam[i][k] * SRGB_TO_XYZ[k][j]).sum();
+        }
+        let total: f32 = row.iter().sum();
+        // Cameras without a known colour matrix are left in the camera colour space
+        if total.abs() < f32::EPSILON {
+            return IDENTITY;
+        }
+        row.iter_mut().for_each(|v| *v /= total);
+    }
+    invert(&srgb_to_cam).unwrap_or(IDENTITY)
+}
+
+fn invert(m: &[[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let determinant = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    Some(adjugate.map(|row| row.map(|v| v / determinant)))
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], value: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row.iter().zip(value).map(|(m, v)| m * v).sum())
+}
+
+/// The lookup table from linear values to the 8-bit output values.
+///
+/// This applies the sRGB transfer function, then blends in an S-curve to restore the
+/// contrast a camera would add when processing the picture.
+fn tone_curve() -> Vec<u8> {
+    (0..TONE_CURVE_SIZE)
+        .map(|i| {
+            let linear = i as f32 / (TONE_CURVE_SIZE - 1) as f32;
+            let srgb = if linear <= 0.003_130_8 {
+                linear * 12.92
+            } else {
+                1.055 * linear.powf(1. / 2.4) - 0.055
+            };
+            let s_curve = srgb * srgb * (3. - 2. * srgb);
+            let value = srgb + TONE_CONTRAST * (s_curve - srgb);
+            (value.clamp(0., 1.) * 255.).round() as u8
+        })
+        .collect()
+}
+
+fn lookup_index(value: f32) -> usize {
+    (value.clamp(0., 1.) * (TONE_CURVE_SIZE - 1) as f32).round() as usize
+}
+
+fn exif_orientation(orientation: Orientation) -> Option<u32> {
+    match orientation {
+        Orientation::Normal => Some(1),
+        Orientation::HorizontalFlip => Some(2),
+        Orientation::Rotate180 => Some(3),
+        Orientation::VerticalFlip => Some(4),
+        Orientation::Transpose => Some(5),
+        Orientation::Rotate90 => Some(6),
+        Orientation::Transverse => Some(7),
+        Orientation::Rotate270 => Some(8),
+        Orientation::Unknown => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn test_invert() {
+        let diagonal = [[2., 0., 0.], [0., 4., 0.], [0., 0., 8.]];
+        assert_eq!(
+            invert(&diagonal),
+            Some([[0.5, 0., 0.], [0., 0.25, 0.], [0., 0., 0.125]])
+        );
+
+        let inverse = invert(&SRGB_TO_XYZ).unwrap();
+        for (i, row) in IDENTITY.iter().enumerate() {
+            assert_close(multiply(&SRGB_TO_XYZ, multiply(&inverse, *row)), *row);
+            assert_close(multiply(&inverse, SRGB_TO_XYZ.map(|r| r[i])), *row);
+        }
+    }
+
+    #[test]
+    fn test_invert_degenerate() {
+        let degenerate = [[1., 2., 3.], [2., 4., 6.], [0., 1., 1.]];
+        assert_eq!(invert(&degenerate), None);
+        assert_eq!(invert(&[[0.; 3]; 3]), None);
+    }
+
+    #[test]
+    fn test_camera_to_srgb() {
+        // A camera which records sRGB needs no conversion
+        let xyz_to_srgb = invert(&SRGB_TO_XYZ).unwrap();
+        let xyz_to_cam = [xyz_to_srgb[0], xyz_to_srgb[1], xyz_to_srgb[2], [0.; 3]];
+        for (actual, expected) in camera_to_srgb(&xyz_to_cam).iter().zip(IDENTITY) {
+            assert_close(*actual, expected);
+        }
+
+        // White balanced camera colours keep white as white
+        let xyz_to_cam = [
+            [0.6446, -0.0366, -0.0864],
+            [-0.4436, 1.2204, 0.2513],
+            [-0.0952, 0.2496, 0.6348],
+            [0.; 3],
+        ];
+        assert_close(multiply(&camera_to_srgb(&xyz_to_cam), [1.; 3]), [1.; 3]);
+    }
+
+    #[test]
+    fn test_camera_to_srgb_unknown() {
+        assert_eq!(camera_to_srgb(&[[0.; 3]; 4]), IDENTITY);
+
+        let degenerate = [[0.5, 0.2, 0.1], [0.5, 0.2, 0.1], [0.1, 0.2, 0.5], [0.; 3]];
+        assert_eq!(camera_to_srgb(&degenerate), IDENTITY);
+    }
+
+    #[test]
+    fn test_white_balance() {
+        assert_eq!(white_balance([4., 2., 3., f32::NAN]), [2., 1., 1.5]);
+        assert_eq!(white_balance([2., 0., 1.5, 0.]), [1.; 3]);
+        assert_eq!(white_balance([2., f32::NAN, 1.5, 0.]), [1.; 3]);
+        assert_eq!(white_balance([2., -1., 1.5, 0.]), [1.; 3]);
+        assert_eq!(white_balance([0., 1., f32::NAN, 0.]), [1.; 3]);
+        assert_eq!(white_balance([f32::INFINITY, 1., 2., 0.]), [1., 1., 2.]);
+    }
+
+    #[test]
+    fn test_tone_curve() {
+        let curve = tone_curve();
+        assert_eq!(curve.len(), TONE_CURVE_SIZE);
+        assert_eq!(curve[0], 0);
+        assert_eq!(curve[TONE_CURVE_SIZE - 1], u8::MAX);
+        assert!(curve.windows(2).all(|w| w[0] <= w[1]));
+
+        assert_eq!(lookup_index(0.), 0);
+        assert_eq!(lookup_index(1.), TONE_CURVE_SIZE - 1);
+        assert_eq!(lookup_index(-0.5), 0);
+        assert_eq!(lookup_index(1.5), TONE_CURVE_SIZE - 1);
+        assert_eq!(lookup_index(f32::NAN), 0);
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use exif::{In, Tag};
+use image::{DynamicImage, ImageFormat, ImageReader};
+
+use super::extract_preview;
+
+/// The file formats which are recognised as pictures.
+///
+/// This is the single place describing the supported formats, covering which files
+/// are found when scanning directories, how RAW files are grouped with the files
+/// they accompany, and how each of the formats is decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PictureFormat {
+    Jpeg,
+    Heif,
+    Tiff,
+    Png,
+    Gif,
+    Webp,
+    /// Sony
+    Arw,
+    /// Fujifilm
+    Raf,
+    /// Canon
+    Cr2,
+    /// Canon
+    Cr3,
+    /// Nikon
+    Nef,
+    /// Adobe Digital Negative
+    Dng,
+    /// Olympus
+    Orf,
+    /// Panasonic
+    Rw2,
+    /// Files with the generic raw extension
+    Raw,
+}
+
+impl PictureFormat {
+    const ALL: [PictureFormat; 15] = [
+        PictureFormat::Jpeg,
+        PictureFormat::Heif,
+        PictureFormat::Tiff,
+        PictureFormat::Png,
+        PictureFormat::Gif,
+        PictureFormat::Webp,
+        PictureFormat::Arw,
+        PictureFormat::Raf,
+        PictureFormat::Cr2,
+        PictureFormat::Cr3,
+        PictureFormat::Nef,
+        PictureFormat::Dng,
+        PictureFormat::Orf,
+        PictureFormat::Rw2,
+        PictureFormat::Raw,
+    ];
+
+    /// The lowercase file extensions of the format.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            PictureFormat::Jpeg => &["jpg", "jpeg"],
+            PictureFormat::Heif => &["heic", "heif", "hif"],
+            PictureFormat::Tiff => &["tif", "tiff"],
+            PictureFormat::Png => &["png"],
+            PictureFormat::Gif => &["gif"],
+            PictureFormat::Webp => &["webp"],
+            PictureFormat::Arw => &["arw"],
+            PictureFormat::Raf => &["raf"],
+            PictureFormat::Cr2 => &["cr2"],
+            PictureFormat::Cr3 => &["cr3"],
+            PictureFormat::Nef => &["nef"],
+            PictureFormat::Dng => &["dng"],
+            PictureFormat::Orf => &["orf"],
+            PictureFormat::Rw2 => &["rw2"],
+            PictureFormat::Raw => &["raw"],
+        }
+    }
+
+    /// Find the format from a file extension, ignoring the case.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|f| f.extensions().contains(&extension.as_str()))
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        path.as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(Self::from_extension)
+    }
+
+    /// Whether the format contains the sensor data from a camera.
+    ///
+    /// RAW files are stored alongside the processed file of the same name, rather than
+    /// being a separate picture.
+    pub fn is_raw(&self) -> bool {
+        matches!(
+            self,
+            PictureFormat::Arw
+                | PictureFormat::Raf
+                | PictureFormat::Cr2
+                | PictureFormat::Cr3
+                | PictureFormat::Nef
+                | PictureFormat::Dng
+                | PictureFormat::Orf
+                | PictureFormat::Rw2
+                | PictureFormat::Raw
+        )
+    }
+
+    /// The preference for the format being the primary file of a picture, with lower
+    /// values preferred. Where a RAW file is accompanied by multiple processed files,
+    /// it is attached to the most preferred of these.
+    pub fn priority(&self) -> u8 {
+        match self {
+            PictureFormat::Jpeg => 0,
+            PictureFormat::Heif => 1,
+            PictureFormat::Tiff => 2,
+            PictureFormat::Png | PictureFormat::Webp | PictureFormat::Gif => 3,
+            _ => 4,
+        }
+    }
+
+    /// The format used by the image crate for decoding.
+    fn image_format(&self) -> Option<ImageFormat> {
+        match self {
+            PictureFormat::Jpeg => Some(ImageFormat::Jpeg),
+            PictureFormat::Tiff => Some(ImageFormat::Tiff),
+            PictureFormat::Png => Some(ImageFormat::Png),
+            PictureFormat::Gif => Some(ImageFormat::Gif),
+            PictureFormat::Webp => Some(ImageFormat::WebP),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the file is a picture in one of the supported formats.
+pub fn is_image(entry: &walkdir::DirEntry) -> bool {
+    entry.file_type().is_file() && PictureFormat::from_path(entry.path()).is_some()
+}
+
+/// Decode the image within a file, using the decoder for the format of the file.
+///
+/// This returns the image along with the exif orientation that needs to be applied.
+/// For RAW files this is the largest JPEG preview embedded within the file.
+pub fn decode_image(filepath: impl AsRef<Path>) -> Result<(DynamicImage, Option<u32>)> {
+    let filepath = filepath.as_ref();
+    let format = PictureFormat::from_path(filepath)
+        .ok_or(anyhow!("Unsupported file format {}", filepath.display()))?;
+    match format {
+        PictureFormat::Heif => Ok((decode_heif(filepath)?, read_orientation(filepath))),
+        f if f.is_raw() => {
+            let preview = extract_preview(filepath)?;
+            Ok((preview.decode()?, preview.orientation))
+        }
+        f => {
+            let file = std::fs::File::open(filepath)?;
+            let mut reader = ImageReader::new(BufReader::new(file));
+            match f.image_format() {
+                Some(image_format) => reader.set_format(image_format),
+                None => reader = reader.with_guessed_format()?,
+            }
+            Ok((reader.decode()?, read_orientation(filepath)))
+        }
+    }
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(filepath: &Path) -> Result<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let lib_heif = LibHeif::new();
+    let context = HeifContext::read_from_file(
+        filepath
+            .to_str()
+            .ok_or(anyhow!("Invalid UTF-8 path {}", filepath.display()))?,
+    )?;
+    let handle = context.primary_image_handle()?;
+    let image = lib_heif.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)?;
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or(anyhow!("No image data within {}", filepath.display()))?;
+
+    // The rows of the image can be padded, so we copy them across one at a time.
+    let row_bytes = plane.width as usize * 4;
+    let mut buffer = Vec::with_capacity(row_bytes * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        buffer.extend_from_slice(&row[..row_bytes]);
+    }
+    image::RgbaImage::from_raw(plane.width, plane.height, buffer)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or(anyhow!("Invalid image data within {}", filepath.display()))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(filepath: &Path) -> Result<DynamicImage> {
+    Err(anyhow!(
+        "Unable to decode {}, HEIF support requires the heif feature",
+        filepath.display()
+    ))
+}
+
+/// Read the exif orientation of a picture.
+///
+/// Many formats, like PNG, often don't contain exif data, in which case there is no
+/// orientation to apply.
+pub fn read_orientation(filepath: impl AsRef<Path>) -> Option<u32> {
+    let file = std::fs::File::open(filepath).ok()?;
+    exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?
+        .get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|e| e.value.get_uint(0))
+}
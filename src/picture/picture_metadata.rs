@@ -0,0 +1,198 @@
+use std::fmt::Write;
+use std::io::BufReader;
+
+use anyhow::Error;
+use camino::Utf8Path;
+use entity::picture_metadata;
+use exif::{Exif, In, Tag, Value};
+use sea_orm::ActiveValue;
+use uuid::Uuid;
+
+/// The camera settings and other details of a picture from its exif data
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PictureMetadata {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub lens: Option<String>,
+    /// The focal length in millimetres
+    pub focal_length: Option<f64>,
+    /// The f-number of the aperture
+    pub aperture: Option<f64>,
+    /// The shutter speed in seconds
+    pub exposure_time: Option<f64>,
+    pub iso: Option<u32>,
+    /// The exposure compensation in stops
+    pub exposure_compensation: Option<f64>,
+    /// The latitude in degrees, positive to the north
+    pub latitude: Option<f64>,
+    /// The longitude in degrees, positive to the east
+    pub longitude: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub orientation: Option<u32>,
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(values)) => values
+            .first()
+            .map(|v| {
+                String::from_utf8_lossy(v)
+                    .trim_matches(['\0', ' '])
+                    .to_string()
+            })
+            .filter(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+}
+
+/// The value of a rational tag, which is None when the denominator is zero.
+fn float(exif: &Exif, tag: Tag) -> Option<f64> {
+    let value = match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) => v.first()?.to_f64(),
+        Value::SRational(v) => v.first()?.to_f64(),
+        _ => return None,
+    };
+    value.is_finite().then_some(value)
+}
+
+/// A GPS coordinate in degrees, with the reference giving the hemisphere.
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: &str) -> Option<f64> {
+    let Value::Rational(parts) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let [degrees, minutes, seconds] = [parts.first()?, parts.get(1)?, parts.get(2)?];
+    let value = degrees.to_f64() + minutes.to_f64() / 60. + seconds.to_f64() / 3600.;
+    if !value.is_finite() {
+        return None;
+    }
+    Some(match ascii(exif, reference) {
+        Some(r) if r.eq_ignore_ascii_case(negative) => -value,
+        _ => value,
+    })
+}
+
+impl PictureMetadata {
+    pub fn from_file(filepath: &Utf8Path) -> Result<Self, Error> {
+        let file = std::fs::File::open(filepath)?;
+        let exif = exif::Reader::new().read_from_container(&mut BufReader::new(file))?;
+        let mut metadata = Self::from_exif(&exif);
+        metadata.update_dimensions(filepath);
+        Ok(metadata)
+    }
+
+    pub fn from_exif(exif: &Exif) -> Self {
+        Self {
+            make: ascii(exif, Tag::Make),
+            model: ascii(exif, Tag::Model),
+            serial: ascii(exif, Tag::BodySerialNumber),
+            lens: ascii(exif, Tag::LensModel),
+            focal_length: float(exif, Tag::FocalLength),
+            aperture: float(exif, Tag::FNumber),
+            exposure_time: float(exif, Tag::ExposureTime),
+            iso: uint(exif, Tag::PhotographicSensitivity),
+            exposure_compensation: float(exif, Tag::ExposureBiasValue),
+            latitude: coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+            longitude: coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+            width: uint(exif, Tag::PixelXDimension).or(uint(exif, Tag::ImageWidth)),
+            height: uint(exif, Tag::PixelYDimension).or(uint(exif, Tag::ImageLength)),
+            orientation: uint(exif, Tag::Orientation),
+        }
+    }
+
+    /// Fill in the dimensions of pictures without these in the exif data, like most
+    /// PNG files, from the header of the image.
+    pub fn update_dimensions(&mut self, filepath: &Utf8Path) {
+        if self.width.is_some() && self.height.is_some() {
+            return;
+        }
+        if let Ok((width, height)) = image::image_dimensions(filepath) {
+            self.width = Some(width);
+            self.height = Some(height);
+        }
+    }
+
+    /// A short description of the camera settings, like `50 mm f/2.8 1/250 s ISO 400`.
+    pub fn exposure_summary(&self) -> String {
+        let mut summary = String::new();
+        if let Some(focal_length) = self.focal_length {
+            let _ = write!(summary, "{focal_length:.0} mm  ");
+        }
+        if let Some(aperture) = self.aperture {
+            let _ = write!(summary, "f/{aperture:.1}  ");
+        }
+        if let Some(exposure_time) = self.exposure_time {
+            if exposure_time < 1. && exposure_time > 0. {
+                let _ = write!(summary, "1/{:.0} s  ", 1. / exposure_time);
+            } else {
+                let _ = write!(summary, "{exposure_time} s  ");
+            }
+        }
+        if let Some(iso) = self.iso {
+            let _ = write!(summary, "ISO {iso}  ");
+        }
+        if let Some(compensation) = self.exposure_compensation.filter(|c| *c != 0.) {
+            let _ = write!(summary, "{compensation:+.1} EV");
+        }
+        summary.trim_end().to_string()
+    }
+
+    /// The camera and lens used to take the picture.
+    pub fn camera_summary(&self) -> String {
+        [
+            self.model.as_deref().or(self.make.as_deref()),
+            self.lens.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+
+    pub fn into_active(self, picture_id: Uuid) -> picture_metadata::ActiveModel {
+        picture_metadata::ActiveModel {
+            picture_id: ActiveValue::Set(picture_id),
+            make: ActiveValue::Set(self.make),
+            model: ActiveValue::Set(self.model),
+            serial: ActiveValue::Set(self.serial),
+            lens: ActiveValue::Set(self.lens),
+            focal_length: ActiveValue::Set(self.focal_length),
+            aperture: ActiveValue::Set(self.aperture),
+            exposure_time: ActiveValue::Set(self.exposure_time),
+            iso: ActiveValue::Set(self.iso.and_then(|v| v.try_into().ok())),
+            exposure_compensation: ActiveValue::Set(self.exposure_compensation),
+            latitude: ActiveValue::Set(self.latitude),
+            longitude: ActiveValue::Set(self.longitude),
+            width: ActiveValue::Set(self.width.and_then(|v| v.try_into().ok())),
+            height: ActiveValue::Set(self.height.and_then(|v| v.try_into().ok())),
+            orientation: ActiveValue::Set(self.orientation.and_then(|v| v.try_into().ok())),
+        }
+    }
+}
+
+impl From<picture_metadata::Model> for PictureMetadata {
+    fn from(value: picture_metadata::Model) -> Self {
+        Self {
+            make: value.make,
+            model: value.model,
+            serial: value.serial,
+            lens: value.lens,
+            focal_length: value.focal_length,
+            aperture: value.aperture,
+            exposure_time: value.exposure_time,
+            iso: value.iso.and_then(|v| v.try_into().ok()),
+            exposure_compensation: value.exposure_compensation,
+            latitude: value.latitude,
+            longitude: value.longitude,
+            width: value.width.and_then(|v| v.try_into().ok()),
+            height: value.height.and_then(|v| v.try_into().ok()),
+            orientation: value.orientation.and_then(|v| v.try_into().ok()),
+        }
+    }
+}
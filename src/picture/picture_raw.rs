@@ -0,0 +1,537 @@
+//! Extraction of the JPEG previews embedded within RAW files
+//
+// We can't decode the sensor data of RAW files directly, however cameras embed
+// processed JPEG previews within the RAW files which are good enough for
+// thumbnails and previewing. Most RAW formats are based on the TIFF structure,
+// where the previews are referenced from one of the image file directories
+// (IFDs), while Fujifilm (RAF) and Canon (CR3) use their own containers.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use exif::{In, Tag};
+use image::{DynamicImage, ImageFormat};
+
+use super::PictureFormat;
+
+const TAG_RW2_JPEG: u16 = 0x002E;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+/// The compression values of TIFF strips containing JPEG data
+const COMPRESSION_JPEG: [u32; 2] = [6, 7];
+/// The maximum depth of nested IFDs we follow, protecting against malformed files.
+const MAX_IFD_DEPTH: usize = 4;
+/// The integer values we read are only a handful of offsets, so larger values are
+/// from a malformed file.
+const MAX_VALUES_BYTES: usize = 64 * 1024;
+/// Previews are never this large, so a larger length is from a malformed file.
+const MAX_PREVIEW_BYTES: u64 = 64 * 1024 * 1024;
+/// The CR3 preview is near the start of the file, so we only search this far.
+const CR3_SEARCH_BYTES: u64 = 8 * 1024 * 1024;
+
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+
+/// A JPEG preview embedded within a RAW file.
+#[derive(Debug, Clone)]
+pub struct RawPreview {
+    pub jpeg: Vec<u8>,
+    /// The orientation of the preview, taken from the exif data of the preview
+    /// falling back to the orientation of the RAW file.
+    pub orientation: Option<u32>,
+}
+
+impl RawPreview {
+    pub fn decode(&self) -> Result<DynamicImage> {
+        Ok(image::load_from_memory_with_format(
+            &self.jpeg,
+            ImageFormat::Jpeg,
+        )?)
+    }
+}
+
+/// The location of a candidate preview within the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Candidate {
+    offset: u64,
+    length: u64,
+}
+
+/// Extract the largest embedded JPEG preview from a RAW file.
+#[tracing::instrument(name = "Extracting RAW preview", level = "debug")]
+pub fn extract_preview(filepath: &Path) -> Result<RawPreview> {
+    let mut reader = BufReader::new(File::open(filepath)?);
+    let (mut candidates, container_orientation) = match PictureFormat::from_path(filepath) {
+        Some(PictureFormat::Raf) => (raf_candidates(&mut reader)?, None),
+        Some(PictureFormat::Cr3) => (cr3_candidates(&mut reader)?, None),
+        _ => tiff_candidates(&mut reader)?,
+    };
+
+    // Try the largest preview first, moving onto the smaller ones where these aren't
+    // baseline JPEGs, like the lossless JPEG compressed sensor data of some cameras.
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.length));
+    for candidate in candidates {
+        let Ok(jpeg) = read_candidate(&mut reader, candidate) else {
+            continue;
+        };
+        let preview = RawPreview {
+            orientation: jpeg_orientation(&jpeg).or(container_orientation),
+            jpeg,
+        };
+        if preview.decode().is_ok() {
+            return Ok(preview);
+        }
+    }
+    Err(anyhow!("No embedded preview within {}", filepath.display()))
+}
+
+fn read_candidate(reader: &mut (impl Read + Seek), candidate: Candidate) -> Result<Vec<u8>> {
+    if candidate.length > MAX_PREVIEW_BYTES {
+        return Err(anyhow!(
+            "Preview of {} bytes is too large",
+            candidate.length
+        ));
+    }
+    reader.seek(SeekFrom::Start(candidate.offset))?;
+    let mut jpeg = vec![0; candidate.length as usize];
+    reader.read_exact(&mut jpeg)?;
+    if !jpeg.starts_with(&JPEG_SOI) {
+        return Err(anyhow!("Preview is not a JPEG"));
+    }
+    Ok(jpeg)
+}
+
+fn jpeg_orientation(jpeg: &[u8]) -> Option<u32> {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(jpeg))
+        .ok()?
+        .get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|e| e.value.get_uint(0))
+}
+
+/// Fujifilm files start with a header containing the location of the preview.
+fn raf_candidates(reader: &mut (impl Read + Seek)) -> Result<Vec<Candidate>> {
+    let mut header = [0u8; 92];
+    reader.rewind()?;
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(RAF_MAGIC) {
+        return Err(anyhow!("Invalid RAF header"));
+    }
+    let offset = u32::from_be_bytes(header[84..88].try_into()?);
+    let length = u32::from_be_bytes(header[88..92].try_into()?);
+    Ok(vec![Candidate {
+        offset: offset.into(),
+        length: length.into(),
+    }])
+}
+
+/// Canon CR3 files store the preview within a `PRVW` box near the start of the file.
+fn cr3_candidates(reader: &mut (impl Read + Seek)) -> Result<Vec<Candidate>> {
+    let mut buffer = vec![];
+    reader.rewind()?;
+    reader
+        .by_ref()
+        .take(CR3_SEARCH_BYTES)
+        .read_to_end(&mut buffer)?;
+    let position = buffer
+        .windows(4)
+        .position(|w| w == b"PRVW")
+        .ok_or(anyhow!("No PRVW box within CR3 file"))?;
+    // The box name is followed by 12 bytes describing the preview, then the length of
+    // the JPEG data and the data itself.
+    let length_start = position + 16;
+    let length = buffer
+        .get(length_start..length_start + 4)
+        .ok_or(anyhow!("Truncated PRVW box"))?;
+    Ok(vec![Candidate {
+        offset: (length_start + 4) as u64,
+        length: u32::from_be_bytes(length.try_into()?).into(),
+    }])
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    /// The value when it fits within four bytes, otherwise the offset of the value
+    value: [u8; 4],
+}
+
+struct TiffReader<'a, R> {
+    reader: &'a mut R,
+    order: ByteOrder,
+}
+
+impl<R: Read + Seek> TiffReader<'_, R> {
+    fn u16_from(&self, bytes: [u8; 2]) -> u16 {
+        match self.order {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32_from(&self, bytes: [u8; 4]) -> u32 {
+        match self.order {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        let mut bytes = [0u8; 2];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(self.u16_from(bytes))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(self.u32_from(bytes))
+    }
+
+    /// Read the entries of the IFD at the offset, returning them along with the offset
+    /// of the next IFD in the chain.
+    fn read_ifd(&mut self, offset: u32) -> Result<(Vec<IfdEntry>, u32)> {
+        self.reader.seek(SeekFrom::Start(offset.into()))?;
+        let count = self.read_u16()?;
+        let mut entries = Vec::with_capacity(count.into());
+        for _ in 0..count {
+            let tag = self.read_u16()?;
+            let field_type = self.read_u16()?;
+            let count = self.read_u32()?;
+            let mut value = [0u8; 4];
+            self.reader.read_exact(&mut value)?;
+            entries.push(IfdEntry {
+                tag,
+                field_type,
+                count,
+                value,
+            });
+        }
+        Ok((entries, self.read_u32()?))
+    }
+
+    /// Read the integer values of an entry.
+    fn values(&mut self, entry: &IfdEntry) -> Result<Vec<u32>> {
+        let size = match entry.field_type {
+            // SHORT
+            3 => 2,
+            // LONG and IFD
+            4 | 13 => 4,
+            _ => return Err(anyhow!("Unsupported field type {}", entry.field_type)),
+        };
+        let total = size * entry.count as usize;
+        if total > MAX_VALUES_BYTES {
+            return Err(anyhow!("Too many values for tag {}", entry.tag));
+        }
+        let bytes = if total <= 4 {
+            entry.value[..total].to_vec()
+        } else {
+            self.reader
+                .seek(SeekFrom::Start(self.u32_from(entry.value).into()))?;
+            let mut bytes = vec![0u8; total];
+            self.reader.read_exact(&mut bytes)?;
+            bytes
+        };
+        Ok(bytes
+            .chunks_exact(size)
+            .map(|c| match size {
+                2 => self.u16_from([c[0], c[1]]).into(),
+                _ => self.u32_from([c[0], c[1], c[2], c[3]]),
+            })
+            .collect())
+    }
+
+    fn first_value(&mut self, entries: &[IfdEntry], tag: u16) -> Option<u32> {
+        let entry = entries.iter().find(|e| e.tag == tag)?;
+        self.values(entry).ok()?.first().copied()
+    }
+
+    /// Collect the previews referenced from an IFD, following the chain of IFDs along
+    /// with any sub-IFDs.
+    fn collect(
+        &mut self,
+        offset: u32,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        candidates: &mut Vec<Candidate>,
+    ) -> Result<()> {
+        let mut next = offset;
+        while next != 0 && depth < MAX_IFD_DEPTH && visited.insert(next) {
+            let (entries, next_offset) = self.read_ifd(next)?;
+            next = next_offset;
+
+            // The preview referenced as a JPEG thumbnail
+            if let (Some(offset), Some(length)) = (
+                self.first_value(&entries, TAG_JPEG_OFFSET),
+                self.first_value(&entries, TAG_JPEG_LENGTH),
+            ) {
+                candidates.push(Candidate {
+                    offset: offset.into(),
+                    length: length.into(),
+                });
+            }
+            // The preview stored as a single JPEG compressed strip
+            let compression = self.first_value(&entries, TAG_COMPRESSION);
+            if compression.is_some_and(|c| COMPRESSION_JPEG.contains(&c)) {
+                if let (Some(offset), Some(length)) = (
+                    self.first_value(&entries, TAG_STRIP_OFFSETS),
+                    self.first_value(&entries, TAG_STRIP_BYTE_COUNTS),
+                ) {
+                    candidates.push(Candidate {
+                        offset: offset.into(),
+                        length: length.into(),
+                    });
+                }
+            }
+            // Panasonic store the preview as an undefined block of bytes
+            if let Some(entry) = entries.iter().find(|e| e.tag == TAG_RW2_JPEG) {
+                candidates.push(Candidate {
+                    offset: self.u32_from(entry.value).into(),
+                    length: entry.count.into(),
+                });
+            }
+
+            if let Some(sub_ifds) = entries.iter().find(|e| e.tag == TAG_SUB_IFDS) {
+                for sub_ifd in self.values(sub_ifds)? {
+                    // A malformed sub-IFD shouldn't prevent finding the other previews
+                    if let Err(e) = self.collect(sub_ifd, depth + 1, visited, candidates) {
+                        tracing::debug!("Unable to read sub-IFD: {e}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the previews within a TIFF based RAW file, along with the orientation of the
+/// RAW file.
+fn tiff_candidates(reader: &mut (impl Read + Seek)) -> Result<(Vec<Candidate>, Option<u32>)> {
+    let mut header = [0u8; 8];
+    reader.rewind()?;
+    reader.read_exact(&mut header)?;
+    // The magic number following the byte order differs between manufacturers, so
+    // only the byte order is checked.
+    let order = match &header[..2] {
+        b"II" => ByteOrder::Little,
+        b"MM" => ByteOrder::Big,
+        _ => return Err(anyhow!("Not a TIFF based RAW file")),
+    };
+    let mut tiff = TiffReader { reader, order };
+    let first_ifd = tiff.u32_from([header[4], header[5], header[6], header[7]]);
+
+    let orientation = tiff
+        .read_ifd(first_ifd)
+        .ok()
+        .and_then(|(entries, _)| tiff.first_value(&entries, TAG_ORIENTATION));
+
+    let mut candidates = vec![];
+    tiff.collect(first_ifd, 0, &mut HashSet::new(), &mut candidates)?;
+    Ok((candidates, orientation))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TYPE_LONG: u16 = 4;
+
+    fn entry(tag: u16, field_type: u16, count: u32, value: u32) -> Vec<u8> {
+        [
+            &tag.to_le_bytes()[..],
+            &field_type.to_le_bytes(),
+            &count.to_le_bytes(),
+            &value.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    fn jpeg_entries(offset: u32, length: u32) -> Vec<Vec<u8>> {
+        vec![
+            entry(TAG_JPEG_OFFSET, TYPE_LONG, 1, offset),
+            entry(TAG_JPEG_LENGTH, TYPE_LONG, 1, length),
+        ]
+    }
+
+    /// A little endian TIFF with the IFDs placed at their offsets, where each IFD is
+    /// its entries along with the offset of the next IFD.
+    fn tiff(ifds: &[(u32, Vec<Vec<u8>>, u32)]) -> Vec<u8> {
+        let first = ifds.first().map_or(0, |(offset, _, _)| *offset);
+        let mut data = [&b"II*\0"[..], &first.to_le_bytes()].concat();
+        for (offset, entries, next) in ifds {
+            let ifd = [
+                &(entries.len() as u16).to_le_bytes()[..],
+                &entries.concat()[..],
+                &next.to_le_bytes(),
+            ]
+            .concat();
+            let start = *offset as usize;
+            if data.len() < start + ifd.len() {
+                data.resize(start + ifd.len(), 0);
+            }
+            data[start..start + ifd.len()].copy_from_slice(&ifd);
+        }
+        data
+    }
+
+    fn collect(data: Vec<u8>) -> Result<Vec<Candidate>> {
+        Ok(tiff_candidates(&mut Cursor::new(data))?.0)
+    }
+
+    #[test]
+    fn test_collect_chain() {
+        let data = tiff(&[
+            (8, jpeg_entries(1000, 200), 100),
+            (100, jpeg_entries(2000, 400), 0),
+        ]);
+        assert_eq!(
+            collect(data).unwrap(),
+            vec![
+                Candidate {
+                    offset: 1000,
+                    length: 200
+                },
+                Candidate {
+                    offset: 2000,
+                    length: 400
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_looping_chain() {
+        // Each IFD points to the other as the next in the chain
+        let data = tiff(&[
+            (8, jpeg_entries(1000, 200), 100),
+            (100, jpeg_entries(2000, 400), 8),
+        ]);
+        assert_eq!(collect(data).unwrap().len(), 2);
+
+        // The sub-IFD refers back to the IFD containing it
+        let mut entries = jpeg_entries(1000, 200);
+        entries.push(entry(TAG_SUB_IFDS, TYPE_LONG, 1, 8));
+        let data = tiff(&[(8, entries, 8)]);
+        assert_eq!(collect(data).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_collect_nested_sub_ifds() {
+        // Each IFD has the next as a sub-IFD, nested deeper than we follow
+        let ifds: Vec<_> = (0..MAX_IFD_DEPTH as u32 + 2)
+            .map(|i| {
+                let mut entries = jpeg_entries(1000 + i, 200);
+                entries.push(entry(TAG_SUB_IFDS, TYPE_LONG, 1, 100 * (i + 2)));
+                (100 * (i + 1), entries, 0)
+            })
+            .collect();
+        assert_eq!(collect(tiff(&ifds)).unwrap().len(), MAX_IFD_DEPTH);
+    }
+
+    #[test]
+    fn test_collect_oversized_counts() {
+        let entries = vec![
+            entry(TAG_JPEG_OFFSET, TYPE_LONG, 0x0100_0000, 1000),
+            entry(TAG_JPEG_LENGTH, TYPE_LONG, 1, 200),
+            entry(TAG_RW2_JPEG, 7, u32::MAX, 3000),
+        ];
+        let data = tiff(&[(8, entries, 0)]);
+        let candidates = collect(data.clone()).unwrap();
+        // The offset with too many values is skipped, while the preview that is too
+        // large is found but never read
+        assert_eq!(
+            candidates,
+            vec![Candidate {
+                offset: 3000,
+                length: u32::MAX.into()
+            }]
+        );
+        assert!(read_candidate(&mut Cursor::new(data), candidates[0]).is_err());
+
+        let entries = vec![entry(TAG_SUB_IFDS, TYPE_LONG, 0x0100_0000, 100)];
+        assert!(collect(tiff(&[(8, entries, 0)])).is_err());
+    }
+
+    #[test]
+    fn test_collect_truncated() {
+        assert!(collect(b"II*\0".to_vec()).is_err());
+        assert!(collect(b"XX*\0\x08\0\0\0".to_vec()).is_err());
+
+        // The IFD claims more entries than the file contains
+        let mut data = tiff(&[(8, jpeg_entries(1000, 200), 0)]);
+        data[8] = 10;
+        assert!(collect(data).is_err());
+
+        // The IFD is beyond the end of the file
+        let data = [&b"II*\0"[..], &5000u32.to_le_bytes()].concat();
+        assert!(collect(data).is_err());
+
+        // A truncated sub-IFD doesn't prevent finding the other previews
+        let mut entries = jpeg_entries(1000, 200);
+        entries.push(entry(TAG_SUB_IFDS, TYPE_LONG, 1, 5000));
+        assert_eq!(collect(tiff(&[(8, entries, 0)])).unwrap().len(), 1);
+    }
+
+    fn raf_header(offset: u32, length: u32) -> Vec<u8> {
+        let mut header = RAF_MAGIC.to_vec();
+        header.resize(84, 0);
+        header.extend(offset.to_be_bytes());
+        header.extend(length.to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn test_raf_candidates() {
+        let header = raf_header(148, 5000);
+        assert_eq!(
+            raf_candidates(&mut Cursor::new(header.clone())).unwrap(),
+            vec![Candidate {
+                offset: 148,
+                length: 5000
+            }]
+        );
+        assert!(raf_candidates(&mut Cursor::new(&header[..60])).is_err());
+
+        let mut header = header;
+        header[0] = b'X';
+        assert!(raf_candidates(&mut Cursor::new(header)).is_err());
+    }
+
+    #[test]
+    fn test_cr3_candidates() {
+        let mut data = b"\0\0\0\x18ftypcrx \0\0\0\x01crx isom".to_vec();
+        let position = data.len();
+        data.extend(b"PRVW");
+        data.extend([0u8; 12]);
+        data.extend(1234u32.to_be_bytes());
+        data.extend(JPEG_SOI);
+        assert_eq!(
+            cr3_candidates(&mut Cursor::new(data.clone())).unwrap(),
+            vec![Candidate {
+                offset: (position + 20) as u64,
+                length: 1234
+            }]
+        );
+
+        // The length of the preview is cut off
+        assert!(cr3_candidates(&mut Cursor::new(&data[..position + 18])).is_err());
+        assert!(cr3_candidates(&mut Cursor::new(&data[..position])).is_err());
+    }
+}
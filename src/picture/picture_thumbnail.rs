@@ -1,6 +1,6 @@
-use entity::Selection;
+use entity::{Flag, Rating, Selection};
 use iced::widget::{button, column, container, horizontal_space, image, pop, row, text};
-use iced::{Element, Theme};
+use iced::{Border, Color, Element, Theme};
 
 use super::PictureData;
 use crate::thumbnail::ThumbnailMessage;
@@ -13,10 +13,11 @@ pub struct PictureThumbnail {
     pub data: PictureData,
 }
 
-fn thumbnail_style(theme: &Theme, status: button::Status) -> button::Style {
+fn thumbnail_style(theme: &Theme, status: button::Status, selected: bool) -> button::Style {
     let palette = theme.extended_palette();
 
     match status {
+        _ if selected => button::Style::default().with_background(palette.primary.weak.color),
         button::Status::Active => {
             button::Style::default().with_background(palette.background.base.color)
         }
@@ -30,6 +31,57 @@ fn thumbnail_style(theme: &Theme, status: button::Status) -> button::Style {
     }
 }
 
+/// The colour of the label of a flag.
+pub fn flag_color(flag: Flag) -> Color {
+    match flag {
+        Flag::Red => Color::from_rgb8(0xd9, 0x3f, 0x3f),
+        Flag::Yellow => Color::from_rgb8(0xe8, 0xc1, 0x2e),
+        Flag::Green => Color::from_rgb8(0x4c, 0xa8, 0x4f),
+        Flag::Blue => Color::from_rgb8(0x3b, 0x78, 0xd8),
+        Flag::Purple => Color::from_rgb8(0x95, 0x5c, 0xc8),
+    }
+}
+
+/// A coloured dot showing the flag, which is an empty outline without a flag.
+pub fn flag_label<'a, M: 'a>(flag: Option<Flag>, size: f32) -> Element<'a, M> {
+    container(horizontal_space())
+        .width(size)
+        .height(size)
+        .style(move |theme: &Theme| container::Style {
+            background: flag.map(|f| flag_color(f).into()),
+            border: Border {
+                color: flag.map_or(theme.extended_palette().background.strong.color, flag_color),
+                width: 1.0,
+                radius: (size / 2.0).into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+/// The stars of the rating, which set the rating to the star pressed.
+///
+/// Pressing the star of the current rating clears the rating.
+pub fn rating_stars<'a, M: 'a>(
+    rating: Option<Rating>,
+    on_press: impl Fn(Option<Rating>) -> M,
+) -> Element<'a, M> {
+    let stars = rating.map_or(0, |r| r.stars());
+    row((1..=5).map(|n| {
+        let rating = if n == stars {
+            None
+        } else {
+            Rating::from_stars(n)
+        };
+        button(text(if n <= stars { "★" } else { "☆" }))
+            .style(button::text)
+            .padding(2)
+            .on_press(on_press(rating))
+            .into()
+    }))
+    .into()
+}
+
 impl PartialEq for PictureThumbnail {
     fn eq(&self, other: &Self) -> bool {
         self.data.eq(&other.data)
@@ -40,13 +92,17 @@ impl Eq for PictureThumbnail {}
 
 impl PartialOrd for PictureThumbnail {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.data.capture_time?.cmp(&other.data.capture_time?))
+        Some(
+            self.data
+                .capture_instant()?
+                .cmp(&other.data.capture_instant()?),
+        )
     }
 }
 
 impl Ord for PictureThumbnail {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match (self.data.capture_time, other.data.capture_time) {
+        match (self.data.capture_instant(), other.data.capture_instant()) {
             (Some(s), Some(o)) => s.cmp(&o),
             (None, Some(_)) => std::cmp::Ordering::Less,
             (Some(_), None) => std::cmp::Ordering::Greater,
@@ -88,14 +144,18 @@ impl PictureThumbnail {
             .on_show(move |_| ThumbnailMessage::ThumbnailPoppedIn(self.data.id).into())
             .into()
         };
-        let message: Option<Message> = if selected {
-            None
-        } else {
-            Some(ThumbnailMessage::SetActive(self.data.id).into())
-        };
-        button(column![image_handle, row(buttons),].align_x(iced::Alignment::Center))
-            .style(thumbnail_style)
-            .on_press_maybe(message)
+        let id = self.data.id;
+        let labels = row![
+            rating_stars(self.data.rating, move |rating| {
+                ThumbnailMessage::SetRating((id, rating)).into()
+            }),
+            flag_label(self.data.flag, 12.),
+        ]
+        .spacing(5)
+        .align_y(iced::Alignment::Center);
+        button(column![image_handle, row(buttons), labels].align_x(iced::Alignment::Center))
+            .style(move |theme, status| thumbnail_style(theme, status, selected))
+            .on_press(ThumbnailMessage::Pressed(self.data.id).into())
             .padding(10)
             .into()
     }
@@ -0,0 +1,180 @@
+//! User configurable settings for the application
+//
+// The settings are stored as a TOML file within the configuration directory of
+// the user. Where the file doesn't exist the default values are used, and any
+// values missing from the file also fall back to their defaults.
+
+use anyhow::{anyhow, Error};
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+use time::UtcOffset;
+
+use crate::capture_time::parse_offset;
+use crate::APP_ID;
+
+const SETTINGS_FILE: &str = "settings.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub import: ImportSettings,
+    pub sidecar: SidecarSettings,
+    pub backup: BackupSettings,
+}
+
+/// Settings controlling where imported pictures are placed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportSettings {
+    /// The directory all imported pictures are placed within. When this is not
+    /// set, the pictures directory of the user is used.
+    pub base_directory: Option<Utf8PathBuf>,
+    /// The template describing the path of each picture relative to the base
+    /// directory, with tokens such as `{year}` replaced by the metadata of each
+    /// picture.
+    pub template: String,
+    /// A free-text name for the current job, available as the `{job}` token.
+    pub job: String,
+    /// Whether imports copy or move pictures by default.
+    pub mode: ImportMode,
+    /// The timezone of pictures without one in their exif data, as an offset from
+    /// UTC like `+10:00`. When this is not set, the timezone is left unknown.
+    pub timezone: Option<String>,
+}
+
+impl ImportSettings {
+    pub fn timezone(&self) -> Result<Option<UtcOffset>, Error> {
+        self.timezone.as_deref().map(parse_offset).transpose()
+    }
+}
+
+/// How the files are transferred into the library when importing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Keep the original files in place
+    #[default]
+    Copy,
+    /// Remove the original files once the copies have been verified
+    Move,
+}
+
+impl Default for ImportSettings {
+    fn default() -> Self {
+        Self {
+            base_directory: None,
+            template: "{year}/{year}-{month}-{day}/{filename}".to_string(),
+            job: String::new(),
+            mode: ImportMode::default(),
+            timezone: None,
+        }
+    }
+}
+
+/// Settings controlling the XMP sidecars written alongside pictures.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SidecarSettings {
+    /// Whether changes to the selection, rating and flag are written to sidecars.
+    pub write: bool,
+    /// How differences between the database and sidecars are resolved on rescan.
+    pub conflict: ConflictPolicy,
+    /// How the sidecars are named from the file of their picture.
+    pub naming: SidecarNaming,
+}
+
+/// Which values are kept when the database and a sidecar disagree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the values of the sidecar, which are likely changes from other tools
+    #[default]
+    Sidecar,
+    /// Keep the values of the database, overwriting the sidecar
+    Database,
+    /// Keep the values of the database, filling in any missing values from the sidecar
+    Merge,
+}
+
+/// How the name of a sidecar is formed from the file of the picture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarNaming {
+    /// Add to the whole filename like `IMG_0001.JPG.xmp`, used by darktable and digiKam
+    #[default]
+    Extension,
+    /// Replace the extension like `IMG_0001.xmp`, used by Lightroom
+    Replace,
+}
+
+impl SidecarNaming {
+    pub const ALL: [SidecarNaming; 2] = [SidecarNaming::Extension, SidecarNaming::Replace];
+}
+
+impl Default for SidecarSettings {
+    fn default() -> Self {
+        Self {
+            write: true,
+            conflict: ConflictPolicy::default(),
+            naming: SidecarNaming::default(),
+        }
+    }
+}
+
+/// Settings controlling the snapshots taken of the database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    /// Whether a snapshot is taken when the application starts, where there hasn't
+    /// been one within the last day.
+    pub daily: bool,
+    /// Whether a snapshot is taken before adding or importing pictures.
+    pub before_import: bool,
+    /// The number of snapshots of each kind kept, with the oldest removed first.
+    /// Snapshots taken by the user are never removed.
+    pub keep: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            daily: true,
+            before_import: true,
+            keep: 10,
+        }
+    }
+}
+
+/// The directory containing all the configuration files of the application.
+pub fn config_directory() -> Result<Utf8PathBuf, Error> {
+    let mut path: Utf8PathBuf = dirs::config_dir()
+        .ok_or(anyhow!("Unable to find config dir"))?
+        .try_into()?;
+    path.push(APP_ID);
+    Ok(path)
+}
+
+impl Settings {
+    pub fn path() -> Result<Utf8PathBuf, Error> {
+        Ok(config_directory()?.join(SETTINGS_FILE))
+    }
+
+    /// Load the settings from the configuration file, using the defaults when the
+    /// file doesn't exist.
+    pub fn load() -> Result<Self, Error> {
+        let path = Self::path()?;
+        if !path.try_exists()? {
+            tracing::debug!("No settings found at {path}, using defaults");
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(&path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let path = Self::path()?;
+        std::fs::create_dir_all(config_directory()?)?;
+        std::fs::write(&path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+//! Reading and writing XMP sidecars alongside pictures
+//
+// The selection, rating and flag of each picture are written to a standard `.xmp`
+// sidecar so other tools can see the culling decisions. The rating is stored as
+// `xmp:Rating`, with ignored pictures given the reject rating of -1, and the flag
+// is stored as the colour of `xmp:Label`. There is no standard way to represent a
+// pick, so this is only stored within the database.
+//
+// Sidecars are often created by other tools which store their own data within the
+// file, so when updating an existing sidecar only the values we manage are changed,
+// with everything else written back untouched.
+
+use anyhow::{anyhow, Error, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use entity::{Flag, Rating, Selection};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use sea_orm::DatabaseConnection;
+
+use crate::data::{query_pictures_within, update_picture_data};
+use crate::picture::PictureData;
+use crate::settings::{ConflictPolicy, SidecarNaming, SidecarSettings};
+
+const DESCRIPTION: &[u8] = b"rdf:Description";
+const RATING: &[u8] = b"xmp:Rating";
+const LABEL: &[u8] = b"xmp:Label";
+const XMP_NAMESPACE: &str = "http://ns.adobe.com/xap/1.0/";
+const REJECT_RATING: i32 = -1;
+
+/// The document written when a picture doesn't already have a sidecar.
+const EMPTY_SIDECAR: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""/>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#;
+
+/// The culling decisions of a picture which are stored within a sidecar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SidecarValues {
+    pub rejected: bool,
+    pub rating: Option<Rating>,
+    pub flag: Option<Flag>,
+}
+
+impl SidecarValues {
+    /// The values of a picture, where the reject rating of an ignored picture takes
+    /// the place of its rating.
+    pub fn from_picture(picture: &PictureData) -> Self {
+        let rejected = picture.selection == Selection::Ignore;
+        Self {
+            rejected,
+            rating: picture.rating.filter(|_| !rejected),
+            flag: picture.flag,
+        }
+    }
+
+    /// Update the picture with the values from the sidecar.
+    ///
+    /// Sidecars can't distinguish a pick from an ordinary picture, so a picture that
+    /// isn't rejected keeps its existing selection. Likewise a rejected picture keeps
+    /// its existing rating.
+    pub fn apply(&self, picture: &mut PictureData) {
+        if self.rejected {
+            picture.selection = Selection::Ignore;
+        } else {
+            if picture.selection == Selection::Ignore {
+                picture.selection = Selection::Ordinary;
+            }
+            picture.rating = self.rating;
+        }
+        picture.flag = self.flag;
+    }
+
+    fn xmp_rating(&self) -> Option<i32> {
+        if self.rejected {
+            Some(REJECT_RATING)
+        } else {
+            self.rating.map(rating_value)
+        }
+    }
+
+    fn set_xmp_rating(&mut self, value: &str) {
+        let Ok(value) = value.trim().parse::<f32>() else {
+            tracing::warn!("Invalid rating {value} within sidecar");
+            return;
+        };
+        let value = value.round() as i32;
+        self.rejected = value <= REJECT_RATING;
+        self.rating = rating_from_value(value);
+    }
+}
+
+fn rating_value(rating: Rating) -> i32 {
+    match rating {
+        Rating::One => 1,
+        Rating::Two => 2,
+        Rating::Three => 3,
+        Rating::Four => 4,
+        Rating::Five => 5,
+    }
+}
+
+fn rating_from_value(value: i32) -> Option<Rating> {
+    match value {
+        1 => Some(Rating::One),
+        2 => Some(Rating::Two),
+        3 => Some(Rating::Three),
+        4 => Some(Rating::Four),
+        5.. => Some(Rating::Five),
+        _ => None,
+    }
+}
+
+fn flag_label(flag: Flag) -> &'static str {
+    match flag {
+        Flag::Red => "Red",
+        Flag::Green => "Green",
+        Flag::Blue => "Blue",
+        Flag::Yellow => "Yellow",
+        Flag::Purple => "Purple",
+    }
+}
+
+/// The flag matching a colour label, where labels which aren't colours don't have a flag.
+fn flag_from_label(label: &str) -> Option<Flag> {
+    match label.trim().to_ascii_lowercase().as_str() {
+        "red" => Some(Flag::Red),
+        "green" => Some(Flag::Green),
+        "blue" => Some(Flag::Blue),
+        "yellow" => Some(Flag::Yellow),
+        "purple" => Some(Flag::Purple),
+        _ => None,
+    }
+}
+
+/// Combine the values of the database and the sidecar where these differ.
+pub fn resolve(
+    policy: ConflictPolicy,
+    database: SidecarValues,
+    sidecar: SidecarValues,
+) -> SidecarValues {
+    match policy {
+        ConflictPolicy::Sidecar => sidecar,
+        ConflictPolicy::Database => database,
+        ConflictPolicy::Merge => SidecarValues {
+            rejected: database.rejected || sidecar.rejected,
+            rating: database.rating.or(sidecar.rating),
+            flag: database.flag.or(sidecar.flag),
+        },
+    }
+}
+
+/// The location of the sidecar of a picture, named from the file of the picture.
+///
+/// A single sidecar covers both the RAW and processed files of a picture, which is
+/// named from the processed file where there is one.
+pub fn sidecar_path(filepath: &Utf8Path, naming: SidecarNaming) -> Utf8PathBuf {
+    match (naming, filepath.extension()) {
+        (SidecarNaming::Extension, Some(extension)) => {
+            filepath.with_extension(format!("{extension}.xmp"))
+        }
+        _ => filepath.with_extension("xmp"),
+    }
+}
+
+/// Read the values from the sidecar of a picture, where one exists.
+pub fn read_sidecar(filepath: &Utf8Path, naming: SidecarNaming) -> Result<Option<SidecarValues>> {
+    let path = sidecar_path(filepath, naming);
+    if !path.try_exists()? {
+        return Ok(None);
+    }
+    let document = std::fs::read_to_string(&path)?;
+    Ok(Some(parse_document(&document)?))
+}
+
+/// Write the values of a picture to its sidecar, preserving the other contents of
+/// an existing sidecar.
+#[tracing::instrument(name = "Writing sidecar", level = "debug", skip_all, fields(filepath = %picture.filepath))]
+pub fn write_sidecar(picture: &PictureData, naming: SidecarNaming) -> Result<()> {
+    let path = sidecar_path(&picture.filepath, naming);
+    let document = if path.try_exists()? {
+        std::fs::read_to_string(&path)?
+    } else {
+        EMPTY_SIDECAR.to_string()
+    };
+    let updated = update_document(&document, &SidecarValues::from_picture(picture))?;
+
+    // Writing to a temporary file first ensures an existing sidecar isn't left
+    // partially written.
+    let temporary = path.with_extension("xmp.tmp");
+    std::fs::write(&temporary, updated)?;
+    std::fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+fn parse_document(document: &str) -> Result<SidecarValues> {
+    let mut reader = Reader::from_str(document);
+    let mut values = SidecarValues::default();
+    // The element we are within where the value is stored as the text
+    let mut element: Option<Vec<u8>> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == DESCRIPTION => {
+                for attribute in e.attributes() {
+                    let attribute = attribute?;
+                    match attribute.key.as_ref() {
+                        RATING => values.set_xmp_rating(&attribute.unescape_value()?),
+                        LABEL => values.flag = flag_from_label(&attribute.unescape_value()?),
+                        _ => {}
+                    }
+                }
+            }
+            Event::Start(e) => element = Some(e.name().as_ref().to_vec()),
+            Event::Text(t) => match element.as_deref() {
+                Some(RATING) => values.set_xmp_rating(&t.unescape()?),
+                Some(LABEL) => values.flag = flag_from_label(&t.unescape()?),
+                _ => {}
+            },
+            Event::End(_) => element = None,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(values)
+}
+
+/// Replace the values we manage within an XMP document.
+///
+/// The values are written as attributes of the first `rdf:Description`, with any
+/// existing values removed, whether they are attributes or elements.
+fn update_document(document: &str, values: &SidecarValues) -> Result<String> {
+    let mut reader = Reader::from_str(document);
+    let mut writer = Writer::new(Vec::new());
+    let mut updated = false;
+    // The depth within an element being removed
+    let mut skipping = 0usize;
+    loop {
+        let event = reader.read_event()?;
+        if skipping > 0 {
+            match event {
+                Event::Start(_) => skipping += 1,
+                Event::End(_) => skipping -= 1,
+                Event::Eof => return Err(anyhow!("Unclosed element within sidecar")),
+                _ => {}
+            }
+            continue;
+        }
+        match event {
+            Event::Start(e) if !updated && e.name().as_ref() == DESCRIPTION => {
+                writer.write_event(Event::Start(update_description(&e, values)?))?;
+                updated = true;
+            }
+            Event::Empty(e) if !updated && e.name().as_ref() == DESCRIPTION => {
+                writer.write_event(Event::Empty(update_description(&e, values)?))?;
+                updated = true;
+            }
+            Event::Start(e) if [RATING, LABEL].contains(&e.name().as_ref()) => skipping = 1,
+            Event::Empty(e) if [RATING, LABEL].contains(&e.name().as_ref()) => {}
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+    }
+    if !updated {
+        return Err(anyhow!("No rdf:Description within sidecar"));
+    }
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+fn update_description(
+    description: &BytesStart<'_>,
+    values: &SidecarValues,
+) -> Result<BytesStart<'static>, Error> {
+    let mut updated = BytesStart::new("rdf:Description");
+    let mut has_namespace = false;
+    for attribute in description.attributes() {
+        let attribute = attribute?;
+        match attribute.key.as_ref() {
+            RATING | LABEL => continue,
+            b"xmlns:xmp" => has_namespace = true,
+            _ => {}
+        }
+        updated.push_attribute(attribute);
+    }
+    if !has_namespace {
+        updated.push_attribute(("xmlns:xmp", XMP_NAMESPACE));
+    }
+    if let Some(rating) = values.xmp_rating() {
+        updated.push_attribute(("xmp:Rating", rating.to_string().as_str()));
+    }
+    if let Some(flag) = values.flag {
+        updated.push_attribute(("xmp:Label", flag_label(flag)));
+    }
+    Ok(updated.into_owned())
+}
+
+/// Bring the pictures within a directory in line with their sidecars.
+///
+/// Where the values within a sidecar differ from the database, these are resolved
+/// using the conflict policy, updating both the database and the sidecar with the
+/// result. This returns the number of pictures updated within the database.
+#[tracing::instrument(name = "Synchronising sidecars", skip(db, settings))]
+pub async fn sync_sidecars(
+    db: &DatabaseConnection,
+    directory: &Utf8PathBuf,
+    settings: &SidecarSettings,
+) -> Result<usize, Error> {
+    let pictures = query_pictures_within(db, directory).await?;
+    let policy = settings.conflict;
+    let write = settings.write;
+    let naming = settings.naming;
+    let changed: Vec<PictureData> = tokio::task::spawn_blocking(move || {
+        pictures
+            .into_iter()
+            .filter_map(|mut picture| {
+                let sidecar = match read_sidecar(&picture.filepath, naming) {
+                    Ok(Some(sidecar)) => sidecar,
+                    Ok(None) => return None,
+                    Err(e) => {
+                        tracing::warn!("Unable to read sidecar of {}: {e}", picture.filepath);
+                        return None;
+                    }
+                };
+                let database = SidecarValues::from_picture(&picture);
+                if sidecar == database {
+                    return None;
+                }
+                let resolved = resolve(policy, database, sidecar);
+                resolved.apply(&mut picture);
+                if write && resolved != sidecar {
+                    if let Err(e) = write_sidecar(&picture, naming) {
+                        tracing::warn!("Unable to write sidecar of {}: {e}", picture.filepath);
+                    }
+                }
+                (resolved != database).then_some(picture)
+            })
+            .collect()
+    })
+    .await?;
+
+    let count = changed.len();
+    for picture in changed.into_iter() {
+        update_picture_data(db, picture).await?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ATTRIBUTE_SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+    xmp:Rating="3"
+    xmp:Label="Green"
+    crs:Exposure2012="+0.50">
+   <dc:subject xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <rdf:Bag>
+     <rdf:li>Holiday</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    const ELEMENT_SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+   <xmp:Rating>4.0</xmp:Rating>
+   <xmp:Label>purple</xmp:Label>
+   <xmp:CreatorTool>Other Tool</xmp:CreatorTool>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    #[test]
+    fn test_parse_attributes() {
+        let values = parse_document(ATTRIBUTE_SIDECAR).unwrap();
+        assert_eq!(
+            values,
+            SidecarValues {
+                rejected: false,
+                rating: Some(Rating::Three),
+                flag: Some(Flag::Green),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_elements() {
+        let values = parse_document(ELEMENT_SIDECAR).unwrap();
+        assert_eq!(
+            values,
+            SidecarValues {
+                rejected: false,
+                rating: Some(Rating::Four),
+                flag: Some(Flag::Purple),
+            }
+        );
+    }
+
+    #[test]
+    fn test_update_keeps_other_values() {
+        let values = SidecarValues {
+            rejected: false,
+            rating: Some(Rating::Five),
+            flag: None,
+        };
+        let updated = update_document(ATTRIBUTE_SIDECAR, &values).unwrap();
+        assert_eq!(parse_document(&updated).unwrap(), values);
+        assert!(updated.contains(r#"crs:Exposure2012="+0.50""#));
+        assert!(updated.contains("<rdf:li>Holiday</rdf:li>"));
+        assert_eq!(updated.matches("xmlns:xmp=").count(), 1);
+        assert!(!updated.contains("Green"));
+    }
+
+    #[test]
+    fn test_update_replaces_elements() {
+        let values = SidecarValues {
+            rejected: false,
+            rating: Some(Rating::One),
+            flag: Some(Flag::Red),
+        };
+        let updated = update_document(ELEMENT_SIDECAR, &values).unwrap();
+        assert_eq!(parse_document(&updated).unwrap(), values);
+        assert!(!updated.contains("<xmp:Rating>"));
+        assert!(!updated.contains("<xmp:Label>"));
+        assert!(updated.contains("<xmp:CreatorTool>Other Tool</xmp:CreatorTool>"));
+    }
+
+    #[test]
+    fn test_update_rejected() {
+        let values = SidecarValues {
+            rejected: true,
+            rating: None,
+            flag: Some(Flag::Blue),
+        };
+        let updated = update_document(EMPTY_SIDECAR, &values).unwrap();
+        assert!(updated.contains(r#"xmp:Rating="-1""#));
+        assert_eq!(parse_document(&updated).unwrap(), values);
+        assert!(updated.starts_with("<?xpacket begin="));
+
+        let cleared = update_document(&updated, &SidecarValues::default()).unwrap();
+        assert!(!cleared.contains("xmp:Rating"));
+        assert!(!cleared.contains("xmp:Label"));
+        assert_eq!(parse_document(&cleared).unwrap(), SidecarValues::default());
+    }
+
+    #[test]
+    fn test_update_without_description() {
+        let document = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"/>"#;
+        assert!(update_document(document, &SidecarValues::default()).is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let database = SidecarValues {
+            rejected: false,
+            rating: Some(Rating::Two),
+            flag: None,
+        };
+        let sidecar = SidecarValues {
+            rejected: true,
+            rating: Some(Rating::Four),
+            flag: Some(Flag::Yellow),
+        };
+        assert_eq!(resolve(ConflictPolicy::Sidecar, database, sidecar), sidecar);
+        assert_eq!(
+            resolve(ConflictPolicy::Database, database, sidecar),
+            database
+        );
+        assert_eq!(
+            resolve(ConflictPolicy::Merge, database, sidecar),
+            SidecarValues {
+                rejected: true,
+                rating: Some(Rating::Two),
+                flag: Some(Flag::Yellow),
+            }
+        );
+    }
+}
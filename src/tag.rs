@@ -0,0 +1,53 @@
+//! Hierarchical tags applied to pictures
+//
+// Tags are stored with their full name, with the levels separated by a `/`, so
+// `Places/Sydney` is nested within `Places`. Filtering on a tag includes all the
+// pictures with any of the tags nested within it.
+
+use std::sync::Arc;
+
+pub const TAG_SEPARATOR: char = '/';
+
+/// Clean up the name of a tag entered by the user.
+///
+/// The whitespace around each level is removed, along with any empty levels,
+/// returning None when nothing remains.
+pub fn normalise_tag(name: &str) -> Option<String> {
+    let name = name
+        .split(TAG_SEPARATOR)
+        .map(str::trim)
+        .filter(|level| !level.is_empty())
+        .collect::<Vec<_>>()
+        .join(&TAG_SEPARATOR.to_string());
+    (!name.is_empty()).then_some(name)
+}
+
+/// The name of the parent of a tag, which is None for top level tags.
+pub fn parent_tag(name: &str) -> Option<&str> {
+    name.rsplit_once(TAG_SEPARATOR).map(|(parent, _)| parent)
+}
+
+/// Whether the tag is the filter or is nested within the filter.
+pub fn tag_matches(tag: &str, filter: &str) -> bool {
+    tag.strip_prefix(filter)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(TAG_SEPARATOR))
+}
+
+#[derive(Debug, Clone)]
+pub enum TagError {
+    TagFailed(Arc<anyhow::Error>),
+}
+
+impl From<anyhow::Error> for TagError {
+    fn from(error: anyhow::Error) -> Self {
+        TagError::TagFailed(Arc::new(error))
+    }
+}
+
+impl std::fmt::Display for TagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagError::TagFailed(e) => write!(f, "Updating tags failed: {e}"),
+        }
+    }
+}
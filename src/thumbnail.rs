@@ -1,33 +1,60 @@
-use std::{borrow::BorrowMut, cell::RefCell, collections::HashMap, num::NonZero};
+use std::{
+    borrow::BorrowMut,
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap, HashSet},
+    num::NonZero,
+};
 use tokio::task;
 
 use camino::Utf8PathBuf;
 use either::Either;
-use entity::Selection;
+use entity::{Flag, Rating, Selection};
 use iced::{
+    keyboard,
     widget::{
-        column, container, horizontal_space, image,
-        image::{viewer, Handle},
-        mouse_area, row, scrollable,
+        button, column, container, horizontal_space, image,
+        image::Handle,
+        mouse_area, pick_list, pop, row, scrollable,
         scrollable::{scroll_to, AbsoluteOffset, Id},
-        stack,
+        stack, text, text_input, toggler,
     },
-    ContentFit, Element,
+    Alignment, ContentFit, Element,
     Length::{self},
-    Task,
+    Size, Task,
 };
 use itertools::Itertools;
 use lru::LruCache;
-use sea_orm::DatabaseConnection;
-use tracing::info;
+use sea_orm::{DatabaseConnection, TransactionTrait};
+use tracing::{info, warn};
 use uuid::Uuid;
 
 use crate::{
-    data::load_thumbnail,
-    picture::{load_image, PictureThumbnail, ThumbnailData},
+    capture_time::{
+        format_capture_time, parse_offset, parse_shift, shift_capture_times, CaptureTimeError,
+        ShiftReport, TimeShift,
+    },
+    data::{add_picture_tags, load_thumbnail, query_tags, remove_picture_tags},
+    import::ImportStructure,
+    journal::{record_edits, redo_edits, undo_edits, Change, Edit, JournalError},
+    persistence::{PersistenceError, PersistenceMessage},
+    picture::{
+        flag_label, load_image, load_raw_image, rating_stars, PictureData, PictureMetadata,
+        PictureThumbnail, ThumbnailData,
+    },
+    settings::Settings,
+    tag::{normalise_tag, tag_matches, TagError},
+    widget::{rubber_band, viewer},
     DatabaseMessage, Message,
 };
 
+/// The space between the thumbnails within the grid
+const GRID_SPACING: f32 = 10.;
+/// The scale each step of zooming changes the preview by
+const ZOOM_STEP: f32 = 1.25;
+/// The furthest the preview can be zoomed in
+const ZOOM_MAX: f32 = 8.;
+
 /// Provide the opportunity to filter thumbnails
 ///
 /// Values are true when the filter is enabled and false
@@ -38,6 +65,16 @@ struct ThumbnailFilter {
     ordinary: bool,
     pick: bool,
     hidden: bool,
+    // Only show pictures with all of these tags, or tags nested within them
+    tags: BTreeSet<String>,
+    // Only show pictures taken with this camera
+    camera: Option<String>,
+    // Only show pictures taken with this lens
+    lens: Option<String>,
+    // Only show pictures with at least this rating
+    rating: MinimumRating,
+    // Only show pictures with one of these flags, or any pictures where empty
+    flags: Vec<Flag>,
 }
 
 impl Default for ThumbnailFilter {
@@ -47,6 +84,11 @@ impl Default for ThumbnailFilter {
             ordinary: true,
             pick: true,
             hidden: false,
+            tags: BTreeSet::new(),
+            camera: None,
+            lens: None,
+            rating: MinimumRating(0),
+            flags: vec![],
         }
     }
 }
@@ -67,23 +109,172 @@ impl ThumbnailFilter {
         if self.hidden {
             value = value && !thumbnail.data.hidden
         }
+        let metadata = thumbnail.data.metadata.as_ref();
         value
+            && self
+                .tags
+                .iter()
+                .all(|filter| thumbnail.data.tags.iter().any(|t| tag_matches(t, filter)))
+            && self
+                .camera
+                .as_ref()
+                .is_none_or(|c| metadata.and_then(camera_name) == Some(c))
+            && self
+                .lens
+                .as_ref()
+                .is_none_or(|l| metadata.and_then(|m| m.lens.as_ref()) == Some(l))
+            && stars(thumbnail) >= self.rating.0
+            && (self.flags.is_empty()
+                || thumbnail.data.flag.is_some_and(|f| self.flags.contains(&f)))
+    }
+}
+
+/// The number of stars of the picture, where pictures without a rating have none.
+fn stars(thumbnail: &PictureThumbnail) -> u8 {
+    thumbnail.data.rating.map_or(0, |r| r.stars())
+}
+
+/// The fewest stars of the pictures shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinimumRating(u8);
+
+impl MinimumRating {
+    const ALL: [MinimumRating; 6] = [
+        MinimumRating(0),
+        MinimumRating(1),
+        MinimumRating(2),
+        MinimumRating(3),
+        MinimumRating(4),
+        MinimumRating(5),
+    ];
+}
+
+impl std::fmt::Display for MinimumRating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            0 => write!(f, "Any"),
+            n => write!(f, "{} & up", "★".repeat(n.into())),
+        }
     }
 }
 
-#[derive(Debug, Default, PartialEq, Eq)]
+/// The name of the camera, where the model usually includes the make.
+fn camera_name(metadata: &PictureMetadata) -> Option<&String> {
+    metadata.model.as_ref().or(metadata.make.as_ref())
+}
+
+/// The choice within the camera and lens filters which shows all the pictures.
+const ANY: &str = "Any";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Order {
     #[default]
     Ascending,
     Descending,
 }
 
+/// A movement of the cursor between the pictures shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Left,
+    Right,
+    Up,
+    Down,
+    First,
+    Last,
+}
+
+/// The value the thumbnails are sorted by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    CaptureTime,
+    Filename,
+    FocalLength,
+    Aperture,
+    ExposureTime,
+    Iso,
+    Rating,
+    Flag,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 8] = [
+        SortKey::CaptureTime,
+        SortKey::Filename,
+        SortKey::FocalLength,
+        SortKey::Aperture,
+        SortKey::ExposureTime,
+        SortKey::Iso,
+        SortKey::Rating,
+        SortKey::Flag,
+    ];
+
+    /// Compare the thumbnails by the key, falling back to the capture time where
+    /// these are the same.
+    ///
+    /// Pictures without a value come first, as with pictures without a capture time.
+    fn compare(&self, a: &PictureThumbnail, b: &PictureThumbnail) -> Ordering {
+        let value = |t: &PictureThumbnail| {
+            let metadata = t.data.metadata.as_ref()?;
+            match self {
+                SortKey::FocalLength => metadata.focal_length,
+                SortKey::Aperture => metadata.aperture,
+                SortKey::ExposureTime => metadata.exposure_time,
+                SortKey::Iso => metadata.iso.map(f64::from),
+                SortKey::CaptureTime | SortKey::Filename | SortKey::Rating | SortKey::Flag => None,
+            }
+        };
+        let ordering = match self {
+            SortKey::CaptureTime => Ordering::Equal,
+            SortKey::Filename => a
+                .data
+                .filepath
+                .file_name()
+                .cmp(&b.data.filepath.file_name()),
+            SortKey::Rating => stars(a).cmp(&stars(b)),
+            // Flags are in the order of their shortcuts, after pictures without a flag
+            SortKey::Flag => {
+                let order = |t: &PictureThumbnail| {
+                    t.data
+                        .flag
+                        .and_then(|f| Flag::ALL.iter().position(|a| a == &f))
+                };
+                order(a).cmp(&order(b))
+            }
+            _ => match (value(a), value(b)) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (None, Some(_)) => Ordering::Less,
+                (Some(_), None) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        };
+        ordering.then_with(|| a.cmp(b))
+    }
+}
+
+impl std::fmt::Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SortKey::CaptureTime => "Capture Time",
+            SortKey::Filename => "Filename",
+            SortKey::FocalLength => "Focal Length",
+            SortKey::Aperture => "Aperture",
+            SortKey::ExposureTime => "Shutter Speed",
+            SortKey::Iso => "ISO",
+            SortKey::Rating => "Rating",
+            SortKey::Flag => "Flag",
+        };
+        write!(f, "{name}")
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub enum Active {
     #[default]
     None,
     Single(Uuid),
-    Multiple(Vec<Uuid>),
+    Multiple(HashSet<Uuid>),
 }
 
 #[derive(Debug, Clone)]
@@ -92,20 +283,83 @@ pub enum ThumbnailMessage {
     DisplayOrdinary(bool),
     DisplayIgnore(bool),
     DisplayHidden(bool),
+    // Hide or show the hidden pictures, whichever they aren't already
+    ToggleDisplayHidden,
     ScrollTo(Uuid),
+    // Set the selection of the picture, or all the selected pictures where the
+    // picture is one of them
     SetSelection((Uuid, Selection)),
+    // Set the selection of all the selected pictures
     SetSelectionCurrent(Selection),
+    // Hide or show all the selected pictures
+    SetHiddenSelected(bool),
+    // Set the rating of the picture, or all the selected pictures where the picture
+    // is one of them
+    SetRating((Uuid, Option<Rating>)),
+    // Set the rating of all the selected pictures
+    SetRatingCurrent(Option<Rating>),
+    // Set the flag of all the selected pictures, clearing it where they all have it
+    ToggleFlagCurrent(Flag),
     SetThumbnails(Vec<PictureThumbnail>),
     ThumbnailPoppedIn(Uuid),
     PreviewPoppedIn(Uuid),
     ImageLoaded((Uuid, Handle)),
     SetThumbnail(ThumbnailData),
-    Next,
-    Prev,
+    // Move the cursor between the pictures, following the rows of the grid
+    Move(Movement),
+    // Open the picture at the cursor
+    OpenCurrent,
+    // Close the open picture, or clear the selection where none is open
+    CloseCurrent,
+    // The size of all the pictures laid out within the grid
+    GridResized(Size),
+    GridScrolled(scrollable::Viewport),
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    // A picture was clicked, which depends on the modifiers held
+    Pressed(Uuid),
+    ModifiersChanged(keyboard::Modifiers),
     SetActive(Uuid),
     ClearActive,
     ToggleActive(Uuid),
     ActivateMany(Vec<Uuid>),
+    // Select the pictures within the rubber band, adding to the selection while
+    // the command modifier is held
+    SelectBand(Vec<Uuid>),
+    SelectAll,
+    DisplayTag((String, bool)),
+    TagInput(String),
+    // Apply the entered tag to the selected pictures
+    TagSelected,
+    // Apply the entered tag to all the pictures shown
+    TagView,
+    TagRemove((Uuid, String)),
+    TagsChanged(Result<Vec<String>, TagError>),
+    CaptionInput(String),
+    // Set the caption of the selected pictures to the entered caption, clearing
+    // their captions where nothing is entered
+    CaptionSelected,
+    SetSort(SortKey),
+    SetOrder(Order),
+    DisplayCamera(String),
+    DisplayLens(String),
+    DisplayRating(MinimumRating),
+    DisplayFlag((Flag, bool)),
+    TimeShiftInput(String),
+    TimezoneInput(String),
+    TimeWriteExif(bool),
+    TimeRefile(bool),
+    // Shift the capture times of the selected pictures
+    ShiftSelected,
+    TimesShifted(Result<ShiftReport, CaptureTimeError>),
+    // Revert the last change made to the pictures
+    Undo,
+    // Make the last change which was undone once more
+    Redo,
+    // Undo, or redo where true, once the pending changes have been written
+    StepJournal((bool, Result<(), PersistenceError>)),
+    EditsApplied(Result<Vec<Edit>, JournalError>),
 }
 
 impl From<ThumbnailMessage> for Message {
@@ -123,13 +377,38 @@ pub struct ThumbnailView {
     filter: ThumbnailFilter,
     // The sort ordering of the thumbnails
     sort: Order,
+    sort_key: SortKey,
     // The items that have been selected
     selection: Active,
+    // The picture a range selection starts from, which is the last picture clicked
+    anchor: Option<Uuid>,
+    // The keyboard modifiers held, which change what clicking a picture does
+    modifiers: keyboard::Modifiers,
     thumbnail_size: u32,
 
     scroller: Id,
+    // Whether the pictures are shown within the preview rather than the grid
+    preview: bool,
+    grid_scroller: Id,
+    // The size of all the pictures laid out within the grid
+    grid_size: Size,
+    // The part of the grid which is visible
+    grid_viewport: Option<scrollable::Viewport>,
     viewer: Option<image::Handle>,
+    // The scale of the picture within the preview
+    zoom: f32,
     preview_cache: RefCell<lru::LruCache<Uuid, image::Handle>>,
+    // All the tags within the database, which can be used as filters
+    tags: Vec<String>,
+    tag_input: String,
+    caption_input: String,
+    // The change to the capture times of the selected pictures
+    time_shift: String,
+    timezone: String,
+    time_write_exif: bool,
+    time_refile: bool,
+    // The outcome of the last shift of capture times
+    time_status: String,
     database: DatabaseConnection,
 }
 
@@ -139,11 +418,27 @@ impl ThumbnailView {
             thumbnails: Default::default(),
             filter: Default::default(),
             sort: Default::default(),
+            sort_key: Default::default(),
             selection: Default::default(),
+            anchor: None,
+            modifiers: Default::default(),
             preview_cache: RefCell::new(LruCache::new(cache_size)),
             viewer: None,
+            zoom: 1.,
             scroller: Id::unique(),
+            preview: false,
+            grid_scroller: Id::unique(),
+            grid_size: Size::ZERO,
+            grid_viewport: None,
             thumbnail_size: 240,
+            tags: vec![],
+            tag_input: String::new(),
+            caption_input: String::new(),
+            time_shift: String::new(),
+            timezone: String::new(),
+            time_write_exif: false,
+            time_refile: false,
+            time_status: String::new(),
             database: db,
         }
     }
@@ -168,24 +463,66 @@ impl ThumbnailView {
                 self.set_hidden(value);
                 Task::none()
             }
+            ThumbnailMessage::ToggleDisplayHidden => {
+                self.set_hidden(!self.hidden());
+                Task::none()
+            }
             ThumbnailMessage::ScrollTo(id) => {
                 let offset =
                     self.get_position(id).unwrap() as f32 * (self.thumbnail_size + 2 * 10) as f32;
                 scroll_to(self.scroller.clone(), AbsoluteOffset { x: offset, y: 0. })
             }
             ThumbnailMessage::SetSelection((id, s)) => {
-                self.set_selection(&id, s);
-                let to_update = self.thumbnails.get(&id).unwrap().data.clone();
-                Task::done(DatabaseMessage::UpdateImage(to_update)).map(Message::Database)
+                let ids = if self.is_selected(&id) {
+                    self.selected_ids()
+                } else {
+                    vec![id]
+                };
+                self.change_pictures(&ids, |data| Change::Selection {
+                    before: data.selection,
+                    after: s,
+                })
             }
             ThumbnailMessage::SetSelectionCurrent(s) => {
-                if let Active::Single(id) = self.selection {
-                    self.set_selection(&id, s);
-                    let to_update = self.thumbnails.get(&id).unwrap().data.clone();
-                    Task::done(DatabaseMessage::UpdateImage(to_update)).map(Message::Database)
+                self.change_pictures(&self.selected_ids(), |data| Change::Selection {
+                    before: data.selection,
+                    after: s,
+                })
+            }
+            ThumbnailMessage::SetRating((id, rating)) => {
+                let ids = if self.is_selected(&id) {
+                    self.selected_ids()
                 } else {
-                    Task::none()
-                }
+                    vec![id]
+                };
+                self.change_pictures(&ids, |data| Change::Rating {
+                    before: data.rating,
+                    after: rating,
+                })
+            }
+            ThumbnailMessage::SetRatingCurrent(rating) => {
+                self.change_pictures(&self.selected_ids(), |data| Change::Rating {
+                    before: data.rating,
+                    after: rating,
+                })
+            }
+            ThumbnailMessage::ToggleFlagCurrent(flag) => {
+                let ids = self.selected_ids();
+                let flagged = ids
+                    .iter()
+                    .filter_map(|id| self.thumbnails.get(id))
+                    .all(|t| t.data.flag == Some(flag));
+                let after = (!flagged).then_some(flag);
+                self.change_pictures(&ids, |data| Change::Flag {
+                    before: data.flag,
+                    after,
+                })
+            }
+            ThumbnailMessage::SetHiddenSelected(hidden) => {
+                self.change_pictures(&self.selected_ids(), |data| Change::Hidden {
+                    before: data.hidden,
+                    after: hidden,
+                })
             }
             ThumbnailMessage::ThumbnailPoppedIn(id) => Task::perform(
                 async move { load_thumbnail(&database, id).await.unwrap() },
@@ -196,28 +533,70 @@ impl ThumbnailView {
                 self.set_thumbnails(thumbnails);
                 // Default to selecting the first image within a directory
                 // self.preview = self.thumbnail_view.positions().next();
-                Task::none()
+                Task::perform(
+                    async move { query_tags(&database).await.map_err(TagError::from) },
+                    ThumbnailMessage::TagsChanged,
+                )
+                .map(Message::Thumbnail)
             }
             ThumbnailMessage::PreviewPoppedIn(id) => {
                 let filepath = self.get_filepath(&id).unwrap();
-                Task::perform(
-                    async move {
-                        let handle = task::spawn_blocking(move || {
-                            let image = load_image(filepath.clone(), None).unwrap();
+                let raw_filepath = self.thumbnails.get(&id).and_then(|t| t.data.raw_filepath());
+                // The file may have been moved or removed outside of the application
+                let preview = Task::future(async move {
+                    task::spawn_blocking(move || match load_image(filepath.clone(), None) {
+                        Ok(image) => {
                             info!("Image Loaded from {filepath}");
-                            Handle::from_rgba(image.width(), image.height(), image.into_vec())
+                            Some(Handle::from_rgba(
+                                image.width(),
+                                image.height(),
+                                image.into_vec(),
+                            ))
+                        }
+                        Err(e) => {
+                            warn!("Unable to load {filepath}: {e}");
+                            None
+                        }
+                    })
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|handle| (id, handle))
+                })
+                .and_then(|loaded| Task::done(ThumbnailMessage::ImageLoaded(loaded)));
+                // Developing the RAW file is slow, so the preview is shown in the meantime
+                let developed = match raw_filepath {
+                    Some(raw_filepath) => Task::future(async move {
+                        task::spawn_blocking(move || match load_raw_image(&raw_filepath) {
+                            Ok(image) => {
+                                info!("RAW Image Developed from {raw_filepath}");
+                                Some(Handle::from_rgba(
+                                    image.width(),
+                                    image.height(),
+                                    image.into_vec(),
+                                ))
+                            }
+                            Err(e) => {
+                                warn!("Unable to develop {raw_filepath}: {e}");
+                                None
+                            }
                         })
                         .await
-                        .unwrap();
-                        (id, handle)
-                    },
-                    ThumbnailMessage::ImageLoaded,
-                )
-                .map(Message::Thumbnail)
+                        .ok()
+                        .flatten()
+                        .map(|handle| (id, handle))
+                    })
+                    .and_then(|loaded| Task::done(ThumbnailMessage::ImageLoaded(loaded))),
+                    None => Task::none(),
+                };
+                preview.chain(developed).map(Message::Thumbnail)
             }
             ThumbnailMessage::ImageLoaded((id, handle)) => {
                 self.preview_cache.borrow_mut().put(id, handle.clone());
-                self.viewer = Some(handle);
+                // The image may finish loading after moving onto another picture
+                if self.get_selected() == Some(id) {
+                    self.viewer = Some(handle);
+                }
                 Task::none()
             }
             ThumbnailMessage::SetThumbnail(data) => {
@@ -232,26 +611,55 @@ impl ThumbnailView {
 
                 Task::none()
             }
-            ThumbnailMessage::Next => {
-                if let Active::Single(id) = self.selection {
-                    Task::done(ThumbnailMessage::SetActive(self.next(Some(id)).unwrap()))
-                        .chain(Task::done(ThumbnailMessage::ScrollTo(id)))
-                        .map(Message::Thumbnail)
-                } else {
-                    Task::none()
+            ThumbnailMessage::Move(movement) => self.move_cursor(movement),
+            ThumbnailMessage::OpenCurrent => match self.get_selected() {
+                Some(id) => self.update(ThumbnailMessage::SetActive(id)),
+                None => Task::none(),
+            },
+            ThumbnailMessage::CloseCurrent => {
+                if self.viewer.take().is_none() {
+                    self.selection = Active::None;
                 }
+                Task::none()
             }
-            ThumbnailMessage::Prev => {
-                if let Active::Single(id) = self.selection {
-                    Task::done(ThumbnailMessage::SetActive(self.prev(Some(id)).unwrap()))
-                        .chain(Task::done(ThumbnailMessage::ScrollTo(id)))
-                        .map(Message::Thumbnail)
-                } else {
+            ThumbnailMessage::GridResized(size) => {
+                self.grid_size = size;
+                Task::none()
+            }
+            ThumbnailMessage::GridScrolled(viewport) => {
+                self.grid_viewport = Some(viewport);
+                Task::none()
+            }
+            ThumbnailMessage::ZoomIn => {
+                self.zoom = (self.zoom * ZOOM_STEP).min(ZOOM_MAX);
+                Task::none()
+            }
+            ThumbnailMessage::ZoomOut => {
+                self.zoom = (self.zoom / ZOOM_STEP).max(1.);
+                Task::none()
+            }
+            ThumbnailMessage::ZoomReset => {
+                self.zoom = 1.;
+                Task::none()
+            }
+            ThumbnailMessage::Pressed(id) => {
+                if self.modifiers.shift() {
+                    self.select_range(id)
+                } else if self.modifiers.command() {
+                    self.update(ThumbnailMessage::ToggleActive(id))
+                } else if self.get_selected() == Some(id) {
                     Task::none()
+                } else {
+                    self.update(ThumbnailMessage::SetActive(id))
                 }
             }
+            ThumbnailMessage::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers;
+                Task::none()
+            }
             ThumbnailMessage::SetActive(id) => {
                 self.selection = Active::Single(id);
+                self.anchor = Some(id);
                 match self.preview_cache.borrow_mut().get(&id) {
                     Some(p) => Task::done(ThumbnailMessage::ImageLoaded((id, p.clone()))),
                     None => {
@@ -266,9 +674,343 @@ impl ThumbnailView {
                 self.viewer = None;
                 Task::none()
             }
-            ThumbnailMessage::ToggleActive(uuid) => todo!(),
-            ThumbnailMessage::ActivateMany(vec) => todo!(),
+            ThumbnailMessage::ToggleActive(id) => {
+                let mut ids = self.selected_ids();
+                if ids.contains(&id) {
+                    ids.retain(|i| i != &id);
+                } else {
+                    ids.push(id);
+                }
+                self.anchor = Some(id);
+                self.select_many(ids);
+                Task::none()
+            }
+            ThumbnailMessage::ActivateMany(ids) => {
+                self.select_many(ids);
+                Task::none()
+            }
+            ThumbnailMessage::SelectBand(ids) => {
+                let ids = if self.modifiers.command() {
+                    self.selected_ids()
+                        .into_iter()
+                        .chain(ids)
+                        .unique()
+                        .collect()
+                } else {
+                    ids
+                };
+                self.select_many(ids);
+                Task::none()
+            }
+            ThumbnailMessage::SelectAll => {
+                self.select_many(self.positions().collect());
+                Task::none()
+            }
+            ThumbnailMessage::DisplayTag((tag, value)) => {
+                if value {
+                    self.filter.tags.insert(tag);
+                } else {
+                    self.filter.tags.remove(&tag);
+                }
+                Task::none()
+            }
+            ThumbnailMessage::TagInput(value) => {
+                self.tag_input = value;
+                Task::none()
+            }
+            ThumbnailMessage::TagSelected => {
+                let ids = self.selected_ids();
+                self.add_tag(ids)
+            }
+            ThumbnailMessage::TagView => {
+                let ids = self.positions().collect();
+                self.add_tag(ids)
+            }
+            ThumbnailMessage::CaptionInput(value) => {
+                self.caption_input = value;
+                Task::none()
+            }
+            ThumbnailMessage::CaptionSelected => {
+                let caption = self.caption_input.trim();
+                let caption = (!caption.is_empty()).then(|| caption.to_string());
+                self.caption_input.clear();
+                self.change_pictures(&self.selected_ids(), |data| Change::Caption {
+                    before: data.caption.clone(),
+                    after: caption.clone(),
+                })
+            }
+            ThumbnailMessage::TagRemove((id, tag)) => {
+                let mut edits = vec![];
+                if let Some(thumbnail) = self.thumbnails.get_mut(&id) {
+                    if thumbnail.data.tags.contains(&tag) {
+                        let change = Change::Tag {
+                            name: tag.clone(),
+                            added: false,
+                        };
+                        edits.push(Edit::new(id, change));
+                    }
+                    thumbnail.data.tags.retain(|t| t != &tag);
+                }
+                Task::perform(
+                    async move { untag_pictures(&database, tag, vec![id], edits).await },
+                    ThumbnailMessage::TagsChanged,
+                )
+                .map(Message::Thumbnail)
+            }
+            ThumbnailMessage::TagsChanged(Ok(tags)) => {
+                self.tags = tags;
+                Task::none()
+            }
+            ThumbnailMessage::TagsChanged(Err(e)) => {
+                tracing::error!("{e}");
+                Task::none()
+            }
+            ThumbnailMessage::SetSort(key) => {
+                self.sort_key = key;
+                Task::none()
+            }
+            ThumbnailMessage::SetOrder(order) => {
+                self.sort = order;
+                Task::none()
+            }
+            ThumbnailMessage::DisplayCamera(camera) => {
+                self.filter.camera = (camera != ANY).then_some(camera);
+                Task::none()
+            }
+            ThumbnailMessage::DisplayRating(rating) => {
+                self.filter.rating = rating;
+                Task::none()
+            }
+            ThumbnailMessage::DisplayFlag((flag, value)) => {
+                self.filter.flags.retain(|f| f != &flag);
+                if value {
+                    self.filter.flags.push(flag);
+                }
+                Task::none()
+            }
+            ThumbnailMessage::DisplayLens(lens) => {
+                self.filter.lens = (lens != ANY).then_some(lens);
+                Task::none()
+            }
+            ThumbnailMessage::TimeShiftInput(value) => {
+                self.time_shift = value;
+                Task::none()
+            }
+            ThumbnailMessage::TimezoneInput(value) => {
+                self.timezone = value;
+                Task::none()
+            }
+            ThumbnailMessage::TimeWriteExif(value) => {
+                self.time_write_exif = value;
+                Task::none()
+            }
+            ThumbnailMessage::TimeRefile(value) => {
+                self.time_refile = value;
+                Task::none()
+            }
+            ThumbnailMessage::ShiftSelected => {
+                let shift = match self.time_shift() {
+                    Ok(shift) if !shift.is_empty() => shift,
+                    Ok(_) => return Task::none(),
+                    Err(e) => {
+                        tracing::warn!("{e}");
+                        return Task::none();
+                    }
+                };
+                let pictures: Vec<_> = self
+                    .selected_ids()
+                    .iter()
+                    .filter_map(|id| self.thumbnails.get(id))
+                    .map(|t| t.data.clone())
+                    .collect();
+                let refile = self.time_refile;
+                Task::perform(
+                    async move { shift_pictures(&database, pictures, shift, refile).await },
+                    ThumbnailMessage::TimesShifted,
+                )
+                .map(Message::Thumbnail)
+            }
+            ThumbnailMessage::TimesShifted(Ok(report)) => {
+                self.time_shift.clear();
+                self.time_status = report.to_string();
+                for picture in report.shifted.into_iter() {
+                    if let Some(thumbnail) = self.thumbnails.get_mut(&picture.id) {
+                        thumbnail.data = picture;
+                    }
+                }
+                Task::none()
+            }
+            ThumbnailMessage::TimesShifted(Err(e)) => {
+                tracing::error!("{e}");
+                self.time_status = e.to_string();
+                Task::none()
+            }
+            // The edits of the pending changes are only within the journal once
+            // they have been written
+            ThumbnailMessage::Undo => Task::done(PersistenceMessage::Settle(|result| {
+                ThumbnailMessage::StepJournal((false, result)).into()
+            }))
+            .map(Message::Persistence),
+            ThumbnailMessage::Redo => Task::done(PersistenceMessage::Settle(|result| {
+                ThumbnailMessage::StepJournal((true, result)).into()
+            }))
+            .map(Message::Persistence),
+            ThumbnailMessage::StepJournal((redo, Ok(()))) => Task::perform(
+                async move {
+                    if redo {
+                        redo_edits(&database).await
+                    } else {
+                        undo_edits(&database).await
+                    }
+                },
+                ThumbnailMessage::EditsApplied,
+            )
+            .map(Message::Thumbnail),
+            ThumbnailMessage::StepJournal((_, Err(e))) => {
+                tracing::error!("Unable to undo or redo before saving the changes: {e}");
+                Task::none()
+            }
+            ThumbnailMessage::EditsApplied(Ok(edits)) => {
+                if edits.is_empty() {
+                    return Task::none();
+                }
+                for edit in edits.iter() {
+                    if let Some(thumbnail) = self.thumbnails.get_mut(&edit.picture_id) {
+                        edit.change.apply(&mut thumbnail.data);
+                    }
+                }
+                // The database has already been updated, leaving the sidecars of the
+                // pictures which are loaded, along with the tags in use.
+                let updates: Vec<_> = edits
+                    .iter()
+                    .map(|e| e.picture_id)
+                    .unique()
+                    .filter_map(|id| self.thumbnails.get(&id))
+                    .map(|t| t.data.clone())
+                    .collect();
+                let tags = Task::perform(
+                    async move { query_tags(&database).await.map_err(TagError::from) },
+                    ThumbnailMessage::TagsChanged,
+                )
+                .map(Message::Thumbnail);
+                Task::done(DatabaseMessage::UpdateImages((updates, vec![])))
+                    .map(Message::Database)
+                    .chain(tags)
+            }
+            ThumbnailMessage::EditsApplied(Err(e)) => {
+                tracing::error!("{e}");
+                Task::none()
+            }
+        }
+    }
+
+    /// Change each of the pictures, which is recorded as a single action to undo and
+    /// written to the database within a single transaction.
+    fn change_pictures(
+        &mut self,
+        ids: &[Uuid],
+        change: impl Fn(&PictureData) -> Change,
+    ) -> Task<Message> {
+        let mut edits = vec![];
+        let mut changed = vec![];
+        for id in ids.iter() {
+            let Some(thumbnail) = self.thumbnails.get_mut(id) else {
+                continue;
+            };
+            let change = change(&thumbnail.data);
+            if change.is_empty() {
+                continue;
+            }
+            change.apply(&mut thumbnail.data);
+            changed.push(thumbnail.data.clone());
+            edits.push(Edit::new(*id, change));
+        }
+        if changed.is_empty() {
+            return Task::none();
+        }
+        Task::done(DatabaseMessage::UpdateImages((changed, edits))).map(Message::Database)
+    }
+
+    /// Select all the pictures shown between the anchor and the picture.
+    fn select_range(&mut self, id: Uuid) -> Task<Message> {
+        let Some(anchor) = self.anchor.or(self.get_selected()) else {
+            return self.update(ThumbnailMessage::SetActive(id));
+        };
+        let positions: Vec<Uuid> = self.positions().collect();
+        let (Some(start), Some(end)) = (
+            positions.iter().position(|i| i == &anchor),
+            positions.iter().position(|i| i == &id),
+        ) else {
+            return self.update(ThumbnailMessage::SetActive(id));
+        };
+        let range = if start <= end {
+            &positions[start..=end]
+        } else {
+            &positions[end..=start]
+        };
+        self.select_many(range.to_vec());
+        Task::none()
+    }
+
+    /// Select the pictures, without showing a preview of any of them.
+    fn select_many(&mut self, ids: Vec<Uuid>) {
+        self.viewer = None;
+        let ids: HashSet<Uuid> = ids.into_iter().collect();
+        self.selection = match ids.len() {
+            0 => Active::None,
+            1 => Active::Single(*ids.iter().next().unwrap()),
+            _ => Active::Multiple(ids),
+        };
+    }
+
+    /// Apply the entered tag to the pictures, clearing the entered tag.
+    fn add_tag(&mut self, ids: Vec<Uuid>) -> Task<Message> {
+        let Some(tag) = normalise_tag(&self.tag_input) else {
+            return Task::none();
+        };
+        if ids.is_empty() {
+            return Task::none();
+        }
+        self.tag_input.clear();
+        let mut edits = vec![];
+        for id in ids.iter() {
+            if let Some(thumbnail) = self.thumbnails.get_mut(id) {
+                if !thumbnail.data.tags.contains(&tag) {
+                    thumbnail.data.tags.push(tag.clone());
+                    thumbnail.data.tags.sort();
+                    let change = Change::Tag {
+                        name: tag.clone(),
+                        added: true,
+                    };
+                    edits.push(Edit::new(*id, change));
+                }
+            }
         }
+        let database = self.database.clone();
+        Task::perform(
+            async move { tag_pictures(&database, tag, ids, edits).await },
+            ThumbnailMessage::TagsChanged,
+        )
+        .map(Message::Thumbnail)
+    }
+
+    /// The change to the capture times entered by the user.
+    fn time_shift(&self) -> Result<TimeShift, anyhow::Error> {
+        let shift = if self.time_shift.trim().is_empty() {
+            Default::default()
+        } else {
+            parse_shift(&self.time_shift)?
+        };
+        let offset = if self.timezone.trim().is_empty() {
+            None
+        } else {
+            Some(parse_offset(&self.timezone)?)
+        };
+        Ok(TimeShift {
+            shift,
+            offset,
+            write_exif: self.time_write_exif,
+        })
     }
 
     pub fn positions(&self) -> impl Iterator<Item = Uuid> + use<'_> {
@@ -276,7 +1018,7 @@ impl ThumbnailView {
             .thumbnails
             .values()
             .filter(|t| self.filter.filter(t))
-            .sorted()
+            .sorted_by(|a, b| self.sort_key.compare(a, b))
             .map(|t| t.data.id);
         if self.sort == Order::Descending {
             Either::Left(positions.rev())
@@ -289,28 +1031,77 @@ impl ThumbnailView {
         self.positions().position(|i| i == id)
     }
 
-    pub fn next(&mut self, id: Option<Uuid>) -> Option<Uuid> {
-        if let Some(id) = id {
-            let positions: Vec<_> = self.positions().collect();
-            self.get_position(id)
-                .map(|i| (i + 1).clamp(0, positions.len() - 1))
-                .and_then(|i| positions.get(i))
-                .copied()
-        } else {
-            None
-        }
+    /// The number of pictures within each row of the grid.
+    fn columns(&self) -> usize {
+        let width = (self.thumbnail_size + 2 * 10) as f32;
+        (((self.grid_size.width + GRID_SPACING) / (width + GRID_SPACING)) as usize).max(1)
     }
 
-    pub fn prev(&mut self, id: Option<Uuid>) -> Option<Uuid> {
-        if let Some(id) = id {
-            let positions: Vec<_> = self.positions().collect();
-            self.get_position(id)
-                .map(|i| (i - 1).clamp(0, positions.len() - 1))
-                .and_then(|i| positions.get(i))
-                .copied()
-        } else {
-            None
+    /// Move the cursor, which is the single picture selected, between the pictures.
+    ///
+    /// The preview shows the picture at the cursor, while the grid only opens it
+    /// where a picture is already open.
+    fn move_cursor(&mut self, movement: Movement) -> Task<Message> {
+        let positions: Vec<Uuid> = self.positions().collect();
+        let Some(last) = positions.len().checked_sub(1) else {
+            return Task::none();
+        };
+        let current = self
+            .get_selected()
+            .or(self.anchor)
+            .and_then(|id| positions.iter().position(|p| *p == id));
+        // The preview shows the pictures within a single row
+        let columns = if self.preview { 1 } else { self.columns() };
+        let index = match (current, movement) {
+            (None, _) | (Some(_), Movement::First) => 0,
+            (Some(_), Movement::Last) => last,
+            (Some(i), Movement::Left) => i.saturating_sub(1),
+            (Some(i), Movement::Right) => (i + 1).min(last),
+            (Some(i), Movement::Up) => i.checked_sub(columns).unwrap_or(i),
+            (Some(i), Movement::Down) if i + columns <= last => i + columns,
+            (Some(i), Movement::Down) => i,
+        };
+        let id = positions[index];
+        if self.preview {
+            return Task::done(ThumbnailMessage::SetActive(id))
+                .chain(Task::done(ThumbnailMessage::ScrollTo(id)))
+                .map(Message::Thumbnail);
         }
+        let open = if self.viewer.is_some() {
+            self.update(ThumbnailMessage::SetActive(id))
+        } else {
+            self.selection = Active::Single(id);
+            self.anchor = Some(id);
+            Task::none()
+        };
+        Task::batch([open, self.scroll_grid(index, columns, positions.len())])
+    }
+
+    /// Scroll the grid just enough to show the row of the picture at the index.
+    fn scroll_grid(&self, index: usize, columns: usize, count: usize) -> Task<Message> {
+        let rows = count.div_ceil(columns);
+        let pitch = (self.grid_size.height + GRID_SPACING) / rows as f32;
+        let top = (index / columns) as f32 * pitch;
+        let y = match &self.grid_viewport {
+            Some(viewport) => {
+                let offset = viewport.absolute_offset().y;
+                let height = viewport.bounds().height;
+                if top < offset {
+                    top
+                } else if top + pitch > offset + height {
+                    top + pitch - height
+                } else {
+                    return Task::none();
+                }
+            }
+            None => top,
+        };
+        scroll_to(self.grid_scroller.clone(), AbsoluteOffset { x: 0., y })
+    }
+
+    /// Whether the pictures are shown within the preview rather than the grid.
+    pub fn set_preview(&mut self, preview: bool) {
+        self.preview = preview;
     }
 
     pub fn pick(&self) -> bool {
@@ -351,10 +1142,6 @@ impl ThumbnailView {
         self.filter.hidden = value;
     }
 
-    pub fn set_selection(&mut self, id: &Uuid, selection: Selection) {
-        self.thumbnails.get_mut(id).unwrap().data.selection = selection;
-    }
-
     pub fn set_thumbnail(&mut self, id: &Uuid, handle: image::Handle) {
         self.thumbnails.get_mut(id).unwrap().handle = Some(handle)
     }
@@ -371,6 +1158,27 @@ impl ThumbnailView {
         }
     }
 
+    /// All the pictures which are selected.
+    pub fn selected_ids(&self) -> Vec<Uuid> {
+        match &self.selection {
+            Active::None => vec![],
+            Active::Single(id) => vec![*id],
+            Active::Multiple(ids) => ids.iter().copied().collect(),
+        }
+    }
+
+    /// The pictures selected together, or all the pictures shown where there isn't
+    /// more than one picture selected, in the order they are shown.
+    ///
+    /// Clicking a single picture selects it, so a single selected picture is still
+    /// treated as the whole view.
+    pub fn get_multiple_or_view(&self) -> impl Iterator<Item = &PictureThumbnail> {
+        let multiple = matches!(self.selection, Active::Multiple(_));
+        self.positions()
+            .filter(move |id| !multiple || self.is_selected(id))
+            .filter_map(|id| self.thumbnails.get(&id))
+    }
+
     pub fn get_selected(&self) -> Option<Uuid> {
         match self.selection {
             Active::Single(selected) => Some(selected),
@@ -384,6 +1192,7 @@ impl ThumbnailView {
                 .width(Length::Fill)
                 .height(Length::Fill)
                 .content_fit(ContentFit::Contain)
+                .scale(self.zoom)
                 .into()
         } else {
             horizontal_space().height(Length::Fill).into()
@@ -391,6 +1200,12 @@ impl ThumbnailView {
 
         column![
             preview,
+            self.selection_view(),
+            self.label_view(),
+            self.metadata_view(),
+            self.tag_view(),
+            self.caption_view(),
+            self.time_view(),
             scrollable(row(self.get_view().map(|p| (PictureThumbnail::view(
                 p,
                 self.is_selected(&p.data.id),
@@ -405,18 +1220,41 @@ impl ThumbnailView {
     }
 
     pub fn get_grid_view(&self) -> Element<'_, Message> {
-        let grid = scrollable(container(
+        // The rubber band selects the pictures by their position within the view
+        let ids: Vec<Uuid> = self.positions().collect();
+        let grid = rubber_band(
             row(self.get_view().map(|p| {
                 PictureThumbnail::view(p, self.is_selected(&p.data.id), self.thumbnail_size)
             }))
-            .spacing(10)
+            .spacing(GRID_SPACING)
             .width(Length::Fill)
             .wrap(),
-        ))
-        .direction(scrollable::Direction::Vertical(
-            scrollable::Scrollbar::new().width(2.).scroller_width(10.),
-        ))
-        .width(Length::Fill);
+            move |indices| {
+                let band = indices.into_iter().filter_map(|i| ids.get(i).copied());
+                ThumbnailMessage::SelectBand(band.collect()).into()
+            },
+        );
+        // The size of the grid is needed to move the cursor between its rows
+        let grid = pop(grid)
+            .on_show(|size| ThumbnailMessage::GridResized(size).into())
+            .on_resize(|size| ThumbnailMessage::GridResized(size).into());
+        let grid = scrollable(container(grid))
+            .id(self.grid_scroller.clone())
+            .on_scroll(|viewport| ThumbnailMessage::GridScrolled(viewport).into())
+            .direction(scrollable::Direction::Vertical(
+                scrollable::Scrollbar::new().width(2.).scroller_width(10.),
+            ))
+            .width(Length::Fill);
+        let grid: Element<'_, Message> = column![
+            self.selection_view(),
+            self.label_view(),
+            self.metadata_view(),
+            self.tag_view(),
+            self.caption_view(),
+            self.time_view(),
+            grid
+        ]
+        .into();
 
         if let Some(view) = &self.viewer {
             let view_area: Element<'_, Message> = mouse_area(
@@ -438,7 +1276,346 @@ impl ThumbnailView {
         }
     }
 
+    /// The actions applied to all the selected pictures, which is only shown when
+    /// more than one picture is selected.
+    fn selection_view(&self) -> Element<'_, Message> {
+        let Active::Multiple(ids) = &self.selection else {
+            return row![].into();
+        };
+        let selections = [
+            ("Ignore", Selection::Ignore),
+            ("Ordinary", Selection::Ordinary),
+            ("Pick", Selection::Pick),
+        ]
+        .into_iter()
+        .map(|(label, selection)| {
+            button(text(label))
+                .on_press(ThumbnailMessage::SetSelectionCurrent(selection).into())
+                .into()
+        });
+        row![
+            text(format!("{} selected", ids.len())),
+            row(selections).spacing(5),
+            button(text("Hide")).on_press(ThumbnailMessage::SetHiddenSelected(true).into()),
+            button(text("Show")).on_press(ThumbnailMessage::SetHiddenSelected(false).into()),
+            button(text("Export")).on_press(Message::SelectionExport),
+            button(text("Clear"))
+                .style(button::secondary)
+                .on_press(ThumbnailMessage::ClearActive.into()),
+        ]
+        .spacing(10)
+        .padding(5)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    /// The rating and flag of the selected pictures, along with the filters on them.
+    fn label_view(&self) -> Element<'_, Message> {
+        let selected: Vec<&PictureThumbnail> = self
+            .selected_ids()
+            .iter()
+            .filter_map(|id| self.thumbnails.get(id))
+            .collect();
+        // The rating shared by all the selected pictures, if they all have the same
+        let rating = selected
+            .iter()
+            .map(|t| t.data.rating)
+            .all_equal_value()
+            .ok()
+            .flatten();
+        let rating: Element<'_, ThumbnailMessage> = if selected.is_empty() {
+            horizontal_space().width(0).into()
+        } else {
+            rating_stars(rating, ThumbnailMessage::SetRatingCurrent)
+        };
+        let flags = row(Flag::ALL.into_iter().map(|flag| {
+            button(flag_label(Some(flag), 14.))
+                .style(button::text)
+                .padding(2)
+                .on_press_maybe(
+                    (!selected.is_empty()).then_some(ThumbnailMessage::ToggleFlagCurrent(flag)),
+                )
+                .into()
+        }));
+
+        let filter_rating = pick_list(
+            MinimumRating::ALL,
+            Some(self.filter.rating),
+            ThumbnailMessage::DisplayRating,
+        );
+        let filter_flags = row(Flag::ALL.into_iter().map(|flag| {
+            let active = self.filter.flags.contains(&flag);
+            let style = if active {
+                button::primary
+            } else {
+                button::secondary
+            };
+            button(row![flag_label(Some(flag), 10.), text(flag.to_string())].spacing(5))
+                .style(style)
+                .on_press(ThumbnailMessage::DisplayFlag((flag, !active)))
+                .into()
+        }))
+        .spacing(5);
+
+        let view: Element<'_, ThumbnailMessage> = row![
+            text("Rating"),
+            rating,
+            text("Flag"),
+            flags,
+            horizontal_space(),
+            text("Filter Rating"),
+            filter_rating,
+            text("Filter Flags"),
+            filter_flags,
+        ]
+        .spacing(10)
+        .padding(5)
+        .align_y(Alignment::Center)
+        .into();
+        view.map(Message::Thumbnail)
+    }
+
+    /// The controls for adding and removing the tags of pictures, along with the
+    /// tags available as filters.
+    fn tag_view(&self) -> Element<'_, Message> {
+        let has_tag = normalise_tag(&self.tag_input).is_some();
+        let input = text_input("Add tag, e.g. Places/Sydney", &self.tag_input)
+            .on_input(ThumbnailMessage::TagInput)
+            .on_submit(ThumbnailMessage::TagSelected)
+            .width(Length::Fixed(240.));
+        let tag_selected = button(text("Tag Selected")).on_press_maybe(
+            (has_tag && !self.selected_ids().is_empty()).then_some(ThumbnailMessage::TagSelected),
+        );
+        let tag_view = button(text("Tag All Shown"))
+            .on_press_maybe(has_tag.then_some(ThumbnailMessage::TagView));
+
+        // The tags of the current picture, which are removed when pressed
+        let current_tags = row(self
+            .get_selected()
+            .and_then(|id| self.thumbnails.get(&id))
+            .into_iter()
+            .flat_map(|t| {
+                t.data.tags.iter().map(move |tag| {
+                    button(text(format!("{tag} ×")))
+                        .style(button::secondary)
+                        .on_press(ThumbnailMessage::TagRemove((t.data.id, tag.clone())))
+                        .into()
+                })
+            }))
+        .spacing(5)
+        .wrap();
+
+        let filters = row(self.tags.iter().map(|tag| {
+            let active = self.filter.tags.contains(tag);
+            let style = if active {
+                button::primary
+            } else {
+                button::secondary
+            };
+            button(text(tag.as_str()))
+                .style(style)
+                .on_press(ThumbnailMessage::DisplayTag((tag.clone(), !active)))
+                .into()
+        }))
+        .spacing(5)
+        .wrap();
+
+        let view: Element<'_, ThumbnailMessage> = column![
+            row![input, tag_selected, tag_view, current_tags]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            row![text("Filter Tags"), filters]
+                .spacing(10)
+                .align_y(Alignment::Center),
+        ]
+        .spacing(5)
+        .padding(5)
+        .into();
+        view.map(Message::Thumbnail)
+    }
+
+    /// The caption of the current picture, along with the control to change the
+    /// captions of the selected pictures.
+    fn caption_view(&self) -> Element<'_, Message> {
+        let caption = self
+            .get_selected()
+            .and_then(|id| self.thumbnails.get(&id))
+            .and_then(|t| t.data.caption.as_deref())
+            .unwrap_or_default();
+        let input = text_input("Caption", &self.caption_input)
+            .on_input(ThumbnailMessage::CaptionInput)
+            .on_submit(ThumbnailMessage::CaptionSelected)
+            .width(Length::Fixed(240.));
+        let label = if self.caption_input.trim().is_empty() {
+            "Clear Caption"
+        } else {
+            "Caption Selected"
+        };
+        let caption_selected = button(text(label)).on_press_maybe(
+            (!self.selected_ids().is_empty()).then_some(ThumbnailMessage::CaptionSelected),
+        );
+
+        let view: Element<'_, ThumbnailMessage> = row![input, caption_selected, text(caption)]
+            .spacing(10)
+            .padding(5)
+            .align_y(Alignment::Center)
+            .into();
+        view.map(Message::Thumbnail)
+    }
+
+    /// The sorting of the pictures and the filters on the camera and lens, along with
+    /// the details of the current picture.
+    fn metadata_view(&self) -> Element<'_, Message> {
+        // The choices of each filter are the values of the pictures which are loaded
+        let choices = |value: fn(&PictureMetadata) -> Option<&String>| {
+            std::iter::once(ANY.to_string())
+                .chain(
+                    self.thumbnails
+                        .values()
+                        .filter_map(|t| t.data.metadata.as_ref().and_then(value))
+                        .unique()
+                        .sorted()
+                        .cloned(),
+                )
+                .collect::<Vec<_>>()
+        };
+        let camera = pick_list(
+            choices(camera_name),
+            Some(self.filter.camera.clone().unwrap_or(ANY.to_string())),
+            ThumbnailMessage::DisplayCamera,
+        );
+        let lens = pick_list(
+            choices(|m| m.lens.as_ref()),
+            Some(self.filter.lens.clone().unwrap_or(ANY.to_string())),
+            ThumbnailMessage::DisplayLens,
+        );
+        let sort = pick_list(SortKey::ALL, Some(self.sort_key), ThumbnailMessage::SetSort);
+        let descending = toggler(self.sort == Order::Descending)
+            .label("Descending")
+            .on_toggle(|value| {
+                ThumbnailMessage::SetOrder(if value {
+                    Order::Descending
+                } else {
+                    Order::Ascending
+                })
+            });
+
+        let details = self
+            .get_selected()
+            .and_then(|id| self.thumbnails.get(&id))
+            .and_then(|t| Some((t.data.metadata.as_ref()?, &t.data)))
+            .map(|(m, data)| {
+                let mut details = vec![m.camera_summary(), m.exposure_summary()];
+                if let Some(time) = data.capture_time {
+                    details.push(format_capture_time(time, data.capture_offset));
+                }
+                if let (Some(width), Some(height)) = (m.width, m.height) {
+                    details.push(format!("{width} × {height}"));
+                }
+                if let (Some(latitude), Some(longitude)) = (m.latitude, m.longitude) {
+                    details.push(format!("{latitude:.5}, {longitude:.5}"));
+                }
+                details.retain(|d| !d.is_empty());
+                details.join("    ")
+            })
+            .unwrap_or_default();
+
+        let view: Element<'_, ThumbnailMessage> = row![
+            text("Sort"),
+            sort,
+            descending,
+            text("Camera"),
+            camera,
+            text("Lens"),
+            lens,
+            horizontal_space(),
+            text(details),
+        ]
+        .spacing(10)
+        .padding(5)
+        .align_y(Alignment::Center)
+        .into();
+        view.map(Message::Thumbnail)
+    }
+
+    /// The controls for shifting the capture times and setting the timezone of the
+    /// selected pictures.
+    fn time_view(&self) -> Element<'_, Message> {
+        let valid = self.time_shift().is_ok_and(|s| !s.is_empty());
+        let shift = text_input("Shift time, e.g. -1:30", &self.time_shift)
+            .on_input(ThumbnailMessage::TimeShiftInput)
+            .on_submit(ThumbnailMessage::ShiftSelected)
+            .width(Length::Fixed(160.));
+        let timezone = text_input("Timezone, e.g. +10:00", &self.timezone)
+            .on_input(ThumbnailMessage::TimezoneInput)
+            .on_submit(ThumbnailMessage::ShiftSelected)
+            .width(Length::Fixed(160.));
+        let apply = button(text("Shift Selected")).on_press_maybe(
+            (valid && !self.selected_ids().is_empty()).then_some(ThumbnailMessage::ShiftSelected),
+        );
+
+        let view: Element<'_, ThumbnailMessage> = row![
+            shift,
+            timezone,
+            toggler(self.time_write_exif)
+                .label("Write EXIF")
+                .on_toggle(ThumbnailMessage::TimeWriteExif),
+            toggler(self.time_refile)
+                .label("Re-file")
+                .on_toggle(ThumbnailMessage::TimeRefile),
+            apply,
+            text(&self.time_status),
+        ]
+        .spacing(10)
+        .padding(5)
+        .align_y(Alignment::Center)
+        .into();
+        view.map(Message::Thumbnail)
+    }
+
     pub fn get_filepath(&self, id: &Uuid) -> Option<Utf8PathBuf> {
         self.thumbnails.get(id).map(|t| t.data.filepath.clone())
     }
 }
+
+/// Add the tag to the pictures, recording the edits within the same transaction.
+async fn tag_pictures(
+    database: &DatabaseConnection,
+    tag: String,
+    ids: Vec<Uuid>,
+    edits: Vec<Edit>,
+) -> Result<Vec<String>, TagError> {
+    let txn = database.begin().await.map_err(anyhow::Error::from)?;
+    add_picture_tags(&txn, &tag, ids).await?;
+    record_edits(&txn, edits).await?;
+    txn.commit().await.map_err(anyhow::Error::from)?;
+    Ok(query_tags(database).await?)
+}
+
+async fn shift_pictures(
+    database: &DatabaseConnection,
+    pictures: Vec<PictureData>,
+    shift: TimeShift,
+    refile: bool,
+) -> Result<ShiftReport, CaptureTimeError> {
+    let structure = if refile {
+        Some(ImportStructure::new(&Settings::load()?.import)?)
+    } else {
+        None
+    };
+    Ok(shift_capture_times(database, pictures, shift, structure).await?)
+}
+
+/// Remove the tag from the pictures, recording the edits within the same transaction.
+async fn untag_pictures(
+    database: &DatabaseConnection,
+    tag: String,
+    ids: Vec<Uuid>,
+    edits: Vec<Edit>,
+) -> Result<Vec<String>, TagError> {
+    let txn = database.begin().await.map_err(anyhow::Error::from)?;
+    remove_picture_tags(&txn, &tag, ids).await?;
+    record_edits(&txn, edits).await?;
+    txn.commit().await.map_err(anyhow::Error::from)?;
+    Ok(query_tags(database).await?)
+}
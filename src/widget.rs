@@ -1,8 +1,18 @@
+mod rubber_band;
 mod viewer;
 
+use rubber_band::RubberBand;
 use viewer::Viewer;
 
 /// Creates a new [`Viewer`] with the given image `Handle`.
 pub fn viewer<Handle>(handle: Handle) -> Viewer<Handle> {
     Viewer::new(handle)
 }
+
+/// Creates a new [`RubberBand`] selecting the items of the content.
+pub fn rubber_band<'a, Message, Renderer>(
+    content: impl Into<iced::Element<'a, Message, iced::Theme, Renderer>>,
+    on_select: impl Fn(Vec<usize>) -> Message + 'a,
+) -> RubberBand<'a, Message, Renderer> {
+    RubberBand::new(content, on_select)
+}
@@ -0,0 +1,237 @@
+//! Select the items of a layout by dragging a rectangle over them.
+use iced::advanced::layout;
+use iced::advanced::renderer::{self, Quad};
+use iced::advanced::widget::tree::{self, Tree};
+use iced::advanced::{Clipboard, Layout, Shell, Widget};
+use iced::{mouse, Border, Color, Element, Event, Length, Point, Rectangle, Size, Theme};
+
+/// The distance the cursor moves while pressed before a drag begins, so a click on
+/// one of the items isn't mistaken for a drag.
+const DRAG_THRESHOLD: f32 = 8.0;
+
+/// A wrapper around a layout of items, like a [`Row`] or [`Column`], which selects
+/// the items within the rectangle dragged out by the cursor.
+///
+/// The items are identified by their index within the layout.
+///
+/// [`Row`]: iced::widget::Row
+/// [`Column`]: iced::widget::Column
+#[allow(missing_debug_implementations)]
+pub struct RubberBand<'a, Message, Renderer> {
+    content: Element<'a, Message, Theme, Renderer>,
+    on_select: Box<dyn Fn(Vec<usize>) -> Message + 'a>,
+}
+
+impl<'a, Message, Renderer> RubberBand<'a, Message, Renderer> {
+    /// Creates a new [`RubberBand`] around the content, producing the message with
+    /// the indices of the items selected.
+    pub fn new(
+        content: impl Into<Element<'a, Message, Theme, Renderer>>,
+        on_select: impl Fn(Vec<usize>) -> Message + 'a,
+    ) -> Self {
+        RubberBand {
+            content: content.into(),
+            on_select: Box::new(on_select),
+        }
+    }
+}
+
+/// The local state of a [`RubberBand`].
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    /// Where the cursor was pressed
+    origin: Option<Point>,
+    /// Where the cursor is, once the press has become a drag
+    current: Option<Point>,
+}
+
+impl State {
+    /// The rectangle dragged out by the cursor.
+    fn band(&self) -> Option<Rectangle> {
+        let (origin, current) = (self.origin?, self.current?);
+        let top_left = Point::new(origin.x.min(current.x), origin.y.min(current.y));
+        Some(Rectangle::new(
+            top_left,
+            Size::new((origin.x - current.x).abs(), (origin.y - current.y).abs()),
+        ))
+    }
+}
+
+impl<Message, Renderer> Widget<Message, Theme, Renderer> for RubberBand<'_, Message, Renderer>
+where
+    Renderer: renderer::Renderer,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
+    }
+
+    fn children(&self) -> Vec<Tree> {
+        vec![Tree::new(&self.content)]
+    }
+
+    fn diff(&self, tree: &mut Tree) {
+        tree.diff_children(std::slice::from_ref(&self.content));
+    }
+
+    fn size(&self) -> Size<Length> {
+        self.content.as_widget().size()
+    }
+
+    fn layout(
+        &self,
+        tree: &mut Tree,
+        renderer: &Renderer,
+        limits: &layout::Limits,
+    ) -> layout::Node {
+        self.content
+            .as_widget()
+            .layout(&mut tree.children[0], renderer, limits)
+    }
+
+    fn update(
+        &mut self,
+        tree: &mut Tree,
+        event: &Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        renderer: &Renderer,
+        clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        viewport: &Rectangle,
+    ) {
+        let state = tree.state.downcast_mut::<State>();
+        let dragging = state.current.is_some();
+
+        // The items don't see the cursor when a drag finishes, so the drag doesn't
+        // also press the item it started on.
+        let content_cursor = match event {
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) if dragging => {
+                mouse::Cursor::Unavailable
+            }
+            _ => cursor,
+        };
+        self.content.as_widget_mut().update(
+            &mut tree.children[0],
+            event,
+            layout,
+            content_cursor,
+            renderer,
+            clipboard,
+            shell,
+            viewport,
+        );
+
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                state.origin = cursor.position_over(layout.bounds());
+                state.current = None;
+            }
+            Event::Mouse(mouse::Event::CursorMoved { .. }) => {
+                if let (Some(origin), Some(position)) = (state.origin, cursor.position()) {
+                    if dragging || origin.distance(position) > DRAG_THRESHOLD {
+                        state.current = Some(position);
+                        shell.request_redraw();
+                    }
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                if let Some(band) = state.band() {
+                    let selected = layout
+                        .children()
+                        .enumerate()
+                        .filter(|(_, item)| item.bounds().intersects(&band))
+                        .map(|(index, _)| index)
+                        .collect();
+                    shell.publish((self.on_select)(selected));
+                    shell.capture_event();
+                    shell.request_redraw();
+                }
+                *state = State::default();
+            }
+            _ => {}
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        viewport: &Rectangle,
+        renderer: &Renderer,
+    ) -> mouse::Interaction {
+        let state = tree.state.downcast_ref::<State>();
+        if state.current.is_some() {
+            mouse::Interaction::Crosshair
+        } else {
+            self.content.as_widget().mouse_interaction(
+                &tree.children[0],
+                layout,
+                cursor,
+                viewport,
+                renderer,
+            )
+        }
+    }
+
+    fn draw(
+        &self,
+        tree: &Tree,
+        renderer: &mut Renderer,
+        theme: &Theme,
+        style: &renderer::Style,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        viewport: &Rectangle,
+    ) {
+        self.content.as_widget().draw(
+            &tree.children[0],
+            renderer,
+            theme,
+            style,
+            layout,
+            cursor,
+            viewport,
+        );
+
+        let state = tree.state.downcast_ref::<State>();
+        if let Some(band) = state.band() {
+            let palette = theme.extended_palette();
+            // The band is drawn within its own layer to be above the images of the items
+            renderer.with_layer(*viewport, |renderer| {
+                renderer.fill_quad(
+                    Quad {
+                        bounds: band,
+                        border: Border {
+                            color: palette.primary.strong.color,
+                            width: 1.0,
+                            radius: 0.0.into(),
+                        },
+                        ..Quad::default()
+                    },
+                    Color {
+                        a: 0.2,
+                        ..palette.primary.base.color
+                    },
+                );
+            });
+        }
+    }
+}
+
+impl<'a, Message, Renderer> From<RubberBand<'a, Message, Renderer>>
+    for Element<'a, Message, Theme, Renderer>
+where
+    Renderer: 'a + renderer::Renderer,
+    Message: 'a,
+{
+    fn from(
+        rubber_band: RubberBand<'a, Message, Renderer>,
+    ) -> Element<'a, Message, Theme, Renderer> {
+        Element::new(rubber_band)
+    }
+}
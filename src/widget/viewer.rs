@@ -3,8 +3,10 @@ use std::time::{Duration, Instant};
 
 use iced::advanced::image::FilterMethod;
 use iced::advanced::widget::tree::{self, Tree};
-use iced::advanced::{Layout, Widget, image, layout, renderer};
-use iced::{Element, Length, Pixels, Point, Rectangle, Size, Theme, Vector, mouse};
+use iced::advanced::{Clipboard, Layout, Shell, Widget, image, layout, renderer};
+use iced::{
+    ContentFit, Element, Event, Length, Pixels, Point, Rectangle, Size, Theme, Vector, mouse,
+};
 
 const DOUBLE_CLICK_TIMEOUT: Duration = Duration::from_millis(250);
 
@@ -17,6 +19,8 @@ pub struct Viewer<Handle> {
     min_scale: f32,
     max_scale: f32,
     scale_step: f32,
+    scale: f32,
+    content_fit: ContentFit,
     handle: Handle,
 }
 
@@ -30,6 +34,8 @@ impl<Handle> Viewer<Handle> {
             min_scale: 0.25,
             max_scale: 10.0,
             scale_step: 0.10,
+            scale: 1.0,
+            content_fit: ContentFit::ScaleDown,
             handle,
         }
     }
@@ -76,6 +82,74 @@ impl<Handle> Viewer<Handle> {
         self.scale_step = scale_step;
         self
     }
+
+    /// Sets the scale applied to the image of the [`Viewer`], on top of any zoom
+    /// from the mouse, which is kept between the min and max scale.
+    ///
+    /// Default is `1.0`
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Sets how the image of the [`Viewer`] is fitted within its bounds before
+    /// any zoom is applied.
+    ///
+    /// Default is [`ContentFit::ScaleDown`]
+    pub fn content_fit(mut self, content_fit: ContentFit) -> Self {
+        self.content_fit = content_fit;
+        self
+    }
+
+    /// The scale of the image, combining the zoom from the mouse and from the [`Viewer`].
+    fn current_scale(&self, state: &State) -> f32 {
+        (state.scale * self.scale).clamp(self.min_scale, self.max_scale)
+    }
+
+    /// Keeps the point of the image under the cursor in place after the scale changes.
+    fn zoom_around<Renderer>(
+        &self,
+        state: &mut State,
+        renderer: &Renderer,
+        bounds: Rectangle,
+        cursor_position: Point,
+        previous_scale: f32,
+    ) where
+        Renderer: image::Renderer<Handle = Handle>,
+    {
+        state.scale = state
+            .scale
+            .clamp(self.min_scale / self.scale, self.max_scale / self.scale);
+        let scale = self.current_scale(state);
+        let image_size = image_size(
+            renderer,
+            &self.handle,
+            self.content_fit,
+            scale,
+            bounds.size(),
+        );
+
+        let factor = scale / previous_scale - 1.0;
+        let cursor_to_center = cursor_position - bounds.center();
+        let adjustment = cursor_to_center * factor + state.current_offset * factor;
+
+        state.current_offset = state.offset(
+            bounds,
+            image_size,
+            Vector::new(
+                if image_size.width > bounds.width {
+                    state.current_offset.x + adjustment.x
+                } else {
+                    0.0
+                },
+                if image_size.height > bounds.height {
+                    state.current_offset.y + adjustment.y
+                } else {
+                    0.0
+                },
+            ),
+        );
+    }
 }
 
 impl<Message, Renderer, Handle> Widget<Message, Theme, Renderer> for Viewer<Handle>
@@ -129,169 +203,111 @@ where
         layout::Node::new(size)
     }
 
-    // fn on_event(
-    //     &mut self,
-    //     tree: &mut Tree,
-    //     event: Event,
-    //     layout: Layout<'_>,
-    //     cursor: mouse::Cursor,
-    //     renderer: &Renderer,
-    //     _clipboard: &mut dyn Clipboard,
-    //     _shell: &mut Shell<'_, Message>,
-    //     _viewport: &Rectangle,
-    // ) -> event::Status {
-    //     let bounds = layout.bounds();
-
-    //     // Ensure the cursor is within the bounds of the widget
-    //     if cursor.position_over(bounds).is_none() {
-    //         return event::Status::Ignored;
-    //     }
-
-    //     match event {
-    //         Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
-    //             let Some(cursor_position) = cursor.position() else {
-    //                 return event::Status::Ignored;
-    //             };
-
-    //             match delta {
-    //                 mouse::ScrollDelta::Lines { y, .. } | mouse::ScrollDelta::Pixels { y, .. } => {
-    //                     let state = tree.state.downcast_mut::<State>();
-    //                     let previous_scale = state.scale;
-
-    //                     if y < 0.0 && previous_scale > self.min_scale
-    //                         || y > 0.0 && previous_scale < self.max_scale
-    //                     {
-    //                         state.scale = (if y > 0.0 {
-    //                             state.scale * (1.0 + self.scale_step)
-    //                         } else {
-    //                             state.scale / (1.0 + self.scale_step)
-    //                         })
-    //                         .clamp(self.min_scale, self.max_scale);
-
-    //                         let image_size =
-    //                             image_size(renderer, &self.handle, state, bounds.size());
-
-    //                         let factor = state.scale / previous_scale - 1.0;
-
-    //                         let cursor_to_center = cursor_position - bounds.center();
-
-    //                         let adjustment =
-    //                             cursor_to_center * factor + state.current_offset * factor;
-
-    //                         state.current_offset = Vector::new(
-    //                             if image_size.width > bounds.width {
-    //                                 state.current_offset.x + adjustment.x
-    //                             } else {
-    //                                 0.0
-    //                             },
-    //                             if image_size.height > bounds.height {
-    //                                 state.current_offset.y + adjustment.y
-    //                             } else {
-    //                                 0.0
-    //                             },
-    //                         );
-    //                     }
-    //                 }
-    //             }
-
-    //             event::Status::Captured
-    //         }
-    //         Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
-    //             let Some(cursor_position) = cursor.position() else {
-    //                 return event::Status::Ignored;
-    //             };
-
-    //             let state = tree.state.downcast_mut::<State>();
-
-    //             if let Some(last_click) = state.last_click_time {
-    //                 // This is the identification of a double click
-    //                 if (Instant::now() - last_click) < DOUBLE_CLICK_TIMEOUT {
-    //                     // Where we are at a scale that is not 1, then revert to this scale factor.
-    //                     //
-    //                     let previous_scale = state.scale;
-    //                     if state.scale != 1. {
-    //                         state.scale = 1.;
-    //                         state.current_offset = Vector::new(0., 0.);
-    //                     } else {
-    //                         let Size {
-    //                             width: bound_width,
-    //                             height: bound_height,
-    //                         } = bounds.size();
-
-    //                         let Size {
-    //                             width: image_width,
-    //                             height: image_height,
-    //                         } = renderer.measure_image(&self.handle);
-
-    //                         // TODO: Check whether this should be max or min
-    //                         state.scale = (image_width as f32 / bound_width)
-    //                             .max(image_height as f32 / bound_height);
-
-    //                         let cursor_to_center = cursor_position - bounds.center();
-    //                         let factor = state.scale / previous_scale - 1.0;
-
-    //                         let adjustment =
-    //                             cursor_to_center * factor + state.current_offset * factor;
-    //                         state.current_offset = Vector::new(
-    //                             state.current_offset.x + adjustment.x,
-    //                             state.current_offset.y + adjustment.y,
-    //                         );
-    //                     }
-    //                     return event::Status::Captured;
-    //                 }
-    //             }
-
-    //             state.cursor_grabbed_at = Some(cursor_position);
-    //             state.last_click_time = Some(Instant::now());
-    //             state.starting_offset = state.current_offset;
-
-    //             event::Status::Captured
-    //         }
-    //         Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
-    //             let state = tree.state.downcast_mut::<State>();
-
-    //             if state.cursor_grabbed_at.is_some() {
-    //                 state.cursor_grabbed_at = None;
-
-    //                 event::Status::Captured
-    //             } else {
-    //                 event::Status::Ignored
-    //             }
-    //         }
-    //         Event::Mouse(mouse::Event::CursorMoved { position }) => {
-    //             let state = tree.state.downcast_mut::<State>();
-
-    //             if let Some(origin) = state.cursor_grabbed_at {
-    //                 let image_size = image_size(renderer, &self.handle, state, bounds.size());
-
-    //                 let hidden_width = (image_size.width - bounds.width / 2.0).max(0.0).round();
-
-    //                 let hidden_height = (image_size.height - bounds.height / 2.0).max(0.0).round();
-
-    //                 let delta = position - origin;
-
-    //                 let x = if bounds.width < image_size.width {
-    //                     (state.starting_offset.x - delta.x).clamp(-hidden_width, hidden_width)
-    //                 } else {
-    //                     0.0
-    //                 };
-
-    //                 let y = if bounds.height < image_size.height {
-    //                     (state.starting_offset.y - delta.y).clamp(-hidden_height, hidden_height)
-    //                 } else {
-    //                     0.0
-    //                 };
-
-    //                 state.current_offset = Vector::new(x, y);
-
-    //                 event::Status::Captured
-    //             } else {
-    //                 event::Status::Ignored
-    //             }
-    //         }
-    //         _ => event::Status::Ignored,
-    //     }
-    // }
+    fn update(
+        &mut self,
+        tree: &mut Tree,
+        event: &Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let state = tree.state.downcast_mut::<State>();
+
+        match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let Some(cursor_position) = cursor.position_over(bounds) else {
+                    return;
+                };
+                let (mouse::ScrollDelta::Lines { y, .. } | mouse::ScrollDelta::Pixels { y, .. }) =
+                    *delta;
+
+                let previous_scale = self.current_scale(state);
+                if y < 0.0 && previous_scale > self.min_scale
+                    || y > 0.0 && previous_scale < self.max_scale
+                {
+                    state.scale = if y > 0.0 {
+                        state.scale * (1.0 + self.scale_step)
+                    } else {
+                        state.scale / (1.0 + self.scale_step)
+                    };
+                    self.zoom_around(state, renderer, bounds, cursor_position, previous_scale);
+                    shell.request_redraw();
+                }
+                shell.capture_event();
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let Some(cursor_position) = cursor.position_over(bounds) else {
+                    return;
+                };
+
+                // A double click switches between fitting the image and its full size
+                if state
+                    .last_click_time
+                    .is_some_and(|last_click| last_click.elapsed() < DOUBLE_CLICK_TIMEOUT)
+                {
+                    let previous_scale = self.current_scale(state);
+                    if state.scale != 1.0 {
+                        state.scale = 1.0;
+                        state.current_offset = Vector::ZERO;
+                    } else {
+                        let fitted = image_size(
+                            renderer,
+                            &self.handle,
+                            self.content_fit,
+                            1.0,
+                            bounds.size(),
+                        );
+                        let Size { width, height } = renderer.measure_image(&self.handle);
+                        state.scale = (width as f32 / fitted.width)
+                            .max(height as f32 / fitted.height)
+                            / self.scale;
+                        self.zoom_around(state, renderer, bounds, cursor_position, previous_scale);
+                    }
+                    state.last_click_time = None;
+                } else {
+                    state.cursor_grabbed_at = Some(cursor_position);
+                    state.last_click_time = Some(Instant::now());
+                    state.starting_offset = state.current_offset;
+                }
+                shell.capture_event();
+                shell.request_redraw();
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                if state.cursor_grabbed_at.take().is_some() {
+                    shell.capture_event();
+                }
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                if let Some(origin) = state.cursor_grabbed_at {
+                    let scale = self.current_scale(state);
+                    let image_size = image_size(
+                        renderer,
+                        &self.handle,
+                        self.content_fit,
+                        scale,
+                        bounds.size(),
+                    );
+                    let delta = *position - origin;
+
+                    state.current_offset = state.offset(
+                        bounds,
+                        image_size,
+                        Vector::new(
+                            state.starting_offset.x - delta.x,
+                            state.starting_offset.y - delta.y,
+                        ),
+                    );
+                    shell.capture_event();
+                    shell.request_redraw();
+                }
+            }
+            _ => {}
+        }
+    }
 
     fn mouse_interaction(
         &self,
@@ -327,7 +343,13 @@ where
         let state = tree.state.downcast_ref::<State>();
         let bounds = layout.bounds();
 
-        let image_size = image_size(renderer, &self.handle, state, bounds.size());
+        let image_size = image_size(
+            renderer,
+            &self.handle,
+            self.content_fit,
+            self.current_scale(state),
+            bounds.size(),
+        );
 
         let translation = {
             let image_top_left = Vector::new(
@@ -335,7 +357,7 @@ where
                 bounds.height / 2.0 - image_size.height / 2.0,
             );
 
-            image_top_left - state.offset(bounds, image_size)
+            image_top_left - state.offset(bounds, image_size, state.current_offset)
         };
 
         renderer.with_layer(bounds, |renderer| {
@@ -395,16 +417,16 @@ impl State {
         State::default()
     }
 
-    /// Returns the current offset of the [`State`], given the bounds
-    /// of the [`Viewer`] and its image.
-    fn offset(&self, bounds: Rectangle, image_size: Size) -> Vector {
+    /// Returns the offset kept within the hidden parts of the image, given the
+    /// bounds of the [`Viewer`] and its image.
+    fn offset(&self, bounds: Rectangle, image_size: Size, offset: Vector) -> Vector {
         let hidden_width = (image_size.width - bounds.width / 2.0).max(0.0).round();
 
         let hidden_height = (image_size.height - bounds.height / 2.0).max(0.0).round();
 
         Vector::new(
-            self.current_offset.x.clamp(-hidden_width, hidden_width),
-            self.current_offset.y.clamp(-hidden_height, hidden_height),
+            offset.x.clamp(-hidden_width, hidden_width),
+            offset.y.clamp(-hidden_height, hidden_height),
         )
     }
 
@@ -426,34 +448,20 @@ where
 }
 
 /// Returns the bounds of the underlying image, given the bounds of
-/// the [`Viewer`]. Scaling will be applied and original aspect ratio
-/// will be respected.
+/// the [`Viewer`]. The image is fitted to the bounds before scaling is
+/// applied, and the original aspect ratio will be respected.
 pub fn image_size<Renderer>(
     renderer: &Renderer,
     handle: &<Renderer as image::Renderer>::Handle,
-    state: &State,
+    content_fit: ContentFit,
+    scale: f32,
     bounds: Size,
 ) -> Size
 where
     Renderer: image::Renderer,
 {
     let Size { width, height } = renderer.measure_image(handle);
+    let fitted = content_fit.fit(Size::new(width as f32, height as f32), bounds);
 
-    let (width, height) = {
-        let dimensions = (width as f32, height as f32);
-
-        let width_ratio = bounds.width / dimensions.0;
-        let height_ratio = bounds.height / dimensions.1;
-
-        let ratio = width_ratio.min(height_ratio);
-        let scale = state.scale;
-
-        if ratio < 1.0 {
-            (dimensions.0 * ratio * scale, dimensions.1 * ratio * scale)
-        } else {
-            (dimensions.0 * scale, dimensions.1 * scale)
-        }
-    };
-
-    Size::new(width, height)
+    Size::new(fitted.width * scale, fitted.height * scale)
 }